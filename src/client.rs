@@ -1,27 +1,46 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::{CStr, OsStr};
 use std::fs::File;
 use std::io::BufReader;
+use std::ptr::{null, null_mut};
 use std::slice;
+use std::sync::atomic::{AtomicPtr, Ordering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use libc::{c_char, size_t};
-use rustls::client::{ResolvesClientCert, ServerCertVerified, ServerCertVerifier};
+use libc::{c_char, c_void, size_t};
+use ring::digest;
+use ring::signature::{self, VerificationAlgorithm};
+use rustls::client::{
+    HandshakeSignatureValid, ResolvesClientCert, ServerCertVerified, ServerCertVerifier,
+};
+use rustls::internal::msgs::handshake::DigitallySignedStruct;
 use rustls::{
-    sign::CertifiedKey, Certificate, ClientConfig, ClientConnection, ProtocolVersion,
-    RootCertStore, SupportedCipherSuite, WantsVerifier, ALL_CIPHER_SUITES,
+    cipher_suite, sign::CertifiedKey, Certificate, ClientConfig, ClientConnection, ProtocolVersion,
+    RootCertStore, SupportedCipherSuite, SupportedKxGroup, SupportedProtocolVersion, WantsVerifier,
+    ALL_CIPHER_SUITES, ALL_KX_GROUPS, DEFAULT_CIPHER_SUITES, DEFAULT_VERSIONS,
 };
 
-use crate::cipher::{rustls_certified_key, rustls_root_cert_store, rustls_supported_ciphersuite};
+use crate::cipher::{
+    find_kx_group, load_roots_from_dir, rustls_certified_key, rustls_root_cert_store,
+    rustls_supported_ciphersuite, rustls_supported_kx_group, RootStore,
+};
 use crate::connection::{rustls_connection, Connection};
+use crate::enums::{rustls_browser_profile, rustls_session_eviction_policy};
 use crate::error::rustls_result::{InvalidParameter, NullParameter};
 use crate::error::{self, rustls_result};
 use crate::rslice::NulByte;
-use crate::rslice::{rustls_slice_bytes, rustls_slice_slice_bytes, rustls_str};
+use crate::rslice::{rustls_slice_bytes, rustls_slice_slice_bytes, rustls_slice_u16, rustls_str};
+use crate::session::{
+    client_session_key, rustls_session_cache_entry, rustls_session_store_get_callback,
+    rustls_session_store_put_callback, ClientSessionCache, NewTicketNotifier, SessionStoreBroker,
+    SessionStoreGetCallback, SessionStorePutCallback,
+};
 use crate::{
     ffi_panic_boundary, try_arc_from_ptr, try_box_from_ptr, try_mut_from_ptr, try_ref_from_ptr,
-    try_slice, userdata_get, ArcCastPtr, BoxCastPtr, CastConstPtr, CastPtr,
+    try_slice, userdata_get, userdata_push, x509, ArcCastPtr, BoxCastPtr, CastConstPtr, CastPtr,
 };
 
 /// A client config being constructed. A builder can be modified by,
@@ -40,10 +59,21 @@ pub struct rustls_client_config_builder {
 
 pub(crate) struct ClientConfigBuilder {
     base: rustls::ConfigBuilder<ClientConfig, WantsVerifier>,
-    verifier: Arc<dyn ServerCertVerifier>,
+    cipher_suites: Vec<SupportedCipherSuite>,
+    versions: Vec<&'static SupportedProtocolVersion>,
+    kx_groups: Vec<&'static SupportedKxGroup>,
+    verifier: Option<Arc<dyn ServerCertVerifier>>,
+    per_host_verifiers: HashMap<String, Arc<dyn ServerCertVerifier>>,
+    verifier_userdata: Arc<AtomicPtr<c_void>>,
+    pinned_sha256: Option<[u8; 32]>,
     alpn_protocols: Vec<Vec<u8>>,
     enable_sni: bool,
+    enable_early_data: bool,
+    max_fragment_size: Option<usize>,
     cert_resolver: Option<Arc<dyn rustls::client::ResolvesClientCert>>,
+    session_storage: Option<Arc<dyn rustls::client::StoresClientSessions>>,
+    preloaded_sessions: Vec<(Vec<u8>, Vec<u8>)>,
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
 }
 
 impl CastPtr for rustls_client_config_builder {
@@ -84,6 +114,404 @@ impl ServerCertVerifier for NoneVerifier {
     }
 }
 
+// A verifier that accepts only one specific end-entity certificate.
+struct SelfSignedVerifier {
+    cert: Certificate,
+}
+
+impl ServerCertVerifier for SelfSignedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if *end_entity == self.cert {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificateData(
+                "certificate does not match the trusted certificate".to_string(),
+            ))
+        }
+    }
+}
+
+// Hands each server certificate to the verifier registered for the
+// connection's server name, or to `default` for any other name.
+struct PerHostVerifier {
+    hosts: HashMap<String, Arc<dyn ServerCertVerifier>>,
+    default: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for PerHostVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verifier = match server_name {
+            rustls::ServerName::DnsName(n) => self.hosts.get(&n.as_ref().to_ascii_lowercase()),
+            _ => None,
+        };
+        verifier.unwrap_or(&self.default).verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn request_scts(&self) -> bool {
+        self.default.request_scts()
+    }
+}
+
+/// A CRL whose signature has been checked against one of the roots.
+struct TrustedCrl {
+    // The serial numbers of the certificates it revokes.
+    revoked: HashSet<Vec<u8>>,
+    // The nextUpdate time, in seconds since the Unix epoch.
+    next_update: Option<i64>,
+}
+
+impl TrustedCrl {
+    /// Parse a DER-encoded CRL, and check that it was signed by a root in
+    /// `roots` whose subject is the CRL's issuer. Returns the CRL's issuer
+    /// Name contents along with it.
+    fn new(der: &[u8], roots: &RootStore) -> Result<(Vec<u8>, TrustedCrl), rustls_result> {
+        let crl = match x509::Crl::parse(der) {
+            Some(crl) => crl,
+            None => return Err(rustls_result::CertificateParseError),
+        };
+        let algorithms = match CRL_SIGNATURE_ALGORITHMS
+            .iter()
+            .find(|(oid, _)| *oid == crl.signature_algorithm)
+        {
+            Some((_, algorithms)) => algorithms,
+            None => return Err(rustls_result::CertInvalidSignatureType),
+        };
+        let signed_by_root = roots
+            .spkis(crl.issuer)
+            .filter_map(x509::subject_public_key)
+            .any(|key| {
+                algorithms.iter().any(|&algorithm| {
+                    signature::UnparsedPublicKey::new(algorithm, key)
+                        .verify(crl.tbs, crl.signature)
+                        .is_ok()
+                })
+            });
+        if !signed_by_root {
+            return Err(rustls_result::CertInvalidSignature);
+        }
+        let revoked = crl.revoked.iter().map(|serial| serial.to_vec()).collect();
+        Ok((
+            crl.issuer.to_vec(),
+            TrustedCrl {
+                revoked,
+                next_update: crl.next_update,
+            },
+        ))
+    }
+}
+
+/// The ring algorithms that can check a CRL signature, by the OID contents of
+/// the signature algorithm. ECDSA OIDs don't name the curve, so they map to
+/// one algorithm per supported curve.
+static CRL_SIGNATURE_ALGORITHMS: &[(&[u8], &[&dyn VerificationAlgorithm])] = &[
+    // sha256WithRSAEncryption, 1.2.840.113549.1.1.11
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b],
+        &[&signature::RSA_PKCS1_2048_8192_SHA256],
+    ),
+    // sha384WithRSAEncryption, 1.2.840.113549.1.1.12
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c],
+        &[&signature::RSA_PKCS1_2048_8192_SHA384],
+    ),
+    // sha512WithRSAEncryption, 1.2.840.113549.1.1.13
+    (
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d],
+        &[&signature::RSA_PKCS1_2048_8192_SHA512],
+    ),
+    // ecdsa-with-SHA256, 1.2.840.10045.4.3.2
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02],
+        &[
+            &signature::ECDSA_P256_SHA256_ASN1,
+            &signature::ECDSA_P384_SHA256_ASN1,
+        ],
+    ),
+    // ecdsa-with-SHA384, 1.2.840.10045.4.3.3
+    (
+        &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03],
+        &[
+            &signature::ECDSA_P256_SHA384_ASN1,
+            &signature::ECDSA_P384_SHA384_ASN1,
+        ],
+    ),
+    // id-Ed25519, 1.3.101.112
+    (&[0x2b, 0x65, 0x70], &[&signature::ED25519]),
+];
+
+/// Wraps a WebPKI verifier, additionally rejecting certificates that a CRL
+/// lists as revoked. Installed by
+/// rustls_client_config_builder_use_roots_with_crls.
+struct CrlVerifier {
+    inner: rustls::client::WebPkiVerifier,
+    // The CRLs, keyed by the contents of their issuer Name.
+    crls: HashMap<Vec<u8>, Vec<TrustedCrl>>,
+}
+
+impl CrlVerifier {
+    /// Check each certificate of a chain against the CRLs from its issuer,
+    /// failing if one is revoked, or if a CRL it should be checked against
+    /// is past its nextUpdate time and so may be missing revocations.
+    fn check_revocation<'a>(
+        &self,
+        chain: impl Iterator<Item = &'a Certificate>,
+        now: SystemTime,
+    ) -> Result<(), rustls::Error> {
+        let now = match now.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(_) => return Err(rustls::Error::FailedToGetCurrentTime),
+        };
+        for cert in chain {
+            let cert = match x509::Certificate::parse(&cert.0) {
+                Some(cert) => cert,
+                None => return Err(rustls::Error::InvalidCertificateEncoding),
+            };
+            for crl in self.crls.get(cert.issuer).into_iter().flatten() {
+                if matches!(crl.next_update, Some(next_update) if next_update < now) {
+                    return Err(rustls::Error::InvalidCertificateData(
+                        "certificate revocation list is out of date".to_string(),
+                    ));
+                }
+                if crl.revoked.contains(cert.serial) {
+                    return Err(rustls::Error::InvalidCertificateData(
+                        "certificate revoked".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ServerCertVerifier for CrlVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        self.check_revocation(std::iter::once(end_entity).chain(intermediates), now)?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+/// Wraps a verifier, additionally requiring the SHA-256 hash of the
+/// end-entity certificate to equal `sha256`. Installed by
+/// rustls_client_config_builder_build when a pin was set with
+/// rustls_client_config_builder_set_pinned_cert_sha256.
+struct PinnedVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+    sha256: [u8; 32],
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        if digest::digest(&digest::SHA256, &end_entity.0).as_ref() != self.sha256 {
+            return Err(rustls::Error::InvalidCertificateData(
+                "certificate doesn't match pinned SHA-256 hash".to_string(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+/// A callback for rustls_client_config_builder_set_key_log_callback. It is
+/// called with a TLS secret in the NSS key log format: `label` says what
+/// kind of secret it is (e.g. "CLIENT_HANDSHAKE_TRAFFIC_SECRET"),
+/// `client_random` identifies the session and `secret` holds the secret
+/// itself. All three are only valid for the duration of the callback.
+#[allow(non_camel_case_types)]
+pub type rustls_key_log_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        label: rustls_str,
+        client_random: rustls_slice_bytes,
+        secret: rustls_slice_bytes,
+    ),
+>;
+
+// This is the same as a rustls_key_log_callback after unwrapping
+// the Option (which is equivalent to checking for null).
+type KeyLogCallback = unsafe extern "C" fn(
+    userdata: *mut c_void,
+    label: rustls_str,
+    client_random: rustls_slice_bytes,
+    secret: rustls_slice_bytes,
+);
+
+// An implementation of rustls::KeyLog based on a C callback.
+struct KeyLogger {
+    callback: KeyLogCallback,
+}
+
+/// Safety: KeyLogger is Send because we don't allocate or deallocate any of
+/// its fields.
+unsafe impl Send for KeyLogger {}
+/// Safety: KeyLogger is Sync if the C code that passes us a callback obeys
+/// the concurrency safety requirements documented in
+/// rustls_client_config_builder_set_key_log_callback.
+unsafe impl Sync for KeyLogger {}
+
+impl rustls::KeyLog for KeyLogger {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        let label: rustls_str = match label.try_into() {
+            Ok(l) => l,
+            Err(NulByte {}) => return,
+        };
+        let userdata = userdata_get().unwrap_or(std::ptr::null_mut());
+        let cb = self.callback;
+        unsafe { cb(userdata, label, client_random.into(), secret.into()) };
+    }
+}
+
+/// A callback for rustls_client_config_builder_load_roots_from_file_with_callback.
+/// It is called once for each certificate in the file that can't be used as
+/// a trust anchor, with the certificate's DER bytes and a short description
+/// of why it was rejected. Both are only valid for the duration of the
+/// callback. Return true to skip the certificate and continue loading, or
+/// false to stop.
+#[allow(non_camel_case_types)]
+pub type rustls_bad_root_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        cert: rustls_slice_bytes,
+        reason: rustls_str,
+    ) -> bool,
+>;
+
+// Read the PEM-formatted certificates in the named file into a new root store.
+fn load_roots_from_pem_file(filename: &str) -> Result<RootCertStore, rustls_result> {
+    roots_from_certs(&read_pem_certs_file(filename)?)
+}
+
+// Read the DER bytes of the PEM-formatted certificates in the named file.
+fn read_pem_certs_file(filename: &str) -> Result<Vec<Vec<u8>>, rustls_result> {
+    let filename: &OsStr = OsStr::new(filename);
+    let mut cafile = match File::open(filename) {
+        Ok(f) => f,
+        Err(_) => return Err(rustls_result::Io),
+    };
+
+    let mut bufreader = BufReader::new(&mut cafile);
+    match rustls_pemfile::certs(&mut bufreader) {
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(rustls_result::Io),
+    }
+}
+
+// Add DER-encoded certificates to a new root store, failing if any of them
+// can't be used.
+fn roots_from_certs(certs: &[Vec<u8>]) -> Result<RootCertStore, rustls_result> {
+    let mut roots = RootCertStore::empty();
+    let (_, failed) = roots.add_parsable_certificates(certs);
+    if failed > 0 {
+        return Err(rustls_result::CertificateParseError);
+    }
+    Ok(roots)
+}
+
 impl rustls_client_config_builder {
     /// Create a rustls_client_config_builder. Caller owns the memory and must
     /// eventually call rustls_client_config_builder_build, then free the
@@ -98,10 +526,21 @@ impl rustls_client_config_builder {
         ffi_panic_boundary! {
             let builder = ClientConfigBuilder {
                 base: rustls::ClientConfig::builder().with_safe_defaults(),
-                verifier: Arc::new(NoneVerifier),
+                cipher_suites: DEFAULT_CIPHER_SUITES.to_vec(),
+                versions: DEFAULT_VERSIONS.to_vec(),
+                kx_groups: ALL_KX_GROUPS.to_vec(),
+                verifier: None,
+                per_host_verifiers: HashMap::new(),
+                verifier_userdata: Arc::new(AtomicPtr::new(null_mut())),
+                pinned_sha256: None,
                 cert_resolver: None,
                 alpn_protocols: vec![],
                 enable_sni: true,
+                enable_early_data: false,
+                max_fragment_size: None,
+                session_storage: None,
+                preloaded_sessions: vec![],
+                key_log: None,
             };
             BoxCastPtr::to_mut_ptr(builder)
         }
@@ -123,6 +562,9 @@ impl rustls_client_config_builder {
     ///
     /// `versions` will only be used during the call and the application retains
     /// ownership. `len` is the number of consecutive `uint16_t` pointed to by `versions`.
+    ///
+    /// This uses rustls' default key exchange groups; see
+    /// rustls_client_config_builder_new_custom_ex to choose them.
     #[no_mangle]
     pub extern "C" fn rustls_client_config_builder_new_custom(
         cipher_suites: *const *const rustls_supported_ciphersuite,
@@ -130,6 +572,37 @@ impl rustls_client_config_builder {
         tls_versions: *const u16,
         tls_versions_len: size_t,
         builder_out: *mut *mut rustls_client_config_builder,
+    ) -> rustls_result {
+        rustls_client_config_builder::rustls_client_config_builder_new_custom_ex(
+            cipher_suites,
+            cipher_suites_len,
+            tls_versions,
+            tls_versions_len,
+            ALL_KX_GROUPS.as_ptr() as *const *const rustls_supported_kx_group,
+            ALL_KX_GROUPS.len(),
+            builder_out,
+        )
+    }
+
+    /// Create a rustls_client_config_builder, as
+    /// rustls_client_config_builder_new_custom does, with the key exchange
+    /// groups to offer given in preference order. `kx_groups` must point to
+    /// an array containing `kx_groups_len` pointers to
+    /// `rustls_supported_kx_group`, for instance from the provided arrays
+    /// RUSTLS_DEFAULT_KX_GROUPS or RUSTLS_ALL_KX_GROUPS. An empty list returns
+    /// RUSTLS_RESULT_INVALID_PARAMETER.
+    ///
+    /// The arrays are only used during the call and the application retains
+    /// ownership.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_new_custom_ex(
+        cipher_suites: *const *const rustls_supported_ciphersuite,
+        cipher_suites_len: size_t,
+        tls_versions: *const u16,
+        tls_versions_len: size_t,
+        kx_groups: *const *const rustls_supported_kx_group,
+        kx_groups_len: size_t,
+        builder_out: *mut *mut rustls_client_config_builder,
     ) -> rustls_result {
         ffi_panic_boundary! {
             let cipher_suites: &[*const rustls_supported_ciphersuite] = try_slice!(cipher_suites, cipher_suites_len);
@@ -142,6 +615,18 @@ impl rustls_client_config_builder {
                 }
             }
 
+            let kx_groups: &[*const rustls_supported_kx_group] = try_slice!(kx_groups, kx_groups_len);
+            if kx_groups.is_empty() {
+                return InvalidParameter;
+            }
+            let mut kx_vec: Vec<&'static SupportedKxGroup> = Vec::with_capacity(kx_groups.len());
+            for &kx in kx_groups.iter() {
+                match find_kx_group(kx) {
+                    Some(group) => kx_vec.push(group),
+                    None => return InvalidParameter,
+                }
+            }
+
             let tls_versions: &[u16] = try_slice!(tls_versions, tls_versions_len);
             let mut versions = vec![];
             for version_number in tls_versions {
@@ -153,27 +638,206 @@ impl rustls_client_config_builder {
                 }
             }
 
-            let result = rustls::ClientConfig::builder().with_cipher_suites(&cs_vec).with_safe_default_kx_groups().with_protocol_versions(&versions);
+            let result = rustls::ClientConfig::builder().with_cipher_suites(&cs_vec).with_kx_groups(&kx_vec).with_protocol_versions(&versions);
             let base = match result {
                 Ok(new) => new,
                 Err(_) => return rustls_result::InvalidParameter,
             };
             let config_builder = ClientConfigBuilder {
                 base,
-                verifier: Arc::new(NoneVerifier),
+                cipher_suites: cs_vec,
+                versions,
+                kx_groups: kx_vec,
+                verifier: None,
+                per_host_verifiers: HashMap::new(),
+                verifier_userdata: Arc::new(AtomicPtr::new(null_mut())),
+                pinned_sha256: None,
                 cert_resolver: None,
                 alpn_protocols: vec![],
                 enable_sni: true,
+                enable_early_data: false,
+                max_fragment_size: None,
+                session_storage: None,
+                preloaded_sessions: vec![],
+                key_log: None,
             };
 
             BoxCastPtr::set_mut_ptr(builder_out, config_builder);
             rustls_result::Ok
         }
     }
+
+    /// Create a rustls_client_config_builder from a declarative profile, so TLS
+    /// policy can be managed as data rather than code. Caller owns the memory
+    /// and must eventually call rustls_client_config_builder_build, then free
+    /// the resulting rustls_client_config.
+    ///
+    /// `profile` is a NUL-terminated UTF-8 string with one `key = value`
+    /// setting per line. Empty lines and lines starting with `#` are ignored.
+    /// List values are separated by commas. The supported keys are:
+    ///
+    /// - `versions`: protocol versions, `TLSv1.2` and/or `TLSv1.3`.
+    /// - `cipher_suites`: cipher suite names in preference order, as returned
+    ///   by rustls_supported_ciphersuite_get_name, e.g. `TLS13_AES_256_GCM_SHA384`.
+    /// - `alpn`: ALPN protocol IDs in preference order, e.g. `h2, http/1.1`.
+    ///   Each must be at most 255 bytes long.
+    /// - `roots`: path to a file of PEM-formatted trusted root certificates.
+    /// - `enable_sni`: `true` or `false`.
+    ///
+    /// Settings that are not given keep the defaults of
+    /// rustls_client_config_builder_new. Unknown keys, malformed lines and
+    /// unknown values result in RUSTLS_RESULT_INVALID_PARAMETER. Errors loading
+    /// `roots` are reported as in rustls_client_config_builder_load_roots_from_file.
+    /// If this returns an error, `builder_out` is left unchanged.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_from_profile(
+        profile: *const c_char,
+        builder_out: *mut *mut rustls_client_config_builder,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let profile: &CStr = unsafe {
+                if profile.is_null() {
+                    return NullParameter;
+                }
+                CStr::from_ptr(profile)
+            };
+            if builder_out.is_null() {
+                return NullParameter;
+            }
+            let profile: &str = match profile.to_str() {
+                Ok(s) => s,
+                Err(_) => return InvalidParameter,
+            };
+            match ClientConfigBuilder::from_profile(profile) {
+                Ok(config_builder) => {
+                    BoxCastPtr::set_mut_ptr(builder_out, config_builder);
+                    rustls_result::Ok
+                }
+                Err(e) => e,
+            }
+        }
+    }
+}
+
+impl ClientConfigBuilder {
+    /// Replace the builder's cipher suites and protocol versions. On error the
+    /// builder is left unchanged.
+    fn set_protocol_params(
+        &mut self,
+        cipher_suites: Vec<SupportedCipherSuite>,
+        versions: Vec<&'static SupportedProtocolVersion>,
+    ) -> Result<(), rustls_result> {
+        self.base = match rustls::ClientConfig::builder()
+            .with_cipher_suites(&cipher_suites)
+            .with_kx_groups(&self.kx_groups)
+            .with_protocol_versions(&versions)
+        {
+            Ok(base) => base,
+            Err(_) => return Err(InvalidParameter),
+        };
+        self.cipher_suites = cipher_suites;
+        self.versions = versions;
+        Ok(())
+    }
+
+    /// Parse a profile as described in rustls_client_config_builder_from_profile.
+    fn from_profile(profile: &str) -> Result<ClientConfigBuilder, rustls_result> {
+        let mut versions: Option<Vec<&'static SupportedProtocolVersion>> = None;
+        let mut cipher_suites: Option<Vec<SupportedCipherSuite>> = None;
+        let mut verifier: Option<Arc<dyn ServerCertVerifier>> = None;
+        let mut alpn_protocols: Vec<Vec<u8>> = vec![];
+        let mut enable_sni = true;
+
+        for line in profile.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return Err(InvalidParameter),
+            };
+            let mut values = value.split(',').map(str::trim).filter(|v| !v.is_empty());
+            match key {
+                "versions" => {
+                    let parsed = values.map(|v| match v {
+                        "TLSv1.2" => Ok(&rustls::version::TLS12),
+                        "TLSv1.3" => Ok(&rustls::version::TLS13),
+                        _ => Err(InvalidParameter),
+                    });
+                    versions = Some(parsed.collect::<Result<_, _>>()?);
+                }
+                "cipher_suites" => {
+                    let parsed = values.map(|v| {
+                        ALL_CIPHER_SUITES
+                            .iter()
+                            .find(|cs| cs.suite().as_str() == Some(v))
+                            .copied()
+                            .ok_or(InvalidParameter)
+                    });
+                    cipher_suites = Some(parsed.collect::<Result<_, _>>()?);
+                }
+                "alpn" => {
+                    let parsed = values.map(|v| match v.len() {
+                        1..=255 => Ok(v.as_bytes().to_vec()),
+                        _ => Err(InvalidParameter),
+                    });
+                    alpn_protocols = parsed.collect::<Result<_, _>>()?;
+                }
+                "roots" => {
+                    let roots = load_roots_from_pem_file(value)?;
+                    verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+                }
+                "enable_sni" => {
+                    enable_sni = match (values.next(), values.next()) {
+                        (Some("true"), None) => true,
+                        (Some("false"), None) => false,
+                        _ => return Err(InvalidParameter),
+                    }
+                }
+                _ => return Err(InvalidParameter),
+            }
+        }
+
+        let cipher_suites = cipher_suites.unwrap_or_else(|| DEFAULT_CIPHER_SUITES.to_vec());
+        let versions = versions.unwrap_or_else(|| DEFAULT_VERSIONS.to_vec());
+        let base = match rustls::ClientConfig::builder()
+            .with_cipher_suites(&cipher_suites)
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&versions)
+        {
+            Ok(base) => base,
+            Err(_) => return Err(InvalidParameter),
+        };
+        Ok(ClientConfigBuilder {
+            base,
+            cipher_suites,
+            versions,
+            kx_groups: ALL_KX_GROUPS.to_vec(),
+            verifier,
+            per_host_verifiers: HashMap::new(),
+            verifier_userdata: Arc::new(AtomicPtr::new(null_mut())),
+            pinned_sha256: None,
+            alpn_protocols,
+            enable_sni,
+            enable_early_data: false,
+            max_fragment_size: None,
+            cert_resolver: None,
+            session_storage: None,
+            preloaded_sessions: vec![],
+            key_log: None,
+        })
+    }
 }
 
 /// Input to a custom certificate verifier callback. See
 /// rustls_client_config_builder_dangerous_set_certificate_verifier().
+///
+/// The negotiated ALPN protocol is not included: rustls calls the verifier
+/// without any access to the connection, so it can't be passed through here.
+/// To tie certificate acceptance to the application protocol, check
+/// rustls_connection_get_alpn_protocol() once the handshake completes and
+/// close the connection if the combination isn't acceptable.
 #[allow(non_camel_case_types)]
 #[repr(C)]
 pub struct rustls_verify_server_cert_params<'a> {
@@ -181,6 +845,9 @@ pub struct rustls_verify_server_cert_params<'a> {
     pub intermediate_certs_der: &'a rustls_slice_slice_bytes<'a>,
     pub dns_name: rustls_str<'a>,
     pub ocsp_response: rustls_slice_bytes<'a>,
+    /// The time rustls is verifying the certificate at, in seconds since the
+    /// Unix epoch, or 0 if the system clock is set before the epoch.
+    pub now_unix_secs: u64,
 }
 
 /// User-provided input to a custom certificate verifier callback. See
@@ -208,8 +875,13 @@ type VerifyCallback = unsafe extern "C" fn(
 ) -> u32;
 
 // An implementation of rustls::ServerCertVerifier based on a C callback.
+// If `webpki` is set, a callback result of RUSTLS_RESULT_DEFER_TO_WEB_PKI
+// hands the decision to it. `userdata` is shared with the builder that
+// created the verifier; when non-NULL it replaces the thread-local userdata.
 struct Verifier {
     callback: VerifyCallback,
+    webpki: Option<rustls::client::WebPkiVerifier>,
+    userdata: Arc<AtomicPtr<c_void>>,
 }
 
 /// Safety: Verifier is Send because we don't allocate or deallocate any of its
@@ -226,9 +898,9 @@ impl rustls::client::ServerCertVerifier for Verifier {
         end_entity: &Certificate,
         intermediates: &[Certificate],
         server_name: &rustls::ServerName,
-        _scts: &mut dyn Iterator<Item = &[u8]>,
+        scts: &mut dyn Iterator<Item = &[u8]>,
         ocsp_response: &[u8],
-        _now: SystemTime,
+        now: SystemTime,
     ) -> Result<ServerCertVerified, rustls::Error> {
         let cb = self.callback;
         let dns_name: &str = match server_name {
@@ -240,27 +912,39 @@ impl rustls::client::ServerCertVerifier for Verifier {
             Err(NulByte {}) => return Err(rustls::Error::General("NUL byte in SNI".to_string())),
         };
 
-        let intermediates: Vec<_> = intermediates.iter().map(|cert| cert.as_ref()).collect();
+        let intermediates_der: Vec<_> = intermediates.iter().map(|cert| cert.as_ref()).collect();
 
-        let intermediates = rustls_slice_slice_bytes {
-            inner: &*intermediates,
+        let intermediates_der = rustls_slice_slice_bytes {
+            inner: &*intermediates_der,
         };
 
         let params = rustls_verify_server_cert_params {
             end_entity_cert_der: end_entity.as_ref().into(),
-            intermediate_certs_der: &intermediates,
+            intermediate_certs_der: &intermediates_der,
             dns_name,
             ocsp_response: ocsp_response.into(),
+            now_unix_secs: now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        };
+        let userdata = match self.userdata.load(Ordering::Acquire) {
+            u if !u.is_null() => u,
+            _ => userdata_get().map_err(|_| {
+                rustls::Error::General("internal error with thread-local storage".to_string())
+            })?,
         };
-        let userdata = userdata_get().map_err(|_| {
-            rustls::Error::General("internal error with thread-local storage".to_string())
-        })?;
         let result: u32 = unsafe { cb(userdata, &params) };
         let result: rustls_result =
             rustls_result::try_from(result).unwrap_or(rustls_result::General);
-        match result {
-            rustls_result::Ok => Ok(ServerCertVerified::assertion()),
-            r => Err(error::cert_result_to_error(r)),
+        match (result, &self.webpki) {
+            (rustls_result::Ok, _) => Ok(ServerCertVerified::assertion()),
+            (rustls_result::DeferToWebPki, Some(webpki)) => webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            ),
+            (r, _) => Err(error::cert_result_to_error(r)),
         }
     }
 }
@@ -270,9 +954,11 @@ impl rustls_client_config_builder {
     ///
     /// The callback must not capture any of the pointers in its
     /// rustls_verify_server_cert_params.
-    /// If `userdata` has been set with rustls_connection_set_userdata, it
-    /// will be passed to the callback. Otherwise the userdata param passed to
-    /// the callback will be NULL.
+    /// If `userdata` has been set with
+    /// rustls_client_config_builder_set_verifier_userdata, it will be passed to
+    /// the callback. Otherwise, if `userdata` has been set with
+    /// rustls_connection_set_userdata, that will be passed. Otherwise the
+    /// userdata param passed to the callback will be NULL.
     ///
     /// The callback must be safe to call on any thread at any time, including
     /// multiple concurrent calls. So, for instance, if the callback mutates
@@ -280,22 +966,26 @@ impl rustls_client_config_builder {
     /// to make such mutation safe.
     ///
     /// The callback receives certificate chain information as raw bytes.
-    /// Currently this library offers no functions for C code to parse the
-    /// certificates, so you'll need to bring your own certificate parsing library
-    /// if you need to parse them.
+    /// This library only offers simple lookups on them, such as
+    /// rustls_certificate_get_not_before_unix and
+    /// rustls_certificate_get_not_after_unix for the validity period; for
+    /// anything more you'll need to bring your own certificate parsing library.
     ///
     /// If you intend to write a verifier that accepts all certificates, be aware
     /// that special measures are required for IP addresses. Rustls currently
     /// (0.20.0) doesn't support building a ClientConnection with an IP address
     /// (because it's not a valid DnsNameRef). One workaround is to detect IP
-    /// addresses and rewrite them to `example.invalid`, and _also_ to disable
-    /// SNI via rustls_client_config_builder_set_enable_sni (IP addresses don't
+    /// addresses and connect with rustls_client_connection_new_anonymous, which
+    /// uses the name `example.invalid` and disables SNI (IP addresses don't
     /// need SNI).
     ///
     /// If the custom verifier accepts the certificate, it should return
     /// RUSTLS_RESULT_OK. Otherwise, it may return any other rustls_result error.
     /// Feel free to use an appropriate error from the RUSTLS_RESULT_CERT_*
     /// section.
+    /// RUSTLS_RESULT_DEFER_TO_WEB_PKI is only meaningful with
+    /// rustls_client_config_builder_set_certificate_verifier_with_roots; here
+    /// it rejects the certificate like any other error.
     ///
     /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.DangerousClientConfig.html#method.set_certificate_verifier>
     #[no_mangle]
@@ -310,292 +1000,2562 @@ impl rustls_client_config_builder {
                 None => return rustls_result::InvalidParameter,
             };
 
-            let verifier: Verifier = Verifier{callback};
-            config_builder.verifier = Arc::new(verifier);
+            let verifier: Verifier = Verifier {
+                callback,
+                webpki: None,
+                userdata: config_builder.verifier_userdata.clone(),
+            };
+            config_builder.verifier = Some(Arc::new(verifier));
             rustls_result::Ok
         }
     }
 
-    /// Use the trusted root certificates from the provided store.
+    /// Set a server certificate verifier that runs `callback` for extra
+    /// checks on top of standard WebPKI validation against `roots`. The
+    /// callback is called as for
+    /// rustls_client_config_builder_dangerous_set_certificate_verifier, and
+    /// runs first:
     ///
-    /// This replaces any trusted roots already configured with copies
-    /// from `roots`. This adds 1 to the refcount for `roots`. When you
-    /// call rustls_client_config_free or rustls_client_config_builder_free,
-    /// those will subtract 1 from the refcount for `roots`.
+    /// - RUSTLS_RESULT_DEFER_TO_WEB_PKI means the callback's own checks
+    ///   passed; the certificate is then verified against `roots`, and that
+    ///   decides the outcome.
+    /// - RUSTLS_RESULT_OK accepts the certificate without WebPKI validation.
+    /// - Any other value rejects it, as for
+    ///   rustls_client_config_builder_dangerous_set_certificate_verifier.
+    ///
+    /// A callback that only adds policy checks, such as requiring an
+    /// organization in the subject, should return RUSTLS_RESULT_DEFER_TO_WEB_PKI
+    /// rather than RUSTLS_RESULT_OK when they pass. The roots are copied, so
+    /// `roots` may be freed afterwards.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_use_roots(
+    pub extern "C" fn rustls_client_config_builder_set_certificate_verifier_with_roots(
         config_builder: *mut rustls_client_config_builder,
         roots: *const rustls_root_cert_store,
+        callback: rustls_verify_server_cert_callback,
     ) -> rustls_result {
         ffi_panic_boundary! {
-            let builder = try_mut_from_ptr!(config_builder);
-            let root_store: &RootCertStore = try_ref_from_ptr!(roots);
-            builder.verifier = Arc::new(rustls::client::WebPkiVerifier::new(root_store.clone(), None));
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let root_store: &RootStore = try_ref_from_ptr!(roots);
+            let callback: VerifyCallback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::InvalidParameter,
+            };
+
+            let verifier = Verifier {
+                callback,
+                webpki: Some(rustls::client::WebPkiVerifier::new(root_store.roots.clone(), None)),
+                userdata: config_builder.verifier_userdata.clone(),
+            };
+            config_builder.verifier = Some(Arc::new(verifier));
             rustls_result::Ok
         }
     }
 
-    /// Add trusted root certificates from the named file, which should contain
-    /// PEM-formatted certificates.
+    /// Use a custom server certificate verifier for connections to one host.
+    /// When the server name passed to rustls_client_connection_new matches
+    /// `hostname` (a NUL-terminated string, compared case-insensitively),
+    /// `callback` verifies the server's certificate instead of the builder's
+    /// default verifier. Connections to every other host keep using the
+    /// default verifier set with the other functions on this builder.
+    ///
+    /// This allows, for instance, pinning the certificate of one host while
+    /// trusting a root store for all others. `callback` follows the same
+    /// rules as for rustls_client_config_builder_dangerous_set_certificate_verifier,
+    /// including how it receives userdata.
+    ///
+    /// Calling this again with the same hostname replaces its verifier.
+    /// Returns RUSTLS_RESULT_INVALID_DNS_NAME_ERROR if `hostname` isn't a
+    /// valid DNS name.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_load_roots_from_file(
+    pub extern "C" fn rustls_client_config_builder_set_per_host_verifier(
         config_builder: *mut rustls_client_config_builder,
-        filename: *const c_char,
+        hostname: *const c_char,
+        callback: rustls_verify_server_cert_callback,
     ) -> rustls_result {
         ffi_panic_boundary! {
             let config_builder = try_mut_from_ptr!(config_builder);
-            let filename: &CStr = unsafe {
-                if filename.is_null() {
-                    return rustls_result::NullParameter;
+            let hostname: &CStr = unsafe {
+                if hostname.is_null() {
+                    return NullParameter;
                 }
-                CStr::from_ptr(filename)
-            };
-
-            let filename: &[u8] = filename.to_bytes();
-            let filename: &str = match std::str::from_utf8(filename) {
-                Ok(s) => s,
-                Err(_) => return rustls_result::Io,
+                CStr::from_ptr(hostname)
             };
-            let filename: &OsStr = OsStr::new(filename);
-            let mut cafile = match File::open(filename) {
-                Ok(f) => f,
-                Err(_) => return rustls_result::Io,
+            let callback: VerifyCallback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::InvalidParameter,
             };
-
-            let mut bufreader = BufReader::new(&mut cafile);
-            let certs = match rustls_pemfile::certs(&mut bufreader) {
-                Ok(certs) => certs,
-                Err(_) => return rustls_result::Io,
+            let hostname: &str = match hostname.to_str() {
+                Ok(s) => s,
+                Err(std::str::Utf8Error { .. }) => return rustls_result::InvalidDnsNameError,
             };
-
-            let mut roots = RootCertStore::empty();
-            let (_, failed) = roots.add_parsable_certificates(&certs);
-            if failed > 0 {
-                return rustls_result::CertificateParseError;
+            if webpki::DnsNameRef::try_from_ascii_str(hostname).is_err() {
+                return rustls_result::InvalidDnsNameError;
             }
-
-            config_builder.verifier = Arc::new(rustls::client::WebPkiVerifier::new(roots, None));
+            let verifier = Verifier {
+                callback,
+                webpki: None,
+                userdata: config_builder.verifier_userdata.clone(),
+            };
+            config_builder
+                .per_host_verifiers
+                .insert(hostname.to_ascii_lowercase(), Arc::new(verifier));
             rustls_result::Ok
         }
     }
 
-    /// Set the ALPN protocol list to the given protocols. `protocols` must point
-    /// to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
-    /// elements. Each element of the buffer must be a rustls_slice_bytes whose
-    /// data field points to a single ALPN protocol ID. Standard ALPN protocol
-    /// IDs are defined at
-    /// <https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids>.
+    /// Set the userdata passed to every certificate verifier callback on this
+    /// builder, set with
+    /// rustls_client_config_builder_dangerous_set_certificate_verifier,
+    /// rustls_client_config_builder_set_certificate_verifier_with_roots or
+    /// rustls_client_config_builder_set_per_host_verifier. It
+    /// takes precedence over userdata set with rustls_connection_set_userdata,
+    /// and unlike that, it doesn't depend on thread-local storage, so it
+    /// reaches the callback even when certificate verification runs on a
+    /// different thread than the rustls_connection_read_tls or
+    /// rustls_connection_process_new_packets call that triggered it.
     ///
-    /// This function makes a copy of the data in `protocols` and does not retain
-    /// any pointers, so the caller can free the pointed-to memory after calling.
+    /// This may be called before or after setting the verifier. Passing NULL
+    /// goes back to the userdata set with rustls_connection_set_userdata.
     ///
-    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.alpn_protocols>
+    /// The pointer is not dereferenced by rustls, but it must stay valid for
+    /// as long as any rustls_client_config built from this builder, or any
+    /// connection made from such a config, is in use. Since the callback may
+    /// be called concurrently from several connections, any state behind it
+    /// must be safe to share between threads.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_set_alpn_protocols(
-        builder: *mut rustls_client_config_builder,
-        protocols: *const rustls_slice_bytes,
-        len: size_t,
-    ) -> rustls_result {
+    pub extern "C" fn rustls_client_config_builder_set_verifier_userdata(
+        config_builder: *mut rustls_client_config_builder,
+        userdata: *mut c_void,
+    ) {
         ffi_panic_boundary! {
-            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
-            let protocols: &[rustls_slice_bytes] = try_slice!(protocols, len);
+            let config_builder = try_mut_from_ptr!(config_builder);
+            config_builder
+                .verifier_userdata
+                .store(userdata, Ordering::Release);
+        }
+    }
 
-            let mut vv: Vec<Vec<u8>> = Vec::with_capacity(protocols.len());
-            for p in protocols {
-                let v: &[u8] = try_slice!(p.data, p.len);
-                vv.push(v.to_vec());
-            }
-            config.alpn_protocols = vv;
-            rustls_result::Ok
-        }
-    }
-
-    /// Enable or disable SNI.
-    /// <https://docs.rs/rustls/0.20.0/rustls/struct.ClientConfig.html#structfield.enable_sni>
+    /// Remove any certificate verifier or trusted roots configured on the
+    /// builder, including per-host verifiers, returning it to its initial
+    /// state, in which all server certificates are rejected. This lets
+    /// configuration code undo a dangerous verifier installed earlier; a new
+    /// verifier or roots must be configured before the resulting config can
+    /// connect to anything.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_set_enable_sni(
-        config: *mut rustls_client_config_builder,
-        enable: bool,
+    pub extern "C" fn rustls_client_config_builder_reset_verifier(
+        config_builder: *mut rustls_client_config_builder,
     ) {
         ffi_panic_boundary! {
-            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
-            config.enable_sni = enable;
+            let config_builder = try_mut_from_ptr!(config_builder);
+            config_builder.verifier = None;
+            config_builder.per_host_verifiers.clear();
         }
     }
 
-    /// Provide the configuration a list of certificates where the connection
-    /// will select the first one that is compatible with the server's signature
-    /// verification capabilities. Clients that want to support both ECDSA and
-    /// RSA certificates will want the ECSDA to go first in the list.
+    /// Trust exactly one certificate: connections succeed only if the server's
+    /// end-entity certificate is byte-for-byte equal to the DER-encoded
+    /// certificate in `cert_der` (of length `cert_der_len`). This is intended
+    /// for development and internal tooling using a self-signed certificate,
+    /// and is safer than accepting all certificates.
     ///
-    /// The built configuration will keep a reference to all certified keys
-    /// provided. The client may `rustls_certified_key_free()` afterwards
-    /// without the configuration losing them. The same certified key may also
-    /// be used in multiple configs.
+    /// By design, the server name, validity period and issuer of the certificate
+    /// are not checked. The server must still prove possession of the
+    /// certificate's private key during the handshake.
     ///
-    /// EXPERIMENTAL: installing a client authentication callback will replace any
-    /// configured certified keys and vice versa.
+    /// This replaces any previously configured verifier or trusted roots. The
+    /// certificate is copied, so the caller can free `cert_der` afterwards.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_set_certified_key(
-        builder: *mut rustls_client_config_builder,
-        certified_keys: *const *const rustls_certified_key,
-        certified_keys_len: size_t,
+    pub extern "C" fn rustls_client_config_builder_trust_self_signed(
+        config_builder: *mut rustls_client_config_builder,
+        cert_der: *const u8,
+        cert_der_len: size_t,
     ) -> rustls_result {
         ffi_panic_boundary! {
-            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
-            let keys_ptrs: &[*const rustls_certified_key] = try_slice!(certified_keys, certified_keys_len);
-            let mut keys: Vec<Arc<CertifiedKey>> = Vec::new();
-            for &key_ptr in keys_ptrs {
-                let certified_key: Arc<CertifiedKey> = try_arc_from_ptr!(key_ptr);
-                keys.push(certified_key);
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let cert_der: &[u8] = try_slice!(cert_der, cert_der_len);
+            if cert_der.is_empty() {
+                return InvalidParameter;
             }
-            config.cert_resolver = Some(Arc::new(ResolvesClientCertFromChoices { keys }));
+            let verifier = SelfSignedVerifier {
+                cert: Certificate(cert_der.to_vec()),
+            };
+            config_builder.verifier = Some(Arc::new(verifier));
             rustls_result::Ok
         }
     }
-}
-
-/// Always send the same client certificate.
-struct ResolvesClientCertFromChoices {
-    keys: Vec<Arc<CertifiedKey>>,
-}
 
-impl ResolvesClientCert for ResolvesClientCertFromChoices {
-    fn resolve(
-        &self,
-        _acceptable_issuers: &[&[u8]],
-        sig_schemes: &[rustls::SignatureScheme],
-    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
-        for key in self.keys.iter() {
-            if key.key.choose_scheme(sig_schemes).is_some() {
-                return Some(key.clone());
-            }
+    /// Use the trusted root certificates from the provided store.
+    ///
+    /// This replaces any trusted roots already configured with copies
+    /// from `roots`. This adds 1 to the refcount for `roots`. When you
+    /// call rustls_client_config_free or rustls_client_config_builder_free,
+    /// those will subtract 1 from the refcount for `roots`.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_use_roots(
+        config_builder: *mut rustls_client_config_builder,
+        roots: *const rustls_root_cert_store,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let builder = try_mut_from_ptr!(config_builder);
+            let root_store: &RootStore = try_ref_from_ptr!(roots);
+            builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(root_store.roots.clone(), None)));
+            rustls_result::Ok
         }
-        None
     }
 
-    fn has_certs(&self) -> bool {
-        !self.keys.is_empty()
+    /// Use the trusted root certificates from the provided store, as
+    /// rustls_client_config_builder_use_roots does, and also reject server
+    /// certificates that are listed as revoked. `crl_ders` points to `crls_len`
+    /// DER-encoded certificate revocation lists (CRLs). A certificate in the
+    /// server's chain counts as revoked if a CRL with the same issuer name lists
+    /// its serial number.
+    ///
+    /// Each CRL must be signed by a root in `roots` whose subject is the CRL's
+    /// issuer; CRLs issued by intermediate CAs aren't supported. Once a CRL is
+    /// past its nextUpdate time, certificates from its issuer are rejected
+    /// until the CRLs are replaced, so callers must keep them current. The
+    /// CRLs are copied, so the caller can free them afterwards.
+    ///
+    /// On error, the builder is left unchanged. Returns
+    /// RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if any CRL can't be parsed,
+    /// RUSTLS_RESULT_CERT_INVALID_SIGNATURE_TYPE if one uses an unsupported
+    /// signature algorithm, and RUSTLS_RESULT_CERT_INVALID_SIGNATURE if one
+    /// isn't signed by a matching root.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_use_roots_with_crls(
+        config_builder: *mut rustls_client_config_builder,
+        roots: *const rustls_root_cert_store,
+        crl_ders: *const rustls_slice_bytes,
+        crls_len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let builder = try_mut_from_ptr!(config_builder);
+            let root_store: &RootStore = try_ref_from_ptr!(roots);
+            let crl_slices: &[rustls_slice_bytes] = try_slice!(crl_ders, crls_len);
+            let mut crls: HashMap<Vec<u8>, Vec<TrustedCrl>> = HashMap::new();
+            for crl in crl_slices {
+                let der: &[u8] = try_slice!(crl.data, crl.len);
+                let (issuer, crl) = match TrustedCrl::new(der, root_store) {
+                    Ok(crl) => crl,
+                    Err(e) => return e,
+                };
+                crls.entry(issuer).or_default().push(crl);
+            }
+            builder.verifier = Some(Arc::new(CrlVerifier {
+                inner: rustls::client::WebPkiVerifier::new(root_store.roots.clone(), None),
+                crls,
+            }));
+            rustls_result::Ok
+        }
     }
-}
 
-impl rustls_client_config_builder {
-    /// Turn a *rustls_client_config_builder (mutable) into a const *rustls_client_config
-    /// (read-only).
+    /// Pin the server's end-entity certificate: in addition to the normal
+    /// certificate verification (for instance against the roots given to
+    /// rustls_client_config_builder_use_roots), require the SHA-256 hash of
+    /// the DER-encoded end-entity certificate to equal `sha256`. Certificates
+    /// that don't match are rejected with RUSTLS_RESULT_CERT_INVALID_DATA.
+    /// The pin applies to whichever verifier the builder has when it's built,
+    /// so it may be set before or after the roots. `sha256_len` must be 32,
+    /// or RUSTLS_RESULT_INVALID_PARAMETER is returned.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_build(
-        builder: *mut rustls_client_config_builder,
-    ) -> *const rustls_client_config {
+    pub extern "C" fn rustls_client_config_builder_set_pinned_cert_sha256(
+        config_builder: *mut rustls_client_config_builder,
+        sha256: *const u8,
+        sha256_len: size_t,
+    ) -> rustls_result {
         ffi_panic_boundary! {
-            let builder: Box<ClientConfigBuilder> = try_box_from_ptr!(builder);
-            let config = builder.base.with_custom_certificate_verifier(builder.verifier);
-            let mut config = match builder.cert_resolver {
-                Some(r) => config.with_client_cert_resolver(r),
-                None => config.with_no_client_auth(),
+            let builder = try_mut_from_ptr!(config_builder);
+            let sha256: &[u8] = try_slice!(sha256, sha256_len);
+            let sha256: [u8; 32] = match sha256.try_into() {
+                Ok(pin) => pin,
+                Err(_) => return InvalidParameter,
             };
-            config.alpn_protocols = builder.alpn_protocols;
-            config.enable_sni = builder.enable_sni;
-            ArcCastPtr::to_const_ptr(config)
+            builder.pinned_sha256 = Some(sha256);
+            rustls_result::Ok
         }
     }
 
-    /// "Free" a client_config_builder without building it into a rustls_client_config.
-    /// Normally builders are built into rustls_client_config via `rustls_client_config_builder_build`
-    /// and may not be free'd or otherwise used afterwards.
-    /// Use free only when the building of a config has to be aborted before a config
-    /// was created.
+    /// Add trusted root certificates from the named file, which should contain
+    /// PEM-formatted certificates.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_builder_free(config: *mut rustls_client_config_builder) {
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_file(
+        config_builder: *mut rustls_client_config_builder,
+        filename: *const c_char,
+    ) -> rustls_result {
         ffi_panic_boundary! {
-            BoxCastPtr::to_box(config);
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let filename: &CStr = unsafe {
+                if filename.is_null() {
+                    return rustls_result::NullParameter;
+                }
+                CStr::from_ptr(filename)
+            };
+
+            let filename: &[u8] = filename.to_bytes();
+            let filename: &str = match std::str::from_utf8(filename) {
+                Ok(s) => s,
+                Err(_) => return rustls_result::Io,
+            };
+            let roots = match load_roots_from_pem_file(filename) {
+                Ok(roots) => roots,
+                Err(e) => return e,
+            };
+
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
         }
     }
-}
 
-impl rustls_client_config {
-    /// "Free" a rustls_client_config previously returned from
-    /// rustls_client_config_builder_build. Since rustls_client_config is actually an
-    /// atomically reference-counted pointer, extant client connections may still
-    /// hold an internal reference to the Rust object. However, C code must
-    /// consider this pointer unusable after "free"ing it.
-    /// Calling with NULL is fine. Must not be called twice with the same value.
+    /// Add trusted root certificates from the named file, which should contain
+    /// PEM-formatted certificates. This behaves like
+    /// rustls_client_config_builder_load_roots_from_file, but if a certificate
+    /// can't be used, its 0-based position in the file is written to
+    /// `first_failed_index_out` along with the
+    /// RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR result, to help track down the
+    /// bad entry in a large bundle. Otherwise SIZE_MAX is written there.
+    /// `first_failed_index_out` may be NULL.
     #[no_mangle]
-    pub extern "C" fn rustls_client_config_free(config: *const rustls_client_config) {
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_file_ex(
+        config_builder: *mut rustls_client_config_builder,
+        filename: *const c_char,
+        first_failed_index_out: *mut size_t,
+    ) -> rustls_result {
         ffi_panic_boundary! {
-            let config: &ClientConfig = try_ref_from_ptr!(config);
-            // To free the rustls_client_config, we reconstruct the Arc and then drop it. It should
-            // have a refcount of 1, representing the C code's copy. When it drops, that
-            // refcount will go down to 0 and the inner ClientConfig will be dropped.
-            unsafe { drop(Arc::from_raw(config)) };
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let first_failed_index_out: Option<&mut size_t> =
+                unsafe { first_failed_index_out.as_mut() };
+            let filename: &CStr = unsafe {
+                if filename.is_null() {
+                    return rustls_result::NullParameter;
+                }
+                CStr::from_ptr(filename)
+            };
+
+            let filename: &[u8] = filename.to_bytes();
+            let filename: &str = match std::str::from_utf8(filename) {
+                Ok(s) => s,
+                Err(_) => return rustls_result::Io,
+            };
+            let certs = match read_pem_certs_file(filename) {
+                Ok(certs) => certs,
+                Err(e) => return e,
+            };
+            let mut roots = RootCertStore::empty();
+            let first_failed = certs
+                .into_iter()
+                .position(|der| roots.add(&Certificate(der)).is_err());
+            if let Some(out) = first_failed_index_out {
+                *out = first_failed.unwrap_or(usize::MAX);
+            }
+            if first_failed.is_some() {
+                return rustls_result::CertificateParseError;
+            }
+
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
         }
     }
 
-    /// Create a new rustls_connection containing a client connection and return
-    /// it in the output parameter `out`. If this returns an error code, the
-    /// memory pointed to by `conn_out` remains unchanged. If this returns a
-    /// non-error, the memory pointed to by `conn_out` is modified to point at a
-    /// valid rustls_connection. The caller now owns the rustls_connection and must
-    /// call `rustls_connection_free` when done with it.
+    /// Add trusted root certificates from the named file, which should contain
+    /// PEM-formatted certificates, skipping any certificates that can't be
+    /// used instead of failing. The number of certificates added and skipped
+    /// are stored in `added_out` and `failed_out`. This suits large bundles
+    /// like the Mozilla CA set, which may contain a certificate rustls can't
+    /// parse; rustls_client_config_builder_load_roots_from_file rejects those.
+    ///
+    /// Returns RUSTLS_RESULT_IO if the file can't be read or contains no
+    /// certificates, and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if none of its
+    /// certificates can be used. In those cases the builder is left
+    /// unchanged; the counts are stored whenever the file could be read.
     #[no_mangle]
-    pub extern "C" fn rustls_client_connection_new(
-        config: *const rustls_client_config,
-        hostname: *const c_char,
-        conn_out: *mut *mut rustls_connection,
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_file_lenient(
+        config_builder: *mut rustls_client_config_builder,
+        filename: *const c_char,
+        added_out: *mut size_t,
+        failed_out: *mut size_t,
     ) -> rustls_result {
         ffi_panic_boundary! {
-        let hostname: &CStr = unsafe {
-            if hostname.is_null() {
+            let config_builder = try_mut_from_ptr!(config_builder);
+            if added_out.is_null() || failed_out.is_null() {
                 return NullParameter;
             }
-            CStr::from_ptr(hostname)
-        };
-        let config: Arc<ClientConfig> = try_arc_from_ptr!(config);
-        let hostname: &str = match hostname.to_str() {
-            Ok(s) => s,
-            Err(std::str::Utf8Error { .. }) => return rustls_result::InvalidDnsNameError,
-        };
-        let server_name: rustls::ServerName = match hostname.try_into() {
-            Ok(sn) => sn,
-            Err(_) => return rustls_result::InvalidDnsNameError,
-        };
-        let client = ClientConnection::new(config, server_name).unwrap();
+            let filename: &CStr = unsafe {
+                if filename.is_null() {
+                    return rustls_result::NullParameter;
+                }
+                CStr::from_ptr(filename)
+            };
+
+            let filename: &[u8] = filename.to_bytes();
+            let filename: &str = match std::str::from_utf8(filename) {
+                Ok(s) => s,
+                Err(_) => return rustls_result::Io,
+            };
+            let certs = match read_pem_certs_file(filename) {
+                Ok(certs) => certs,
+                Err(e) => return e,
+            };
+            let mut roots = RootCertStore::empty();
+            let (added, failed) = roots.add_parsable_certificates(&certs);
+            unsafe {
+                *added_out = added;
+                *failed_out = failed;
+            }
+            if certs.is_empty() {
+                return rustls_result::Io;
+            }
+            if added == 0 {
+                return rustls_result::CertificateParseError;
+            }
 
-        // We've succeeded. Put the client on the heap, and transfer ownership
-        // to the caller. After this point, we must return CRUSTLS_OK so the
-        // caller knows it is responsible for this memory.
-        let c = Connection::from_client(client);
-        BoxCastPtr::set_mut_ptr(conn_out, c);
-        rustls_result::Ok
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::ptr::{null, null_mut};
+    /// Add trusted root certificates from every file in the directory named
+    /// `dirname` whose name ends in ".pem" or ".crt", such as a system trust
+    /// store directory like /etc/ssl/certs. Files that can't be read or
+    /// parsed, and certificates that can't be used, are skipped, as for
+    /// rustls_root_cert_store_from_dir. Returns RUSTLS_RESULT_IO if the
+    /// directory can't be read or yields no usable certificates; the builder
+    /// is then left unchanged.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_dir(
+        config_builder: *mut rustls_client_config_builder,
+        dirname: *const c_char,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let dirname: &CStr = unsafe {
+                if dirname.is_null() {
+                    return rustls_result::NullParameter;
+                }
+                CStr::from_ptr(dirname)
+            };
+            let dirname: &str = match dirname.to_str() {
+                Ok(s) => s,
+                Err(_) => return rustls_result::Io,
+            };
+            let roots = match load_roots_from_dir(dirname) {
+                Ok(store) if !store.roots.is_empty() => store.roots,
+                _ => return rustls_result::Io,
+            };
 
-    use super::*;
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
+        }
+    }
 
-    #[test]
-    fn test_config_builder() {
-        let builder: *mut rustls_client_config_builder =
-            rustls_client_config_builder::rustls_client_config_builder_new();
-        let h1 = "http/1.1".as_bytes();
-        let h2 = "h2".as_bytes();
-        let alpn: Vec<rustls_slice_bytes> = vec![h1.into(), h2.into()];
-        rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
-            builder,
-            alpn.as_ptr(),
-            alpn.len(),
-        );
-        rustls_client_config_builder::rustls_client_config_builder_set_enable_sni(builder, false);
-        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
-        {
-            let config2 = try_ref_from_ptr!(config);
-            assert_eq!(config2.enable_sni, false);
-            assert_eq!(config2.alpn_protocols, vec![h1, h2]);
+    /// Add trusted root certificates from `pem_bytes`, a buffer of length
+    /// `pem_len` holding PEM-formatted certificates, e.g. a CA bundle embedded
+    /// in the program or fetched over the network. This behaves like
+    /// rustls_client_config_builder_load_roots_from_file, without the need to
+    /// write the PEM to a file first. The buffer is not retained, so the
+    /// caller can free it afterwards.
+    ///
+    /// Returns RUSTLS_RESULT_IO if the buffer contains no certificates, and
+    /// RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if any of them can't be used.
+    /// In either case the builder is left unchanged.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_buffer(
+        config_builder: *mut rustls_client_config_builder,
+        pem_bytes: *const u8,
+        pem_len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let mut pem: &[u8] = try_slice!(pem_bytes, pem_len);
+            let certs = match rustls_pemfile::certs(&mut pem) {
+                Ok(certs) if !certs.is_empty() => certs,
+                _ => return rustls_result::Io,
+            };
+            let roots = match roots_from_certs(&certs) {
+                Ok(roots) => roots,
+                Err(e) => return e,
+            };
+
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
         }
-        rustls_client_config::rustls_client_config_free(config)
+    }
+
+    /// Add trusted root certificates from the named file, which should contain
+    /// PEM-formatted certificates, calling `callback` with `userdata` for each
+    /// certificate that can't be used. If the callback returns true for every
+    /// bad certificate, they are skipped and the remaining certificates are
+    /// used. If it returns false, loading stops, the builder is left unchanged
+    /// and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR is returned. This is useful for
+    /// auditing large CA bundles, where
+    /// rustls_client_config_builder_load_roots_from_file only reports that
+    /// some certificate failed.
+    ///
+    /// Returns RUSTLS_RESULT_IO if the file can't be read or contains no
+    /// certificates, and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if every
+    /// certificate was skipped. In those cases the builder is left unchanged.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_load_roots_from_file_with_callback(
+        config_builder: *mut rustls_client_config_builder,
+        filename: *const c_char,
+        callback: rustls_bad_root_callback,
+        userdata: *mut c_void,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config_builder = try_mut_from_ptr!(config_builder);
+            let callback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let filename: &CStr = unsafe {
+                if filename.is_null() {
+                    return rustls_result::NullParameter;
+                }
+                CStr::from_ptr(filename)
+            };
+
+            let filename: &[u8] = filename.to_bytes();
+            let filename: &str = match std::str::from_utf8(filename) {
+                Ok(s) => s,
+                Err(_) => return rustls_result::Io,
+            };
+            let certs = match read_pem_certs_file(filename) {
+                Ok(certs) if !certs.is_empty() => certs,
+                Ok(_) => return rustls_result::Io,
+                Err(e) => return e,
+            };
+
+            let mut roots = RootCertStore::empty();
+            for der in certs {
+                let cert = Certificate(der);
+                if let Err(e) = roots.add(&cert) {
+                    let reason = format!("{:?}", e);
+                    let reason: rustls_str = match reason.as_str().try_into() {
+                        Ok(r) => r,
+                        Err(NulByte {}) => rustls_str::from_str_unchecked(""),
+                    };
+                    if !unsafe { callback(userdata, cert.0.as_slice().into(), reason) } {
+                        return rustls_result::CertificateParseError;
+                    }
+                }
+            }
+            if roots.is_empty() {
+                return rustls_result::CertificateParseError;
+            }
+
+            config_builder.verifier = Some(Arc::new(rustls::client::WebPkiVerifier::new(roots, None)));
+            rustls_result::Ok
+        }
+    }
+
+    /// Set the ALPN protocol list to the given protocols. `protocols` must point
+    /// to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
+    /// elements. Each element of the buffer must be a rustls_slice_bytes whose
+    /// data field points to a single ALPN protocol ID. Standard ALPN protocol
+    /// IDs are defined at
+    /// <https://www.iana.org/assignments/tls-extensiontype-values/tls-extensiontype-values.xhtml#alpn-protocol-ids>.
+    ///
+    /// This function makes a copy of the data in `protocols` and does not retain
+    /// any pointers, so the caller can free the pointed-to memory after calling.
+    /// Protocol IDs are copied byte for byte and may contain any byte values,
+    /// including NUL and non-ASCII bytes. Each must be between 1 and 255 bytes
+    /// long, as required on the wire; otherwise this returns
+    /// RUSTLS_RESULT_INVALID_PARAMETER and leaves the builder unchanged.
+    ///
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.alpn_protocols>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_alpn_protocols(
+        builder: *mut rustls_client_config_builder,
+        protocols: *const rustls_slice_bytes,
+        len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            let protocols: &[rustls_slice_bytes] = try_slice!(protocols, len);
+
+            let mut vv: Vec<Vec<u8>> = Vec::with_capacity(protocols.len());
+            for p in protocols {
+                if p.len == 0 || p.len > 255 {
+                    return InvalidParameter;
+                }
+                let v: &[u8] = try_slice!(p.data, p.len);
+                vv.push(v.to_vec());
+            }
+            config.alpn_protocols = vv;
+            rustls_result::Ok
+        }
+    }
+
+    /// Set the ALPN protocol list, in the client's order of preference. This is
+    /// the same as `rustls_client_config_builder_set_alpn_protocols`, but
+    /// documents the ordering guarantee: protocols are offered to the server in
+    /// exactly the order given, most preferred first. A server that honors
+    /// client preference will pick the first entry it supports.
+    ///
+    /// <https://datatracker.ietf.org/doc/html/rfc7301#section-3.1>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_alpn_protocols_with_fallback_order(
+        builder: *mut rustls_client_config_builder,
+        protocols: *const rustls_slice_bytes,
+        len: size_t,
+    ) -> rustls_result {
+        rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+            builder, protocols, len,
+        )
+    }
+
+    /// Remove cipher suites from the builder's current set, leaving the rest in
+    /// their existing preference order. `suite_ids` must point to an array of
+    /// `len` IANA cipher suite identifiers, as returned by
+    /// `rustls_supported_ciphersuite_get_suite`. Identifiers that are not in the
+    /// current set are ignored. Returns `RUSTLS_RESULT_INVALID_PARAMETER`, and
+    /// leaves the builder unchanged, if this would leave no cipher suites usable
+    /// with the builder's protocol versions.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_disable_cipher_suites(
+        builder: *mut rustls_client_config_builder,
+        suite_ids: *const u16,
+        len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            let suite_ids: &[u16] = try_slice!(suite_ids, len);
+            let cipher_suites: Vec<SupportedCipherSuite> = config
+                .cipher_suites
+                .iter()
+                .filter(|cs| !suite_ids.contains(&cs.suite().get_u16()))
+                .copied()
+                .collect();
+            let versions = config.versions.clone();
+            match config.set_protocol_params(cipher_suites, versions) {
+                Ok(()) => rustls_result::Ok,
+                Err(e) => e,
+            }
+        }
+    }
+
+    /// Configure the builder to resemble a web browser's ClientHello, for
+    /// testing and automation against servers that fingerprint TLS clients.
+    /// `profile` is a `rustls_browser_profile`. This sets the cipher suite
+    /// order, the protocol versions and the ALPN protocol list, replacing any
+    /// previous settings for them.
+    ///
+    /// The result is only an approximation. Browsers offer cipher suites,
+    /// extensions, key exchange groups and GREASE values that rustls doesn't
+    /// support or doesn't let callers control, and rustls picks its own
+    /// extension order. Fingerprints that hash the whole ClientHello, like
+    /// JA3, will still differ from the real browser.
+    ///
+    /// Returns RUSTLS_RESULT_INVALID_PARAMETER for an unknown `profile`.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_apply_fingerprint_profile(
+        builder: *mut rustls_client_config_builder,
+        profile: u32,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            let cipher_suites: &[SupportedCipherSuite] = match profile {
+                p if p == rustls_browser_profile::Chrome as u32 => &[
+                    cipher_suite::TLS13_AES_128_GCM_SHA256,
+                    cipher_suite::TLS13_AES_256_GCM_SHA384,
+                    cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                ],
+                p if p == rustls_browser_profile::Firefox as u32 => &[
+                    cipher_suite::TLS13_AES_128_GCM_SHA256,
+                    cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+                    cipher_suite::TLS13_AES_256_GCM_SHA384,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+                    cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                    cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                ],
+                _ => return InvalidParameter,
+            };
+            let versions = vec![&rustls::version::TLS13, &rustls::version::TLS12];
+            if let Err(e) = config.set_protocol_params(cipher_suites.to_vec(), versions) {
+                return e;
+            }
+            config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            rustls_result::Ok
+        }
+    }
+
+    /// Replace the default in-memory session cache with one that evicts
+    /// according to `policy`, one of the `rustls_session_eviction_policy`
+    /// values. Like the default, the cache holds up to 256 entries. With LRU,
+    /// looking up a session for resumption counts as a use; with FIFO, only
+    /// storing one does.
+    ///
+    /// `max_age_secs` is a hint for how long a cached entry stays usable: an
+    /// older entry is dropped instead of being offered for resumption. Pass 0
+    /// to keep entries until they are evicted. The server's ticket lifetime
+    /// still applies on top of this.
+    ///
+    /// Returns RUSTLS_RESULT_INVALID_PARAMETER for an unknown `policy`.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_session_eviction(
+        builder: *mut rustls_client_config_builder,
+        policy: u32,
+        max_age_secs: u64,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            let lru = match policy {
+                p if p == rustls_session_eviction_policy::Lru as u32 => true,
+                p if p == rustls_session_eviction_policy::Fifo as u32 => false,
+                _ => return InvalidParameter,
+            };
+            let max_age = match max_age_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            };
+            config.session_storage = Some(Arc::new(ClientSessionCache::new(256, lru, max_age)));
+            rustls_result::Ok
+        }
+    }
+
+    /// Register callbacks for persistence of TLS sessions, so that sessions
+    /// can be kept outside the process, e.g. to resume across restarts. The
+    /// keys identify the server a session is for, and the values are the
+    /// encoded sessions, both opaque byte strings. Values are highly
+    /// sensitive data, containing enough information to break the security
+    /// of the connections involved.
+    ///
+    /// This replaces the in-memory cache, including one chosen with
+    /// rustls_client_config_builder_set_session_eviction. Sessions added with
+    /// rustls_client_config_builder_preload_session_cache are passed to the
+    /// put callback, with NULL userdata, when the config is built.
+    ///
+    /// rustls looks up the session to resume while creating a connection,
+    /// before rustls_connection_set_userdata can be called, so the get callback
+    /// is passed the userdata given to rustls_client_connection_new_full, or
+    /// NULL for the other constructors. Later calls, including the put
+    /// callback for sessions the server sends, are passed the userdata set with
+    /// rustls_connection_set_userdata, or NULL if none was set.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_persistence(
+        builder: *mut rustls_client_config_builder,
+        get_cb: rustls_session_store_get_callback,
+        put_cb: rustls_session_store_put_callback,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let get_cb: SessionStoreGetCallback = match get_cb {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let put_cb: SessionStorePutCallback = match put_cb {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            config.session_storage = Some(Arc::new(SessionStoreBroker::new(get_cb, put_cb)));
+            rustls_result::Ok
+        }
+    }
+
+    /// Add previously saved sessions to the client's session cache, so that the
+    /// first connection to each of those servers can resume instead of doing a
+    /// full handshake. `entries` must point to an array of `len`
+    /// `rustls_session_cache_entry`. Each session must be one that was passed
+    /// to a `rustls_new_ticket_callback` for a connection to the same server
+    /// name; the encoding is internal to rustls and sessions saved by a
+    /// different rustls-ffi version may be ignored. A session the server no
+    /// longer accepts just means a full handshake.
+    ///
+    /// The sessions are added when the config is built, to the cache chosen
+    /// with rustls_client_config_builder_set_session_eviction if there is one,
+    /// and the cache's size limit applies to them. A later entry for the same
+    /// server name replaces an earlier one. This copies the data in `entries`
+    /// and does not retain any pointers.
+    ///
+    /// Returns RUSTLS_RESULT_INVALID_DNS_NAME_ERROR, and adds none of the
+    /// entries, if any server name is not a valid DNS name.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_preload_session_cache(
+        builder: *mut rustls_client_config_builder,
+        entries: *const rustls_session_cache_entry,
+        len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            if entries.is_null() && len > 0 {
+                return NullParameter;
+            }
+            let entries: &[rustls_session_cache_entry] = try_slice!(entries, len);
+            let mut sessions = Vec::with_capacity(entries.len());
+            for entry in entries {
+                if entry.server_name.is_null() || (entry.session.data.is_null() && entry.session.len > 0) {
+                    return NullParameter;
+                }
+                let server_name: &CStr = unsafe { CStr::from_ptr(entry.server_name) };
+                let key = match server_name.to_str().ok().and_then(client_session_key) {
+                    Some(key) => key,
+                    None => return rustls_result::InvalidDnsNameError,
+                };
+                let session: &[u8] = try_slice!(entry.session.data, entry.session.len);
+                sessions.push((key, session.to_vec()));
+            }
+            config.preloaded_sessions.extend(sessions);
+            rustls_result::Ok
+        }
+    }
+
+    /// Enable or disable SNI.
+    /// <https://docs.rs/rustls/0.20.0/rustls/struct.ClientConfig.html#structfield.enable_sni>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_enable_sni(
+        config: *mut rustls_client_config_builder,
+        enable: bool,
+    ) {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
+            config.enable_sni = enable;
+        }
+    }
+
+    /// Enable or disable sending TLS 1.3 early data (0-RTT) with
+    /// rustls_connection_write_early_data. The default is disabled. Early data
+    /// can only be sent when resuming a TLS 1.3 session from a server that
+    /// allows it, and it can be replayed by an attacker, so only send requests
+    /// that are safe to process more than once.
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.enable_early_data>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_enable_early_data(
+        config: *mut rustls_client_config_builder,
+        enable: bool,
+    ) {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
+            config.enable_early_data = enable;
+        }
+    }
+
+    /// Set the maximum size of the TLS records this client sends, including
+    /// the 5-byte record header, so that each record fits the buffers of a
+    /// memory-constrained peer. `size` must be between 32 and 16389 (the
+    /// largest TLS record); otherwise this returns
+    /// RUSTLS_RESULT_INVALID_PARAMETER and leaves the setting unchanged. A
+    /// `size` of 0 restores the default, which is the largest record size.
+    /// This does not negotiate the max_fragment_length extension, so it
+    /// doesn't limit the records the server sends.
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.max_fragment_size>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_max_fragment_size(
+        config: *mut rustls_client_config_builder,
+        size: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(config);
+            config.max_fragment_size = match size {
+                0 => None,
+                32..=16389 => Some(size),
+                _ => return InvalidParameter,
+            };
+            rustls_result::Ok
+        }
+    }
+
+    /// Register a callback that receives the TLS secrets of each connection
+    /// made with this config, in the NSS key log format that tools like
+    /// Wireshark use to decrypt captured traffic. Anyone with these secrets
+    /// can decrypt the connection, so only enable this for debugging.
+    ///
+    /// If `userdata` has been set with rustls_connection_set_userdata, it
+    /// will be passed to the callback. Otherwise the userdata param passed to
+    /// the callback will be NULL.
+    ///
+    /// The callback must be safe to call on any thread at any time, including
+    /// multiple concurrent calls. So, for instance, if the callback mutates
+    /// userdata (or other shared state), it must use synchronization primitives
+    /// to make such mutation safe.
+    ///
+    /// This replaces any key log set earlier, including one installed by
+    /// rustls_client_config_builder_enable_sslkeylogfile.
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.key_log>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_key_log_callback(
+        builder: *mut rustls_client_config_builder,
+        callback: rustls_key_log_callback,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let callback: KeyLogCallback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            config.key_log = Some(Arc::new(KeyLogger { callback }));
+            rustls_result::Ok
+        }
+    }
+
+    /// Log the TLS secrets of each connection made with this config to the
+    /// file named by the SSLKEYLOGFILE environment variable, in the NSS key
+    /// log format. The variable is read and the file opened (for appending)
+    /// during this call; if it isn't set, nothing is logged. As with
+    /// rustls_client_config_builder_set_key_log_callback, this is only meant
+    /// for debugging.
+    ///
+    /// This replaces any key log set earlier.
+    /// <https://docs.rs/rustls/0.20.0/rustls/struct.KeyLogFile.html>
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_enable_sslkeylogfile(
+        builder: *mut rustls_client_config_builder,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            config.key_log = Some(Arc::new(rustls::KeyLogFile::new()));
+            rustls_result::Ok
+        }
+    }
+
+    /// Provide the configuration a list of certificates where the connection
+    /// will select the first one that is compatible with the server's signature
+    /// verification capabilities. Clients that want to support both ECDSA and
+    /// RSA certificates will want the ECSDA to go first in the list.
+    ///
+    /// The built configuration will keep a reference to all certified keys
+    /// provided. The client may `rustls_certified_key_free()` afterwards
+    /// without the configuration losing them. The same certified key may also
+    /// be used in multiple configs.
+    ///
+    /// EXPERIMENTAL: installing a client authentication callback will replace any
+    /// configured certified keys and vice versa.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_certified_key(
+        builder: *mut rustls_client_config_builder,
+        certified_keys: *const *const rustls_certified_key,
+        certified_keys_len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            let keys_ptrs: &[*const rustls_certified_key] = try_slice!(certified_keys, certified_keys_len);
+            let mut keys: Vec<Arc<CertifiedKey>> = Vec::new();
+            for &key_ptr in keys_ptrs {
+                let certified_key: Arc<CertifiedKey> = try_arc_from_ptr!(key_ptr);
+                keys.push(certified_key);
+            }
+            config.cert_resolver = Some(Arc::new(ResolvesClientCertFromChoices { keys }));
+            rustls_result::Ok
+        }
+    }
+
+    /// Register a callback that chooses the client certificate to send when a
+    /// server requests client authentication. The callback receives the DER
+    /// encoded distinguished names of the CAs the server accepts (possibly
+    /// none) and the signature schemes it supports, and returns the
+    /// `rustls_certified_key` to use, or NULL to continue without a client
+    /// certificate. `rustls_certified_key_free` may be called on the returned
+    /// key afterwards; the connection keeps its own reference.
+    ///
+    /// If `userdata` has been set with rustls_connection_set_userdata, it
+    /// will be passed to the callback. Otherwise the userdata param passed to
+    /// the callback will be NULL. The issuers and signature schemes are only
+    /// valid for the duration of the callback.
+    ///
+    /// The callback must be safe to call on any thread at any time, including
+    /// multiple concurrent calls. So, for instance, if the callback mutates
+    /// userdata (or other shared state), it must use synchronization primitives
+    /// to make such mutation safe.
+    ///
+    /// EXPERIMENTAL: installing a client authentication callback will replace any
+    /// configured certified keys and vice versa. It is not permitted to pass a
+    /// NULL value for `callback`.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_set_client_cert_resolver(
+        builder: *mut rustls_client_config_builder,
+        callback: rustls_client_cert_resolver_callback,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let callback: ClientCertResolverCallback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let config: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            config.cert_resolver = Some(Arc::new(ClientCertResolver { callback }));
+            rustls_result::Ok
+        }
+    }
+}
+
+/// Always send the same client certificate.
+struct ResolvesClientCertFromChoices {
+    keys: Vec<Arc<CertifiedKey>>,
+}
+
+impl ResolvesClientCert for ResolvesClientCertFromChoices {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        sig_schemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        for key in self.keys.iter() {
+            if key.key.choose_scheme(sig_schemes).is_some() {
+                return Some(key.clone());
+            }
+        }
+        None
+    }
+
+    fn has_certs(&self) -> bool {
+        !self.keys.is_empty()
+    }
+}
+
+/// A callback for rustls_client_config_builder_set_client_cert_resolver.
+/// `acceptable_issuers` holds the DER encoded distinguished names from the
+/// server's CertificateRequest, and `signature_schemes` the TLS signature
+/// schemes it supports. Return the certified key to authenticate with, or
+/// NULL to send no certificate.
+#[allow(non_camel_case_types)]
+pub type rustls_client_cert_resolver_callback = Option<
+    unsafe extern "C" fn(
+        userdata: *mut c_void,
+        acceptable_issuers: *const rustls_slice_slice_bytes,
+        signature_schemes: rustls_slice_u16,
+    ) -> *const rustls_certified_key,
+>;
+
+// This is the same as a rustls_client_cert_resolver_callback after unwrapping
+// the Option (which is equivalent to checking for null).
+type ClientCertResolverCallback = unsafe extern "C" fn(
+    userdata: *mut c_void,
+    acceptable_issuers: *const rustls_slice_slice_bytes,
+    signature_schemes: rustls_slice_u16,
+) -> *const rustls_certified_key;
+
+// An implementation of rustls::client::ResolvesClientCert based on a C callback.
+struct ClientCertResolver {
+    callback: ClientCertResolverCallback,
+}
+
+/// Safety: ClientCertResolver is Send because we don't allocate or deallocate
+/// any of its fields.
+unsafe impl Send for ClientCertResolver {}
+/// Safety: ClientCertResolver is Sync if the C code that passes us a callback
+/// obeys the concurrency safety requirements documented in
+/// rustls_client_config_builder_set_client_cert_resolver.
+unsafe impl Sync for ClientCertResolver {}
+
+impl ResolvesClientCert for ClientCertResolver {
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sig_schemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        let issuers = rustls_slice_slice_bytes {
+            inner: acceptable_issuers,
+        };
+        let schemes: Vec<u16> = sig_schemes.iter().map(|s| s.get_u16()).collect();
+        let userdata = userdata_get().ok()?;
+        let cb = self.callback;
+        let key_ptr: *const rustls_certified_key =
+            unsafe { cb(userdata, &issuers, (&*schemes).into()) };
+        let certified_key: Arc<CertifiedKey> = try_arc_from_ptr!(key_ptr);
+        Some(certified_key)
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+thread_local! {
+    // Set by IssuerRecordingResolver when a server asks for a client
+    // certificate. rustls doesn't keep the CA names from the
+    // CertificateRequest, so rustls_connection_process_new_packets moves them
+    // into the connection.
+    static ACCEPTABLE_ISSUERS: RefCell<Option<Vec<Vec<u8>>>> = RefCell::new(None);
+    // Set by VerifyRecordingVerifier when a server certificate is verified.
+    // rustls skips verification when resuming, so this tells
+    // rustls_connection_is_resumed that the handshake was a full one.
+    static SERVER_CERT_VERIFIED: Cell<bool> = Cell::new(false);
+    // Set by VerifyRecordingVerifier to the OCSP response the server stapled.
+    // rustls drops it after verification, so
+    // rustls_connection_process_new_packets moves it into the connection.
+    static STAPLED_OCSP: RefCell<Option<Vec<u8>>> = RefCell::new(None);
+}
+
+/// Return the CA names from a CertificateRequest processed on this thread
+/// since the last call, if any, and clear them.
+pub(crate) fn take_acceptable_issuers() -> Option<Vec<Vec<u8>>> {
+    ACCEPTABLE_ISSUERS.with(|i| i.borrow_mut().take())
+}
+
+/// Return whether a server certificate was verified on this thread since the
+/// last call, and reset the flag.
+pub(crate) fn take_server_cert_verified() -> bool {
+    SERVER_CERT_VERIFIED.with(|v| v.replace(false))
+}
+
+/// Return the stapled OCSP response from a server certificate verified on
+/// this thread since the last call, if any, and clear it.
+pub(crate) fn take_stapled_ocsp() -> Option<Vec<u8>> {
+    STAPLED_OCSP.with(|o| o.borrow_mut().take())
+}
+
+/// Wraps the configured server certificate verifier to record that it was
+/// called, and the OCSP response it was given. Installed by
+/// rustls_client_config_builder_build.
+struct VerifyRecordingVerifier {
+    inner: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for VerifyRecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &rustls::ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        SERVER_CERT_VERIFIED.with(|v| v.set(true));
+        STAPLED_OCSP.with(|o| *o.borrow_mut() = Some(ocsp_response.to_vec()));
+        self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+
+    fn request_scts(&self) -> bool {
+        self.inner.request_scts()
+    }
+}
+
+/// Wraps the configured client certificate resolver, if any, to record the
+/// CA names the server sent. Installed by rustls_client_config_builder_build.
+struct IssuerRecordingResolver {
+    inner: Option<Arc<dyn ResolvesClientCert>>,
+}
+
+impl ResolvesClientCert for IssuerRecordingResolver {
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sig_schemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<rustls::sign::CertifiedKey>> {
+        let names = acceptable_issuers.iter().map(|i| i.to_vec()).collect();
+        ACCEPTABLE_ISSUERS.with(|i| *i.borrow_mut() = Some(names));
+        match &self.inner {
+            Some(inner) => inner.resolve(acceptable_issuers, sig_schemes),
+            None => None,
+        }
+    }
+
+    fn has_certs(&self) -> bool {
+        matches!(&self.inner, Some(inner) if inner.has_certs())
+    }
+}
+
+/// A callback for rustls_client_config_builder_validate. It is called once
+/// for each problem found, with a human-readable description of the problem.
+/// The `message` is only valid for the duration of the callback.
+#[allow(non_camel_case_types)]
+pub type rustls_validate_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, message: rustls_str)>;
+
+impl ClientConfigBuilder {
+    /// Assemble the verifier for the built config from the default verifier,
+    /// the per-host verifiers and the pin, if any.
+    fn take_verifier(&mut self) -> Arc<dyn ServerCertVerifier> {
+        let mut verifier = self
+            .verifier
+            .take()
+            .unwrap_or_else(|| Arc::new(NoneVerifier));
+        if !self.per_host_verifiers.is_empty() {
+            verifier = Arc::new(PerHostVerifier {
+                hosts: std::mem::take(&mut self.per_host_verifiers),
+                default: verifier,
+            });
+        }
+        if let Some(sha256) = self.pinned_sha256.take() {
+            verifier = Arc::new(PinnedVerifier {
+                inner: verifier,
+                sha256,
+            });
+        }
+        verifier
+    }
+
+    /// Collect a description of every problem with the current builder state.
+    fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.verifier.is_none() && self.per_host_verifiers.is_empty() {
+            problems.push(
+                "no certificate verifier configured; all server certificates will be rejected"
+                    .to_string(),
+            );
+        } else if self.verifier.is_none() {
+            problems.push(
+                "no default certificate verifier configured; server certificates for hosts \
+                 without a per-host verifier will be rejected"
+                    .to_string(),
+            );
+        }
+        for (i, p) in self.alpn_protocols.iter().enumerate() {
+            if p.is_empty() {
+                problems.push(format!("ALPN protocol at index {} is empty", i));
+            } else if p.len() > 255 {
+                problems.push(format!(
+                    "ALPN protocol at index {} is longer than 255 bytes",
+                    i
+                ));
+            }
+        }
+        problems
+    }
+}
+
+impl rustls_client_config_builder {
+    /// Check the builder for configuration problems without consuming it.
+    /// If `callback` is non-NULL, it is called once for each problem found,
+    /// with `userdata` and a description of the problem. Returns
+    /// RUSTLS_RESULT_OK if no problems were found, and
+    /// RUSTLS_RESULT_INVALID_PARAMETER otherwise.
+    ///
+    /// Currently this detects a missing certificate verifier and ALPN
+    /// protocol IDs that are empty or longer than 255 bytes. Incompatible
+    /// combinations of cipher suites and protocol versions are already
+    /// rejected by rustls_client_config_builder_new_custom.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_validate(
+        builder: *const rustls_client_config_builder,
+        callback: rustls_validate_callback,
+        userdata: *mut c_void,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let builder: &ClientConfigBuilder = try_ref_from_ptr!(builder);
+            let problems = builder.problems();
+            if let Some(cb) = callback {
+                for p in problems.iter() {
+                    let message: rustls_str = match p.as_str().try_into() {
+                        Ok(m) => m,
+                        Err(NulByte {}) => continue,
+                    };
+                    unsafe { cb(userdata, message) };
+                }
+            }
+            if problems.is_empty() {
+                rustls_result::Ok
+            } else {
+                rustls_result::InvalidParameter
+            }
+        }
+    }
+}
+
+impl rustls_client_config_builder {
+    /// Turn a *rustls_client_config_builder (mutable) into a const *rustls_client_config
+    /// (read-only).
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_build(
+        builder: *mut rustls_client_config_builder,
+    ) -> *const rustls_client_config {
+        ffi_panic_boundary! {
+            let mut builder: Box<ClientConfigBuilder> = try_box_from_ptr!(builder);
+            let verifier = Arc::new(VerifyRecordingVerifier {
+                inner: builder.take_verifier(),
+            });
+            let config = builder.base.with_custom_certificate_verifier(verifier);
+            let mut config = config.with_client_cert_resolver(Arc::new(IssuerRecordingResolver {
+                inner: builder.cert_resolver,
+            }));
+            config.alpn_protocols = builder.alpn_protocols;
+            config.enable_sni = builder.enable_sni;
+            config.enable_early_data = builder.enable_early_data;
+            config.max_fragment_size = builder.max_fragment_size;
+            if let Some(key_log) = builder.key_log {
+                config.key_log = key_log;
+            }
+            let session_storage = builder.session_storage.unwrap_or(config.session_storage);
+            if !builder.preloaded_sessions.is_empty() {
+                // Persistence callbacks need a userdata entry to run.
+                let guard = match userdata_push(null_mut(), None, None, null_mut()) {
+                    Ok(g) => g,
+                    Err(_) => return null(),
+                };
+                for (key, value) in builder.preloaded_sessions {
+                    session_storage.put(key, value);
+                }
+                if guard.try_drop().is_err() {
+                    return null();
+                }
+            }
+            config.session_storage = Arc::new(NewTicketNotifier {
+                inner: session_storage,
+            });
+            ArcCastPtr::to_const_ptr(config)
+        }
+    }
+
+    /// "Free" a client_config_builder without building it into a rustls_client_config.
+    /// Normally builders are built into rustls_client_config via `rustls_client_config_builder_build`
+    /// and may not be free'd or otherwise used afterwards.
+    /// Use free only when the building of a config has to be aborted before a config
+    /// was created.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_builder_free(config: *mut rustls_client_config_builder) {
+        ffi_panic_boundary! {
+            BoxCastPtr::to_box(config);
+        }
+    }
+}
+
+impl rustls_client_config {
+    /// "Free" a rustls_client_config previously returned from
+    /// rustls_client_config_builder_build. Since rustls_client_config is actually an
+    /// atomically reference-counted pointer, extant client connections may still
+    /// hold an internal reference to the Rust object. However, C code must
+    /// consider this pointer unusable after "free"ing it.
+    /// Calling with NULL is fine. Must not be called twice with the same value.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_config_free(config: *const rustls_client_config) {
+        ffi_panic_boundary! {
+            let config: &ClientConfig = try_ref_from_ptr!(config);
+            // To free the rustls_client_config, we reconstruct the Arc and then drop it. It should
+            // have a refcount of 1, representing the C code's copy. When it drops, that
+            // refcount will go down to 0 and the inner ClientConfig will be dropped.
+            unsafe { drop(Arc::from_raw(config)) };
+        }
+    }
+
+    /// Create a new rustls_connection containing a client connection and return
+    /// it in the output parameter `out`. If this returns an error code, the
+    /// memory pointed to by `conn_out` remains unchanged. If this returns a
+    /// non-error, the memory pointed to by `conn_out` is modified to point at a
+    /// valid rustls_connection. The caller now owns the rustls_connection and must
+    /// call `rustls_connection_free` when done with it.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_connection_new(
+        config: *const rustls_client_config,
+        hostname: *const c_char,
+        conn_out: *mut *mut rustls_connection,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let enable_sni = try_ref_from_ptr!(config).enable_sni;
+            rustls_client_config::rustls_client_connection_new_with_options(
+                config, hostname, enable_sni, conn_out)
+        }
+    }
+
+    /// Create a new rustls_connection containing a client connection, as
+    /// rustls_client_connection_new does, but with SNI enabled or disabled for
+    /// this connection according to `enable_sni`, regardless of the setting
+    /// from rustls_client_config_builder_set_enable_sni. This lets one config
+    /// be shared between connections that do and don't send SNI, for instance
+    /// when some servers are reached through a raw IP alias. `config` is not
+    /// modified. Ownership of the connection is as for
+    /// rustls_client_connection_new.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_connection_new_with_options(
+        config: *const rustls_client_config,
+        hostname: *const c_char,
+        enable_sni: bool,
+        conn_out: *mut *mut rustls_connection,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let hostname: &CStr = unsafe {
+                if hostname.is_null() {
+                    return NullParameter;
+                }
+                CStr::from_ptr(hostname)
+            };
+            let mut config: Arc<ClientConfig> = try_arc_from_ptr!(config);
+            if config.enable_sni != enable_sni {
+                let mut with_sni: ClientConfig = (*config).clone();
+                with_sni.enable_sni = enable_sni;
+                config = Arc::new(with_sni);
+            }
+            let hostname: &str = match hostname.to_str() {
+                Ok(s) => s,
+                Err(std::str::Utf8Error { .. }) => return rustls_result::InvalidDnsNameError,
+            };
+            let server_name: rustls::ServerName = match hostname.try_into() {
+                Ok(sn) => sn,
+                Err(_) => return rustls_result::InvalidDnsNameError,
+            };
+            let session_storage = Arc::clone(&config.session_storage);
+            let client = match new_client_connection(config, server_name, null_mut()) {
+                Ok(client) => client,
+                Err(e) => return e,
+            };
+
+            // We've succeeded. Put the client on the heap, and transfer ownership
+            // to the caller. After this point, we must return CRUSTLS_OK so the
+            // caller knows it is responsible for this memory.
+            let mut c = Connection::from_client(client);
+            if let Some(key) = client_session_key(hostname) {
+                c.set_session_key(session_storage, key);
+            }
+            BoxCastPtr::set_mut_ptr(conn_out, c);
+            rustls_result::Ok
+        }
+    }
+
+    /// Create a new rustls_connection containing a client connection for a
+    /// server that has no hostname, such as the far end of a tunnel or a bare
+    /// IP address. The connection doesn't send SNI, and the server name seen
+    /// by the certificate verifier is the placeholder `example.invalid`.
+    ///
+    /// The config must use a custom certificate verifier (see
+    /// rustls_client_config_builder_dangerous_set_certificate_verifier) that
+    /// authenticates the server some other way. With a WebPKI verifier the
+    /// handshake will fail, since no valid certificate can match the
+    /// placeholder name. Ownership of the connection is as for
+    /// rustls_client_connection_new.
+    ///
+    /// Anonymous connections never store or resume sessions. They would all
+    /// share the placeholder name, so one endpoint could otherwise resume a
+    /// session set up with another, skipping the verifier that is the only
+    /// check of which endpoint this is. The config's session store and new
+    /// ticket callback are therefore not used.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_connection_new_anonymous(
+        config: *const rustls_client_config,
+        conn_out: *mut *mut rustls_connection,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            if conn_out.is_null() {
+                return NullParameter;
+            }
+            let config: Arc<ClientConfig> = try_arc_from_ptr!(config);
+            let mut anonymous: ClientConfig = (*config).clone();
+            anonymous.enable_sni = false;
+            anonymous.session_storage = Arc::new(rustls::client::NoClientSessionStorage {});
+            let server_name: rustls::ServerName = match "example.invalid".try_into() {
+                Ok(sn) => sn,
+                Err(_) => return rustls_result::InvalidDnsNameError,
+            };
+            let client = match new_client_connection(Arc::new(anonymous), server_name, null_mut()) {
+                Ok(client) => client,
+                Err(e) => return e,
+            };
+            BoxCastPtr::set_mut_ptr(conn_out, Connection::from_client(client));
+            rustls_result::Ok
+        }
+    }
+
+    /// Create a new rustls_connection containing a client connection, as
+    /// rustls_client_connection_new does, with its userdata and ALPN
+    /// protocols set before it is handed to the caller. This saves separate
+    /// configuration calls between creating a connection and first using it.
+    ///
+    /// `server_name` is the NUL-terminated hostname to connect to and must not
+    /// be NULL. `userdata` is set as by rustls_connection_set_userdata; NULL
+    /// leaves it unset, which is the same as the default. If `alpn_override`
+    /// is NULL, the ALPN protocols from `config` are offered. Otherwise the
+    /// `alpn_len` protocols it points to are offered instead, as for
+    /// rustls_client_config_builder_set_alpn_protocols, and `config` is not
+    /// modified; an empty list disables ALPN for this connection. As there,
+    /// each protocol must be 1 to 255 bytes long, or
+    /// RUSTLS_RESULT_INVALID_PARAMETER is returned. The protocols are copied,
+    /// so the caller can free them afterwards.
+    /// Ownership of the connection is as for rustls_client_connection_new.
+    #[no_mangle]
+    pub extern "C" fn rustls_client_connection_new_full(
+        config: *const rustls_client_config,
+        server_name: *const c_char,
+        userdata: *mut c_void,
+        alpn_override: *const rustls_slice_bytes,
+        alpn_len: size_t,
+        conn_out: *mut *mut rustls_connection,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            if conn_out.is_null() {
+                return NullParameter;
+            }
+            let server_name: &CStr = unsafe {
+                if server_name.is_null() {
+                    return NullParameter;
+                }
+                CStr::from_ptr(server_name)
+            };
+            let mut config: Arc<ClientConfig> = try_arc_from_ptr!(config);
+            let server_name: &str = match server_name.to_str() {
+                Ok(s) => s,
+                Err(std::str::Utf8Error { .. }) => return rustls_result::InvalidDnsNameError,
+            };
+            let session_key = client_session_key(server_name);
+            let server_name: rustls::ServerName = match server_name.try_into() {
+                Ok(sn) => sn,
+                Err(_) => return rustls_result::InvalidDnsNameError,
+            };
+            if !alpn_override.is_null() {
+                let protocols: &[rustls_slice_bytes] = try_slice!(alpn_override, alpn_len);
+                let mut alpn_protocols: Vec<Vec<u8>> = Vec::with_capacity(protocols.len());
+                for p in protocols {
+                    if p.len == 0 || p.len > 255 {
+                        return InvalidParameter;
+                    }
+                    let v: &[u8] = try_slice!(p.data, p.len);
+                    alpn_protocols.push(v.to_vec());
+                }
+                let mut with_alpn: ClientConfig = (*config).clone();
+                with_alpn.alpn_protocols = alpn_protocols;
+                config = Arc::new(with_alpn);
+            }
+            let session_storage = Arc::clone(&config.session_storage);
+            let client = match new_client_connection(config, server_name, userdata) {
+                Ok(client) => client,
+                Err(e) => return e,
+            };
+            let mut c = Connection::from_client(client);
+            c.set_userdata(userdata);
+            if let Some(key) = session_key {
+                c.set_session_key(session_storage, key);
+            }
+            BoxCastPtr::set_mut_ptr(conn_out, c);
+            rustls_result::Ok
+        }
+    }
+}
+
+/// Create a rustls ClientConnection with `userdata` available to callbacks.
+/// rustls looks up a session to resume while building the ClientHello, so
+/// this can call the get callback set with
+/// rustls_client_config_builder_set_persistence.
+fn new_client_connection(
+    config: Arc<ClientConfig>,
+    server_name: rustls::ServerName,
+    userdata: *mut c_void,
+) -> Result<ClientConnection, rustls_result> {
+    let guard = match userdata_push(userdata, None, None, null_mut()) {
+        Ok(g) => g,
+        Err(_) => return Err(rustls_result::Panic),
+    };
+    let result = ClientConnection::new(config, server_name).map_err(error::map_error);
+    match guard.try_drop() {
+        Ok(()) => result,
+        Err(_) => Err(rustls_result::Panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::{null, null_mut};
+
+    use rustls::client::StoresClientSessions;
+
+    use super::*;
+
+    #[test]
+    fn test_config_builder() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let h1 = "http/1.1".as_bytes();
+        let h2 = "h2".as_bytes();
+        let alpn: Vec<rustls_slice_bytes> = vec![h1.into(), h2.into()];
+        rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+            builder,
+            alpn.as_ptr(),
+            alpn.len(),
+        );
+        rustls_client_config_builder::rustls_client_config_builder_set_enable_sni(builder, false);
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        {
+            let config2 = try_ref_from_ptr!(config);
+            assert_eq!(config2.enable_sni, false);
+            assert_eq!(config2.alpn_protocols, vec![h1, h2]);
+        }
+        rustls_client_config::rustls_client_config_free(config)
+    }
+
+    #[test]
+    fn test_alpn_protocols_preference_order() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let protocols: Vec<&[u8]> = vec![b"h2", b"spdy/3", b"http/1.1"];
+        let alpn: Vec<rustls_slice_bytes> = protocols.iter().map(|p| (*p).into()).collect();
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols_with_fallback_order(
+            builder,
+            alpn.as_ptr(),
+            alpn.len(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        {
+            let config2 = try_ref_from_ptr!(config);
+            assert_eq!(config2.alpn_protocols, protocols);
+        }
+        rustls_client_config::rustls_client_config_free(config)
+    }
+
+    #[test]
+    fn test_alpn_protocols_invalid_length() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let empty: &[u8] = &[];
+        let too_long: &[u8] = &[b'a'; 256];
+        let longest: &[u8] = &[b'a'; 255];
+        for &bad in &[empty, too_long] {
+            let alpn: Vec<rustls_slice_bytes> = vec!["h2".as_bytes().into(), bad.into()];
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+                    builder,
+                    alpn.as_ptr(),
+                    alpn.len(),
+                );
+            assert!(matches!(result, rustls_result::InvalidParameter));
+            assert!(try_ref_from_ptr!(builder).alpn_protocols.is_empty());
+        }
+
+        let alpn: Vec<rustls_slice_bytes> = vec!["h2".as_bytes().into(), longest.into()];
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+            builder,
+            alpn.as_ptr(),
+            alpn.len(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            try_ref_from_ptr!(builder).alpn_protocols,
+            vec![b"h2".to_vec(), longest.to_vec()]
+        );
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_alpn_protocols_binary_round_trip() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let long = vec![0x42u8; 255];
+        let protocols: Vec<&[u8]> = vec![
+            b"\0",
+            b"a\0b",
+            &[0xff, 0xfe, 0x80],
+            "caf\u{e9}/1".as_bytes(),
+            &long,
+            b"h2",
+        ];
+        let alpn: Vec<rustls_slice_bytes> = protocols.iter().map(|p| (*p).into()).collect();
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+            builder,
+            alpn.as_ptr(),
+            alpn.len(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        {
+            let config2 = try_ref_from_ptr!(config);
+            assert_eq!(config2.alpn_protocols, protocols);
+        }
+        rustls_client_config::rustls_client_config_free(config)
+    }
+
+    #[test]
+    fn test_config_builder_disable_cipher_suites() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        // TLS13_CHACHA20_POLY1305_SHA256, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256
+        // and an ID that isn't in the default set.
+        let disabled: [u16; 3] = [0x1303, 0xcca8, 0x0005];
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_disable_cipher_suites(
+                builder,
+                disabled.as_ptr(),
+                disabled.len(),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+
+        // Removing every remaining suite is refused and leaves the builder as it was.
+        let all: Vec<u16> = ALL_CIPHER_SUITES
+            .iter()
+            .map(|cs| cs.suite().get_u16())
+            .collect();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_disable_cipher_suites(
+                builder,
+                all.as_ptr(),
+                all.len(),
+            );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+
+        {
+            let builder2: &ClientConfigBuilder = try_ref_from_ptr!(builder);
+            let expected: Vec<SupportedCipherSuite> = DEFAULT_CIPHER_SUITES
+                .iter()
+                .filter(|cs| !disabled.contains(&cs.suite().get_u16()))
+                .copied()
+                .collect();
+            assert_eq!(builder2.cipher_suites, expected);
+            assert_eq!(
+                builder2.cipher_suites.len(),
+                DEFAULT_CIPHER_SUITES.len() - 2
+            );
+        }
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        rustls_client_config::rustls_client_config_free(config)
+    }
+
+    unsafe extern "C" fn skip_bad_root(
+        userdata: *mut c_void,
+        cert: rustls_slice_bytes,
+        reason: rustls_str,
+    ) -> bool {
+        assert_eq!(slice::from_raw_parts(cert.data, cert.len), b"bad");
+        assert!(reason.len > 0);
+        *(userdata as *mut usize) += 1;
+        true
+    }
+
+    unsafe extern "C" fn stop_at_bad_root(
+        userdata: *mut c_void,
+        _cert: rustls_slice_bytes,
+        _reason: rustls_str,
+    ) -> bool {
+        *(userdata as *mut usize) += 1;
+        false
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_roots_from_file_with_callback() {
+        let path = std::env::temp_dir().join(format!(
+            "rustls-ffi-test-bad-roots-{}.pem",
+            std::process::id()
+        ));
+        let bundle = format!(
+            "{}-----BEGIN CERTIFICATE-----\nYmFk\n-----END CERTIFICATE-----\n{}",
+            include_str!("../minica.pem"),
+            include_str!("../localhost/cert.pem"),
+        );
+        std::fs::write(&path, bundle).unwrap();
+        let filename = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let mut bad: usize = 0;
+        let result = rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_with_callback(
+            builder,
+            filename.as_ptr(),
+            Some(stop_at_bad_root),
+            &mut bad as *mut usize as *mut c_void,
+        );
+        assert!(matches!(result, rustls_result::CertificateParseError));
+        assert_eq!(bad, 1);
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+
+        let mut bad: usize = 0;
+        let result = rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_with_callback(
+            builder,
+            filename.as_ptr(),
+            Some(skip_bad_root),
+            &mut bad as *mut usize as *mut c_void,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(bad, 1);
+        assert!(try_ref_from_ptr!(builder).verifier.is_some());
+
+        // Skipping every certificate, or finding none, leaves no roots to
+        // trust, which is an error rather than a verifier that rejects
+        // everything.
+        let only_bad = std::env::temp_dir().join(format!(
+            "rustls-ffi-test-only-bad-roots-{}.pem",
+            std::process::id()
+        ));
+        let only_bad_filename = std::ffi::CString::new(only_bad.to_str().unwrap()).unwrap();
+        rustls_client_config_builder::rustls_client_config_builder_reset_verifier(builder);
+        let mut load_only = |contents: &str| {
+            std::fs::write(&only_bad, contents).unwrap();
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_with_callback(
+                builder,
+                only_bad_filename.as_ptr(),
+                Some(skip_bad_root),
+                &mut bad as *mut usize as *mut c_void,
+            )
+        };
+        assert!(matches!(
+            load_only("-----BEGIN CERTIFICATE-----\nYmFk\n-----END CERTIFICATE-----\n"),
+            rustls_result::CertificateParseError
+        ));
+        assert!(matches!(load_only(""), rustls_result::Io));
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+        std::fs::remove_file(&only_bad).unwrap();
+
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file(
+                builder,
+                filename.as_ptr(),
+            );
+        assert!(matches!(result, rustls_result::CertificateParseError));
+
+        let mut first_failed: size_t = 0;
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_ex(
+                builder,
+                filename.as_ptr(),
+                &mut first_failed,
+            );
+        assert!(matches!(result, rustls_result::CertificateParseError));
+        assert_eq!(first_failed, 1);
+
+        let (mut added, mut failed): (size_t, size_t) = (0, 0);
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_lenient(
+                builder,
+                filename.as_ptr(),
+                &mut added,
+                &mut failed,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!((added, failed), (2, 1));
+
+        let good =
+            std::ffi::CString::new(concat!(env!("CARGO_MANIFEST_DIR"), "/minica.pem")).unwrap();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_file_ex(
+                builder,
+                good.as_ptr(),
+                &mut first_failed,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(first_failed, usize::MAX);
+
+        std::fs::remove_file(&path).unwrap();
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    // The test certificates are only valid until 2023, so tests verify them
+    // at this time, within their validity period.
+    fn localhost_valid_time() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_650_000_000)
+    }
+
+    fn localhost_cert() -> Certificate {
+        let mut pem = &include_bytes!("../localhost/cert.pem")[..];
+        Certificate(rustls_pemfile::certs(&mut pem).unwrap().remove(0))
+    }
+
+    // Verify the localhost certificate, presented by a server we connected to
+    // as `server_name`.
+    fn verify_localhost_at_valid_time(
+        verifier: &dyn ServerCertVerifier,
+        server_name: &str,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let server_name: rustls::ServerName = server_name.try_into().unwrap();
+        verifier.verify_server_cert(
+            &localhost_cert(),
+            &[],
+            &server_name,
+            &mut std::iter::empty(),
+            &[],
+            localhost_valid_time(),
+        )
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_roots_from_dir() {
+        let dir = std::env::temp_dir().join(format!(
+            "rustls-ffi-test-client-roots-dir-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dirname = std::ffi::CString::new(dir.to_str().unwrap()).unwrap();
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+
+        let result = rustls_client_config_builder::rustls_client_config_builder_load_roots_from_dir(
+            builder,
+            dirname.as_ptr(),
+        );
+        assert!(matches!(result, rustls_result::Io));
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+
+        std::fs::write(dir.join("notes.txt"), "not a certificate").unwrap();
+        std::fs::write(dir.join("bad.pem"), "not a certificate").unwrap();
+        std::fs::write(dir.join("minica.pem"), include_str!("../minica.pem")).unwrap();
+        std::fs::write(
+            dir.join("localhost.crt"),
+            include_str!("../localhost/cert.pem"),
+        )
+        .unwrap();
+        let result = rustls_client_config_builder::rustls_client_config_builder_load_roots_from_dir(
+            builder,
+            dirname.as_ptr(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            load_roots_from_dir(dir.to_str().unwrap())
+                .unwrap()
+                .roots
+                .len(),
+            2
+        );
+        let verifier = try_ref_from_ptr!(builder).verifier.clone().unwrap();
+        assert!(verify_localhost_at_valid_time(verifier.as_ref(), "localhost").is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        let result = rustls_client_config_builder::rustls_client_config_builder_load_roots_from_dir(
+            builder,
+            dirname.as_ptr(),
+        );
+        assert!(matches!(result, rustls_result::Io));
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_load_roots_from_buffer() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        for pem in &[&b""[..], &b"not a certificate"[..]] {
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_load_roots_from_buffer(
+                    builder,
+                    pem.as_ptr(),
+                    pem.len(),
+                );
+            assert!(matches!(result, rustls_result::Io));
+        }
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+
+        let pem = include_bytes!("../minica.pem");
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_load_roots_from_buffer(
+                builder,
+                pem.as_ptr(),
+                pem.len(),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let verifier = try_ref_from_ptr!(builder).verifier.clone().unwrap();
+        assert!(verify_localhost_at_valid_time(verifier.as_ref(), "localhost").is_ok());
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_use_roots_with_crls() {
+        let store = rustls_root_cert_store::rustls_root_cert_store_new();
+        let pem = include_bytes!("../minica.pem");
+        let result = rustls_root_cert_store::rustls_root_cert_store_add_pem(
+            store,
+            pem.as_ptr(),
+            pem.len(),
+            true,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let garbage: &[u8] = b"not a CRL";
+        let crls: Vec<rustls_slice_bytes> = vec![garbage.into()];
+        let result = rustls_client_config_builder::rustls_client_config_builder_use_roots_with_crls(
+            builder,
+            store,
+            crls.as_ptr(),
+            crls.len(),
+        );
+        assert!(matches!(result, rustls_result::CertificateParseError));
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+
+        let empty: &[u8] = include_bytes!("../localhost/empty.crl");
+        let revoked: &[u8] = include_bytes!("../localhost/revoked.crl");
+        // A CRL whose signature doesn't verify is rejected, as is one whose
+        // issuer isn't among the roots.
+        let mut forged = revoked.to_vec();
+        *forged.last_mut().unwrap() ^= 1;
+        let crls: Vec<rustls_slice_bytes> = vec![empty.into(), forged[..].into()];
+        let result = rustls_client_config_builder::rustls_client_config_builder_use_roots_with_crls(
+            builder,
+            store,
+            crls.as_ptr(),
+            crls.len(),
+        );
+        assert!(matches!(result, rustls_result::CertInvalidSignature));
+        let no_roots = rustls_root_cert_store::rustls_root_cert_store_new();
+        let result = rustls_client_config_builder::rustls_client_config_builder_use_roots_with_crls(
+            builder,
+            no_roots,
+            crls.as_ptr(),
+            1,
+        );
+        assert!(matches!(result, rustls_result::CertInvalidSignature));
+        rustls_root_cert_store::rustls_root_cert_store_free(no_roots);
+        assert!(try_ref_from_ptr!(builder).verifier.is_none());
+
+        for &(crl, expect_ok) in &[(empty, true), (revoked, false)] {
+            let crls: Vec<rustls_slice_bytes> = vec![crl.into()];
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_use_roots_with_crls(
+                    builder,
+                    store,
+                    crls.as_ptr(),
+                    crls.len(),
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let verifier = try_ref_from_ptr!(builder).verifier.clone().unwrap();
+            match verify_localhost_at_valid_time(verifier.as_ref(), "localhost") {
+                Ok(_) => assert!(expect_ok),
+                Err(e) => {
+                    assert!(!expect_ok);
+                    assert!(matches!(
+                        error::map_error(e),
+                        rustls_result::CertInvalidData
+                    ));
+                }
+            }
+        }
+
+        // Once the CRL is out of date, certificates from its issuer are
+        // rejected even though the CRL doesn't list them.
+        let roots: &RootStore = try_ref_from_ptr!(store);
+        let (issuer, crl) = TrustedCrl::new(empty, roots).unwrap();
+        let verifier = CrlVerifier {
+            inner: rustls::client::WebPkiVerifier::new(roots.roots.clone(), None),
+            crls: vec![(issuer, vec![crl])].into_iter().collect(),
+        };
+        let leaf = localhost_cert();
+        let now = localhost_valid_time();
+        assert!(verifier
+            .check_revocation(std::iter::once(&leaf), now)
+            .is_ok());
+        let after_next_update = SystemTime::UNIX_EPOCH + Duration::from_secs(4_945_656_888);
+        let result = verifier.check_revocation(std::iter::once(&leaf), after_next_update);
+        assert!(matches!(
+            error::map_error(result.unwrap_err()),
+            rustls_result::CertInvalidData
+        ));
+        let garbage = Certificate(b"not a certificate".to_vec());
+        let result = verifier.check_revocation(std::iter::once(&garbage), now);
+        assert!(matches!(
+            error::map_error(result.unwrap_err()),
+            rustls_result::CertInvalidEncoding
+        ));
+
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+        rustls_root_cert_store::rustls_root_cert_store_free(store);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_set_pinned_cert_sha256() {
+        let leaf_sha256 = digest::digest(&digest::SHA256, &localhost_cert().0);
+        let wrong_sha256 = [0u8; 32];
+        let pem = include_bytes!("../minica.pem");
+
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_pinned_cert_sha256(
+                builder,
+                wrong_sha256.as_ptr(),
+                31,
+            );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        assert!(try_ref_from_ptr!(builder).pinned_sha256.is_none());
+
+        for &(pin, expect_ok) in &[(leaf_sha256.as_ref(), true), (&wrong_sha256[..], false)] {
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_set_pinned_cert_sha256(
+                    builder,
+                    pin.as_ptr(),
+                    pin.len(),
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_load_roots_from_buffer(
+                    builder,
+                    pem.as_ptr(),
+                    pem.len(),
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let builder2: &mut ClientConfigBuilder = try_mut_from_ptr!(builder);
+            match verify_localhost_at_valid_time(builder2.take_verifier().as_ref(), "localhost") {
+                Ok(_) => assert!(expect_ok),
+                Err(e) => {
+                    assert!(!expect_ok);
+                    assert!(matches!(
+                        error::map_error(e),
+                        rustls_result::CertInvalidData
+                    ));
+                }
+            }
+        }
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_config_builder_apply_fingerprint_profile() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_apply_fingerprint_profile(
+                builder,
+                rustls_browser_profile::Firefox as u32,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        {
+            let builder2: &ClientConfigBuilder = try_ref_from_ptr!(builder);
+            assert_eq!(builder2.cipher_suites.len(), 9);
+            assert_eq!(builder2.cipher_suites[1].suite().get_u16(), 0x1303);
+            assert_eq!(builder2.versions.len(), 2);
+            assert_eq!(
+                builder2.alpn_protocols,
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            );
+        }
+
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_apply_fingerprint_profile(
+                builder,
+                rustls_browser_profile::Chrome as u32,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        {
+            let builder2: &ClientConfigBuilder = try_ref_from_ptr!(builder);
+            assert_eq!(builder2.cipher_suites[1].suite().get_u16(), 0x1302);
+        }
+
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_apply_fingerprint_profile(
+                builder, 0,
+            );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    fn test_config_builder_set_session_eviction() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_session_eviction(
+                builder, 0, 0,
+            );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_session_eviction(
+                builder,
+                rustls_session_eviction_policy::Fifo as u32,
+                3600,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        assert!(!config.is_null());
+        rustls_client_config::rustls_client_config_free(config);
+
+        for &lru in &[true, false] {
+            let cache = ClientSessionCache::new(2, lru, None);
+            assert!(cache.put(b"a".to_vec(), b"1".to_vec()));
+            assert!(cache.put(b"b".to_vec(), b"2".to_vec()));
+            assert_eq!(cache.get(b"a"), Some(b"1".to_vec()));
+            assert!(cache.put(b"c".to_vec(), b"3".to_vec()));
+            // LRU keeps "a" because it was just used; FIFO drops it as the oldest.
+            assert_eq!(cache.get(b"a").is_some(), lru);
+            assert_eq!(cache.get(b"b").is_some(), !lru);
+            assert_eq!(cache.get(b"c"), Some(b"3".to_vec()));
+        }
+
+        let cache = ClientSessionCache::new(2, true, Some(Duration::from_millis(1)));
+        assert!(cache.put(b"a".to_vec(), b"1".to_vec()));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.get(b"a"), None);
+    }
+
+    #[test]
+    fn test_config_builder_preload_session_cache() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let entries = [
+            rustls_session_cache_entry {
+                server_name: "Example.com\0".as_ptr() as *const c_char,
+                session: b"first"[..].into(),
+            },
+            rustls_session_cache_entry {
+                server_name: "example.net\0".as_ptr() as *const c_char,
+                session: b"second"[..].into(),
+            },
+        ];
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_preload_session_cache(
+                builder,
+                entries.as_ptr(),
+                entries.len(),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let bad = [rustls_session_cache_entry {
+            server_name: "not a name\0".as_ptr() as *const c_char,
+            session: b"third"[..].into(),
+        }];
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_preload_session_cache(
+                builder,
+                bad.as_ptr(),
+                bad.len(),
+            );
+        assert!(matches!(result, rustls_result::InvalidDnsNameError));
+
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        {
+            let config2: &ClientConfig = try_ref_from_ptr!(config);
+            let storage = &config2.session_storage;
+            assert_eq!(
+                storage.get(b"session\x01\x0bexample.com"),
+                Some(b"first".to_vec())
+            );
+            assert_eq!(
+                storage.get(b"session\x01\x0bexample.net"),
+                Some(b"second".to_vec())
+            );
+        }
+        rustls_client_config::rustls_client_config_free(config);
+    }
+
+    #[test]
+    fn test_config_builder_new_custom_ex() {
+        let cipher_suites: Vec<*const rustls_supported_ciphersuite> = DEFAULT_CIPHER_SUITES
+            .iter()
+            .map(|cs| cs as *const SupportedCipherSuite as *const _)
+            .collect();
+        let versions: [u16; 1] = [0x0304];
+        let x25519 = &rustls::kx_group::X25519 as *const SupportedKxGroup
+            as *const rustls_supported_kx_group;
+        let mut builder: *mut rustls_client_config_builder = null_mut();
+        let result = rustls_client_config_builder::rustls_client_config_builder_new_custom_ex(
+            cipher_suites.as_ptr(),
+            cipher_suites.len(),
+            versions.as_ptr(),
+            versions.len(),
+            &x25519,
+            0,
+            &mut builder,
+        );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        assert!(builder.is_null());
+
+        let result = rustls_client_config_builder::rustls_client_config_builder_new_custom_ex(
+            cipher_suites.as_ptr(),
+            cipher_suites.len(),
+            versions.as_ptr(),
+            versions.len(),
+            &x25519,
+            1,
+            &mut builder,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let kx_groups: Vec<u16> = try_ref_from_ptr!(builder)
+            .kx_groups
+            .iter()
+            .map(|g| g.name.get_u16())
+            .collect();
+        assert_eq!(kx_groups, vec![0x001d]);
+        assert_eq!(
+            crate::cipher::rustls_supported_kx_group::rustls_supported_kx_group_get_group(x25519),
+            0x001d
+        );
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        assert!(!config.is_null());
+        rustls_client_config::rustls_client_config_free(config);
+    }
+
+    #[test]
+    fn test_set_max_fragment_size() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        for &size in &[31, 16390] {
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_set_max_fragment_size(
+                    builder, size,
+                );
+            assert!(matches!(result, rustls_result::InvalidParameter));
+        }
+        assert_eq!(try_ref_from_ptr!(builder).max_fragment_size, None);
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_max_fragment_size(
+                builder, 512,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        assert_eq!(try_ref_from_ptr!(config).max_fragment_size, Some(512));
+
+        // The setting is accepted by rustls when creating a connection.
+        let mut conn: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new(
+            config,
+            "example.com\0".as_ptr() as *const c_char,
+            &mut conn,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        rustls_connection::rustls_connection_free(conn);
+        rustls_client_config::rustls_client_config_free(config);
+    }
+
+    unsafe extern "C" fn verify_from_userdata(
+        userdata: rustls_verify_server_cert_user_data,
+        _params: *const rustls_verify_server_cert_params,
+    ) -> u32 {
+        *(userdata as *const u32)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_set_certificate_verifier_with_roots() {
+        let store = rustls_root_cert_store::rustls_root_cert_store_new();
+        let pem = include_bytes!("../minica.pem");
+        let result = rustls_root_cert_store::rustls_root_cert_store_add_pem(
+            store,
+            pem.as_ptr(),
+            pem.len(),
+            true,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_certificate_verifier_with_roots(
+            builder,
+            store,
+            Some(verify_from_userdata),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let verifier = try_ref_from_ptr!(builder).verifier.clone().unwrap();
+
+        let accept = rustls_result::Ok as u32;
+        let reject = rustls_result::CertInvalidData as u32;
+        let defer = rustls_result::DeferToWebPki as u32;
+        // "example.com" doesn't match the certificate, so only the callback
+        // can accept it.
+        for &(callback_result, name, expect_ok) in &[
+            (accept, "example.com", true),
+            (reject, "localhost", false),
+            (defer, "localhost", true),
+            (defer, "example.com", false),
+        ] {
+            let guard = userdata_push(
+                &callback_result as *const u32 as *mut c_void,
+                None,
+                None,
+                null_mut(),
+            )
+            .unwrap();
+            let verified = verify_localhost_at_valid_time(verifier.as_ref(), name);
+            guard.try_drop().unwrap();
+            assert_eq!(verified.is_ok(), expect_ok, "{} {}", callback_result, name);
+        }
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+        rustls_root_cert_store::rustls_root_cert_store_free(store);
+    }
+
+    #[test]
+    fn test_set_verifier_userdata() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let accept = rustls_result::Ok as u32;
+        rustls_client_config_builder::rustls_client_config_builder_set_verifier_userdata(
+            builder,
+            &accept as *const u32 as *mut c_void,
+        );
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+                builder,
+                Some(verify_from_userdata),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let verifier = try_ref_from_ptr!(builder).verifier.clone().unwrap();
+
+        // No userdata is pushed on the spawned thread, so the callback can
+        // only get it from the builder.
+        let verify = |verifier: Arc<dyn ServerCertVerifier>| {
+            std::thread::spawn(move || {
+                verifier
+                    .verify_server_cert(
+                        &Certificate(vec![]),
+                        &[],
+                        &"example.com".try_into().unwrap(),
+                        &mut std::iter::empty(),
+                        &[],
+                        SystemTime::now(),
+                    )
+                    .is_ok()
+            })
+            .join()
+            .unwrap()
+        };
+        assert!(verify(verifier.clone()));
+
+        rustls_client_config_builder::rustls_client_config_builder_set_verifier_userdata(
+            builder,
+            null_mut(),
+        );
+        assert!(!verify(verifier));
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    unsafe extern "C" fn count_problems(userdata: *mut c_void, _message: rustls_str) {
+        *(userdata as *mut usize) += 1;
+    }
+
+    #[test]
+    fn test_config_builder_validate() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        // rustls_client_config_builder_set_alpn_protocols rejects empty
+        // protocols, so set one directly.
+        try_mut_from_ptr!(builder).alpn_protocols = vec![b"h2".to_vec(), vec![]];
+        let mut count: usize = 0;
+        let result = rustls_client_config_builder::rustls_client_config_builder_validate(
+            builder,
+            Some(count_problems),
+            &mut count as *mut usize as *mut c_void,
+        );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        // Missing verifier and empty ALPN protocol.
+        assert_eq!(count, 2);
+
+        let store = crate::cipher::rustls_root_cert_store::rustls_root_cert_store_new();
+        rustls_client_config_builder::rustls_client_config_builder_use_roots(builder, store);
+        let alpn: Vec<rustls_slice_bytes> = vec!["h2".as_bytes().into()];
+        rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+            builder,
+            alpn.as_ptr(),
+            alpn.len(),
+        );
+        let result = rustls_client_config_builder::rustls_client_config_builder_validate(
+            builder,
+            None,
+            null_mut(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+
+        rustls_client_config_builder::rustls_client_config_builder_reset_verifier(builder);
+        let result = rustls_client_config_builder::rustls_client_config_builder_validate(
+            builder,
+            None,
+            null_mut(),
+        );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        crate::cipher::rustls_root_cert_store::rustls_root_cert_store_free(store);
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_config_builder_from_profile() {
+        let profile = format!(
+            "# A comment\n\
+             versions = TLSv1.3\n\
+             cipher_suites = TLS13_CHACHA20_POLY1305_SHA256, TLS13_AES_128_GCM_SHA256\n\
+             alpn = h2, http/1.1\n\
+             \n\
+             roots = {}/minica.pem\n\
+             enable_sni = false\n\0",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let mut builder: *mut rustls_client_config_builder = null_mut();
+        let result = rustls_client_config_builder::rustls_client_config_builder_from_profile(
+            profile.as_ptr() as *const c_char,
+            &mut builder,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let result = rustls_client_config_builder::rustls_client_config_builder_validate(
+            builder,
+            None,
+            null_mut(),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        {
+            let config2 = try_ref_from_ptr!(config);
+            assert!(!config2.enable_sni);
+            assert_eq!(
+                config2.alpn_protocols,
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            );
+        }
+        rustls_client_config::rustls_client_config_free(config);
+
+        let long_alpn = format!("alpn = h2, {}\0", "a".repeat(256));
+        for bad in &[
+            "unknown_key = 1\0",
+            long_alpn.as_str(),
+            "versions = TLSv1.1\0",
+            "cipher_suites = TLS_NULL_WITH_NULL_NULL\0",
+            "enable_sni = yes\0",
+            "no equals sign\0",
+            "versions = TLSv1.2\ncipher_suites = TLS13_AES_128_GCM_SHA256\0",
+        ] {
+            let mut builder: *mut rustls_client_config_builder = null_mut();
+            let result = rustls_client_config_builder::rustls_client_config_builder_from_profile(
+                bad.as_ptr() as *const c_char,
+                &mut builder,
+            );
+            assert!(matches!(result, rustls_result::InvalidParameter), "{}", bad);
+            assert_eq!(builder, null_mut());
+        }
+    }
+
+    fn client_hello(
+        config: *const rustls_client_config,
+        hostname: &str,
+        enable_sni: bool,
+    ) -> Vec<u8> {
+        let hostname = std::ffi::CString::new(hostname).unwrap();
+        let mut conn: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new_with_options(
+            config,
+            hostname.as_ptr(),
+            enable_sni,
+            &mut conn,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let mut hello: Vec<u8> = vec![];
+        let c: &mut Connection = crate::try_from_mut(conn).unwrap();
+        c.write_tls(&mut hello).unwrap();
+        rustls_connection::rustls_connection_free(conn);
+        hello
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_connection_new_with_options() {
+        let builder: *mut rustls_client_config_builder =
+            rustls_client_config_builder::rustls_client_config_builder_new();
+        let config = rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        let contains_name = |hello: &[u8]| {
+            hello
+                .windows(b"example.com".len())
+                .any(|w| w == b"example.com")
+        };
+
+        assert!(contains_name(&client_hello(config, "example.com", true)));
+        assert!(!contains_name(&client_hello(config, "example.com", false)));
+        // Overriding SNI for one connection doesn't change the config.
+        assert!(try_ref_from_ptr!(config).enable_sni);
+        rustls_client_config::rustls_client_config_free(config);
     }
 
     // Build a client connection and test the getters and initial values.
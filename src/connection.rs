@@ -1,17 +1,29 @@
-use std::io::{ErrorKind, Read, Write};
-use std::{ffi::c_void, ptr::null};
+use std::cmp::{max, min};
+use std::convert::{TryFrom, TryInto};
+use std::ffi::{c_void, CStr};
+use std::io::{ErrorKind, IoSlice, Read, Write};
+use std::ptr::null;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{ptr::null_mut, slice};
 
-use libc::{size_t, EINVAL, EIO};
+use libc::{c_char, size_t, EINVAL, EIO, ENOBUFS};
+use ring::digest;
+use rustls::client::StoresClientSessions;
 use rustls::{
-    Certificate, ClientConnection, ServerConnection, SupportedCipherSuite, ALL_CIPHER_SUITES,
+    BulkAlgorithm, Certificate, ClientConnection, IoState, ServerConnection, SupportedCipherSuite,
+    ALL_CIPHER_SUITES,
 };
 
+use crate::enums::rustls_fingerprint_algorithm;
 use crate::io::{
     rustls_write_vectored_callback, CallbackReader, CallbackWriter, ReadCallback,
     VectoredCallbackWriter, VectoredWriteCallback, WriteCallback,
 };
 use crate::log::{ensure_log_registered, rustls_log_callback};
+use crate::server;
+use crate::session::rustls_new_ticket_callback;
+use crate::{client, rslice::rustls_str};
 
 use crate::BoxCastPtr;
 use crate::{
@@ -21,13 +33,52 @@ use crate::{
     try_callback,
 };
 use crate::{ffi_panic_boundary, try_ref_from_ptr};
-use crate::{try_mut_from_ptr, try_slice, userdata_push, CastPtr};
+use crate::{try_mut_from_ptr, try_slice, userdata_push, x509, CastPtr};
 use rustls_result::NullParameter;
 
 pub(crate) struct Connection {
     conn: rustls::Connection,
     userdata: *mut c_void,
     log_callback: rustls_log_callback,
+    new_ticket_callback: rustls_new_ticket_callback,
+    new_ticket_userdata: *mut c_void,
+    // Plaintext read from rustls by rustls_connection_peek, but not yet
+    // consumed by rustls_connection_read.
+    peeked: Vec<u8>,
+    // Total plaintext bytes returned by rustls_connection_read.
+    plaintext_read: u64,
+    // Byte counts since the connection was created or
+    // rustls_connection_reset_stats was last called.
+    stats: rustls_connection_stats,
+    // Set by rustls_connection_set_buffer_limits. rustls itself doesn't
+    // bound the data it receives, so read_tls refuses more once this many
+    // bytes are waiting to be processed or read.
+    read_limit: Option<usize>,
+    // TLS bytes passed to rustls since the last process_new_packets, and
+    // the plaintext rustls held after it.
+    tls_unprocessed: usize,
+    plaintext_pending: usize,
+    record_coalescing: bool,
+    // DER-encoded distinguished names from the server's CertificateRequest.
+    ca_names: Vec<Vec<u8>>,
+    // The OCSP response the server stapled to its certificate, if any.
+    stapled_ocsp: Vec<u8>,
+    // Set by rustls_connection_set_peer_finished_sending.
+    peer_finished: bool,
+    // TLS bytes a client connection wrote before any arrived from the server.
+    client_hello: Vec<u8>,
+    tls_received: bool,
+    // TLS bytes written and read during the handshake.
+    handshake_sent: usize,
+    handshake_received: usize,
+    // Handshake bytes rustls still had to send when the handshake completed.
+    handshake_unsent: usize,
+    // For client connections, the config's session store and the key rustls
+    // stores this server's resumption session under.
+    session_key: Option<(Arc<dyn StoresClientSessions>, Vec<u8>)>,
+    // Whether the server's certificate was verified (for clients) or its key
+    // used to sign (for servers), which rustls only does in full handshakes.
+    certificate_used: bool,
 }
 
 impl Connection {
@@ -36,6 +87,25 @@ impl Connection {
             conn: conn.into(),
             userdata: null_mut(),
             log_callback: None,
+            new_ticket_callback: None,
+            new_ticket_userdata: null_mut(),
+            peeked: Vec::new(),
+            plaintext_read: 0,
+            stats: rustls_connection_stats::default(),
+            read_limit: None,
+            tls_unprocessed: 0,
+            plaintext_pending: 0,
+            record_coalescing: true,
+            ca_names: Vec::new(),
+            stapled_ocsp: Vec::new(),
+            peer_finished: false,
+            client_hello: Vec::new(),
+            tls_received: false,
+            handshake_sent: 0,
+            handshake_received: 0,
+            handshake_unsent: 0,
+            session_key: None,
+            certificate_used: false,
         }
     }
 
@@ -44,6 +114,25 @@ impl Connection {
             conn: conn.into(),
             userdata: null_mut(),
             log_callback: None,
+            new_ticket_callback: None,
+            new_ticket_userdata: null_mut(),
+            peeked: Vec::new(),
+            plaintext_read: 0,
+            stats: rustls_connection_stats::default(),
+            read_limit: None,
+            tls_unprocessed: 0,
+            plaintext_pending: 0,
+            record_coalescing: true,
+            ca_names: Vec::new(),
+            stapled_ocsp: Vec::new(),
+            peer_finished: false,
+            client_hello: Vec::new(),
+            tls_received: false,
+            handshake_sent: 0,
+            handshake_received: 0,
+            handshake_unsent: 0,
+            session_key: None,
+            certificate_used: false,
         }
     }
 
@@ -77,6 +166,161 @@ impl Connection {
             _ => None,
         }
     }
+
+    pub(crate) fn set_userdata(&mut self, userdata: *mut c_void) {
+        self.userdata = userdata;
+    }
+
+    pub(crate) fn set_session_key(&mut self, store: Arc<dyn StoresClientSessions>, key: Vec<u8>) {
+        self.session_key = Some((store, key));
+    }
+
+    /// Run rustls' process_new_packets with this connection's callbacks
+    /// available, mapping any error to a rustls_result.
+    fn process_packets(&mut self) -> Result<IoState, rustls_result> {
+        let guard = match userdata_push(
+            self.userdata,
+            self.log_callback,
+            self.new_ticket_callback,
+            self.new_ticket_userdata,
+        ) {
+            Ok(g) => g,
+            Err(_) => return Err(rustls_result::Panic),
+        };
+        server::take_sni_missing();
+        server::take_server_key_used();
+        client::take_acceptable_issuers();
+        client::take_server_cert_verified();
+        client::take_stapled_ocsp();
+        let was_handshaking = self.conn.is_handshaking();
+        self.tls_unprocessed = 0;
+        let result = match self.conn.process_new_packets() {
+            Ok(io_state) => Ok(io_state),
+            Err(_) if server::take_sni_missing() => Err(rustls_result::MissingSni),
+            Err(e) => Err(map_error(e)),
+        };
+        if let Ok(io_state) = &result {
+            self.plaintext_pending = io_state.plaintext_bytes_to_read();
+            if was_handshaking && !self.conn.is_handshaking() {
+                self.handshake_unsent = io_state.tls_bytes_to_write();
+            }
+        }
+        if client::take_server_cert_verified() | server::take_server_key_used() {
+            self.certificate_used = true;
+        }
+        if let Some(ca_names) = client::take_acceptable_issuers() {
+            self.ca_names = ca_names;
+        }
+        if let Some(ocsp) = client::take_stapled_ocsp() {
+            self.stapled_ocsp = ocsp;
+        }
+        match guard.try_drop() {
+            Ok(()) => result,
+            Err(_) => Err(rustls_result::Panic),
+        }
+    }
+
+    /// Read TLS bytes from `rd` into rustls, as rustls::Connection::read_tls.
+    /// Fails with ENOBUFS, without reading, if the read limit is reached.
+    pub(crate) fn read_tls(&mut self, rd: &mut dyn Read) -> std::io::Result<usize> {
+        if let Some(limit) = self.read_limit {
+            if self.tls_unprocessed + self.plaintext_pending + self.peeked.len() >= limit {
+                return Err(std::io::Error::from_raw_os_error(ENOBUFS));
+            }
+        }
+        let n = self.conn.read_tls(rd)?;
+        self.tls_unprocessed += n;
+        self.stats.tls_read += n as u64;
+        if n > 0 {
+            self.tls_received = true;
+        }
+        if self.conn.is_handshaking() {
+            self.handshake_received += n;
+        }
+        Ok(n)
+    }
+
+    /// Write pending TLS bytes to `wr`, as rustls::Connection::write_tls.
+    /// Until the server's first bytes arrive, a client keeps a copy of what
+    /// it wrote for rustls_connection_get_client_hello_bytes.
+    pub(crate) fn write_tls(&mut self, wr: &mut dyn Write) -> std::io::Result<usize> {
+        let handshaking = self.conn.is_handshaking();
+        let n = if self.tls_received || self.as_client().is_none() {
+            self.conn.write_tls(wr)?
+        } else {
+            let mut tee = TeeWriter {
+                inner: wr,
+                copy: &mut self.client_hello,
+            };
+            self.conn.write_tls(&mut tee)?
+        };
+        self.stats.tls_written += n as u64;
+        if handshaking {
+            self.handshake_sent += n;
+        } else {
+            let tail = min(n, self.handshake_unsent);
+            self.handshake_sent += tail;
+            self.handshake_unsent -= tail;
+        }
+        Ok(n)
+    }
+
+    /// Move up to `max` previously peeked plaintext bytes out of the peek
+    /// buffer, returning them.
+    fn take_peeked(&mut self, max: usize) -> Vec<u8> {
+        let n = min(max, self.peeked.len());
+        self.count_plaintext_read(n);
+        self.peeked.drain(..n).collect()
+    }
+
+    /// Count `n` plaintext bytes as returned to the application.
+    fn count_plaintext_read(&mut self, n: usize) {
+        self.plaintext_read += n as u64;
+        self.stats.plaintext_read += n as u64;
+    }
+
+    /// Read plaintext from rustls into `buf`, as rustls::Reader::read.
+    fn read_plaintext(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.conn.reader().read(buf)?;
+        self.plaintext_pending = self.plaintext_pending.saturating_sub(n);
+        Ok(n)
+    }
+
+    /// Drop any plaintext received from the peer that hasn't been read yet.
+    fn discard_plaintext(&mut self) {
+        self.peeked = Vec::new();
+        let mut scratch = [0u8; 4096];
+        while matches!(self.read_plaintext(&mut scratch), Ok(n) if n > 0) {}
+    }
+}
+
+/// Passes writes through to `inner`, appending the bytes it accepted to `copy`.
+struct TeeWriter<'a> {
+    inner: &'a mut dyn Write,
+    copy: &'a mut Vec<u8>,
+}
+
+impl Write for TeeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let n = self.inner.write_vectored(bufs)?;
+        let mut left = n;
+        for buf in bufs {
+            let take = min(left, buf.len());
+            self.copy.extend_from_slice(&buf[..take]);
+            left -= take;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 impl std::ops::Deref for Connection {
@@ -97,6 +341,110 @@ pub struct rustls_connection {
     _private: [u8; 0],
 }
 
+/// Signature algorithms accepted by chain_includes_cert. These are the ones
+/// rustls' WebPKI verifier accepts.
+static SUPPORTED_SIG_ALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// Whether `cert` is one of `peer_certs`, or the chain they form is valid at
+/// `now` with `cert` as its only trust anchor. `peer_is_client` selects
+/// whether the end entity must be valid for client or server authentication.
+pub(crate) fn chain_includes_cert(
+    peer_certs: &[Certificate],
+    peer_is_client: bool,
+    cert: &[u8],
+    now: webpki::Time,
+) -> bool {
+    if peer_certs.iter().any(|c| c.0 == cert) {
+        return true;
+    }
+    let (end_entity, intermediates) = match peer_certs.split_first() {
+        Some(split) => split,
+        None => return false,
+    };
+    let anchors = match webpki::TrustAnchor::try_from_cert_der(cert) {
+        Ok(anchor) => [anchor],
+        Err(_) => return false,
+    };
+    let end_entity = match webpki::EndEntityCert::try_from(end_entity.0.as_slice()) {
+        Ok(end_entity) => end_entity,
+        Err(_) => return false,
+    };
+    let intermediates: Vec<&[u8]> = intermediates.iter().map(|c| c.0.as_slice()).collect();
+    let result = if peer_is_client {
+        end_entity.verify_is_valid_tls_client_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsClientTrustAnchors(&anchors),
+            &intermediates,
+            now,
+        )
+    } else {
+        end_entity.verify_is_valid_tls_server_cert(
+            SUPPORTED_SIG_ALGS,
+            &webpki::TlsServerTrustAnchors(&anchors),
+            &intermediates,
+            now,
+        )
+    };
+    result.is_ok()
+}
+
+/// A callback for rustls_connection_get_ca_names_formatted. It is called
+/// once for each certificate authority name, which is only valid for the
+/// duration of the callback.
+#[allow(non_camel_case_types)]
+pub type rustls_ca_name_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, name: rustls_str)>;
+
+/// A snapshot of a connection's I/O state, filled in by
+/// rustls_connection_io_state.
+/// <https://docs.rs/rustls/0.20.0/rustls/struct.IoState.html>
+#[repr(C)]
+pub struct rustls_io_state {
+    /// Same as rustls_connection_wants_read.
+    pub wants_read: bool,
+    /// Same as rustls_connection_wants_write.
+    pub wants_write: bool,
+    /// Same as rustls_connection_is_handshaking.
+    pub is_handshaking: bool,
+    /// True if the peer has sent a close_notify alert.
+    pub peer_has_closed: bool,
+    /// How many plaintext bytes rustls_connection_read can return without
+    /// further I/O.
+    pub plaintext_bytes_to_read: size_t,
+    /// How many bytes rustls_connection_write_tls could write right now.
+    pub tls_bytes_to_write: size_t,
+}
+
+/// Byte counts for a connection, filled in by rustls_connection_get_stats.
+/// They count from when the connection was created, or from the last call
+/// to rustls_connection_reset_stats.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct rustls_connection_stats {
+    /// Plaintext bytes returned by rustls_connection_read.
+    pub plaintext_read: u64,
+    /// Plaintext bytes accepted by rustls_connection_write and
+    /// rustls_connection_write_early_data.
+    pub plaintext_written: u64,
+    /// TLS bytes passed in with rustls_connection_read_tls.
+    pub tls_read: u64,
+    /// TLS bytes passed out with rustls_connection_write_tls.
+    pub tls_written: u64,
+}
+
 impl CastPtr for rustls_connection {
     type RustType = Connection;
 }
@@ -129,6 +477,25 @@ impl rustls_connection {
         conn.log_callback = cb;
     }
 
+    /// Set a callback to be invoked whenever this client connection receives a
+    /// new session from the server that can be used for later resumption,
+    /// e.g. to persist it eagerly in a shared cache. The callback is invoked
+    /// during rustls_connection_process_new_packets with `userdata` and the
+    /// opaque encoded session. Passing a NULL callback removes it.
+    /// This has no effect on server connections.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_set_new_ticket_callback(
+        conn: *mut rustls_connection,
+        callback: rustls_new_ticket_callback,
+        userdata: *mut c_void,
+    ) {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            conn.new_ticket_callback = callback;
+            conn.new_ticket_userdata = userdata;
+        }
+    }
+
     /// Read some TLS bytes from the network into internal buffers. The actual network
     /// I/O is performed by `callback`, which you provide. Rustls will invoke your
     /// callback with a suitable buffer to store the read bytes into. You don't have
@@ -137,7 +504,8 @@ impl rustls_connection {
     /// this is distinct from the `userdata` parameter set with
     /// `rustls_connection_set_userdata`.
     /// Returns 0 for success, or an errno value on error. Passes through return values
-    /// from callback. See rustls_read_callback for more details.
+    /// from callback. See rustls_read_callback for more details. Returns ENOBUFS
+    /// if the read limit set with rustls_connection_set_buffer_limits is reached.
     /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.read_tls>
     #[no_mangle]
     pub extern "C" fn rustls_connection_read_tls(
@@ -170,6 +538,11 @@ impl rustls_connection {
     /// `callback`, which you provide. Rustls will invoke your callback with a
     /// suitable buffer containing TLS bytes to send. You don't have to write them
     /// all, just as many as you can in one syscall.
+    ///
+    /// By default, all pending TLS records are copied into a single buffer, so
+    /// one call hands the callback everything there is to send. See
+    /// rustls_connection_set_record_coalescing.
+    ///
     /// The `userdata` parameter is passed through directly to `callback`. Note that
     /// this is distinct from the `userdata` parameter set with
     /// `rustls_connection_set_userdata`.
@@ -190,7 +563,11 @@ impl rustls_connection {
             }
             let callback: WriteCallback = try_callback!(callback);
 
-            let mut writer = CallbackWriter { callback, userdata };
+            let mut writer = CallbackWriter {
+                callback,
+                userdata,
+                coalesce: conn.record_coalescing,
+            };
             let n_written: usize = match conn.write_tls(&mut writer) {
                 Ok(n) => n,
                 Err(e) => return rustls_io_result(e.raw_os_error().unwrap_or(EIO)),
@@ -203,6 +580,24 @@ impl rustls_connection {
         }
     }
 
+    /// Enable or disable record coalescing for rustls_connection_write_tls. It is
+    /// enabled by default: each call copies all pending TLS records into one
+    /// buffer and passes it to the callback, so that several records written
+    /// with rustls_connection_write go out in one syscall. When disabled, each
+    /// call passes only the first pending record, avoiding the copy.
+    /// rustls_connection_write_tls_vectored is not affected, since it passes
+    /// every pending record to its callback without copying.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_set_record_coalescing(
+        conn: *mut rustls_connection,
+        enable: bool,
+    ) {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            conn.record_coalescing = enable;
+        }
+    }
+
     /// Write all available TLS bytes to the network. The actual network I/O is performed by
     /// `callback`, which you provide. Rustls will invoke your callback with an array
     /// of rustls_slice_bytes, each containing a buffer with TLS bytes to send.
@@ -240,6 +635,50 @@ impl rustls_connection {
         }
     }
 
+    /// Copy the ClientHello a client connection sent into `buf`, which can
+    /// hold up to `len` bytes, and store its length in `out_n`. The bytes are
+    /// exactly what rustls passed to rustls_connection_write_tls (or
+    /// rustls_connection_write_tls_vectored), record headers included, so they
+    /// can be fed to tools that fingerprint a ClientHello.
+    ///
+    /// Capture starts when the connection is created and stops as soon as
+    /// rustls_connection_read_tls reads any bytes from the server, so this is
+    /// only meaningful before the server has responded. Call it after the
+    /// first rustls_connection_write_tls has sent the whole ClientHello.
+    ///
+    /// Returns RUSTLS_RESULT_NOT_FOUND for server connections, or if nothing
+    /// has been written yet, and RUSTLS_RESULT_INSUFFICIENT_SIZE if the
+    /// captured bytes are longer than `len`.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_client_hello_bytes(
+        conn: *const rustls_connection,
+        buf: *mut u8,
+        len: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if buf.is_null() || out_n.is_null() {
+                return NullParameter
+            }
+            let hello: &[u8] = &conn.client_hello;
+            if hello.is_empty() {
+                return rustls_result::NotFound;
+            }
+            if hello.len() > len {
+                unsafe {
+                    *out_n = 0
+                }
+                return rustls_result::InsufficientSize;
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(hello.as_ptr(), buf, hello.len());
+                *out_n = hello.len();
+            }
+            rustls_result::Ok
+        }
+    }
+
     /// Decrypt any available ciphertext from the internal buffer and put it
     /// into the internal plaintext buffer, potentially making bytes available
     /// for rustls_connection_read().
@@ -250,18 +689,47 @@ impl rustls_connection {
     ) -> rustls_result {
         ffi_panic_boundary! {
             let conn: &mut Connection = try_mut_from_ptr!(conn);
-            let guard = match userdata_push(conn.userdata, conn.log_callback) {
-                Ok(g) => g,
-                Err(_) => return rustls_result::Panic,
-            };
-            let result = match conn.process_new_packets() {
+            match conn.process_packets() {
                 Ok(_) => rustls_result::Ok,
-                Err(e) => map_error(e),
+                Err(e) => e,
+            }
+        }
+    }
+
+    /// Fill in `out` with a snapshot of the connection's I/O state, collapsing
+    /// the individual getters into one call for event loops that poll them
+    /// every iteration. To get an up-to-date count of readable plaintext, this
+    /// first processes any TLS data already passed in with
+    /// rustls_connection_read_tls, exactly as
+    /// rustls_connection_process_new_packets does, and returns its error if
+    /// that fails. In that case `out` is not modified.
+    /// <https://docs.rs/rustls/0.20.0/rustls/struct.IoState.html>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_io_state(
+        conn: *mut rustls_connection,
+        out: *mut rustls_io_state,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            if out.is_null() {
+                return NullParameter
+            }
+            let io_state = match conn.process_packets() {
+                Ok(s) => s,
+                Err(e) => return e,
             };
-            match guard.try_drop() {
-                Ok(()) => result,
-                Err(_) => rustls_result::Panic,
+            let io_state = rustls_io_state {
+                wants_read: conn.wants_read(),
+                wants_write: conn.wants_write(),
+                is_handshaking: conn.is_handshaking(),
+                peer_has_closed: io_state.peer_has_closed(),
+                plaintext_bytes_to_read: io_state.plaintext_bytes_to_read() + conn.peeked.len(),
+                tls_bytes_to_write: io_state.tls_bytes_to_write(),
+            };
+            unsafe {
+                *out = io_state;
             }
+            rustls_result::Ok
         }
     }
 
@@ -292,16 +760,122 @@ impl rustls_connection {
         }
     }
 
+    /// Return true if a client connection's session store holds a session
+    /// for its server, so that the next connection to that server made with
+    /// the same config can resume instead of doing a full handshake. With
+    /// TLS 1.3 the server sends its tickets after the handshake, so this may
+    /// only become true after further calls to
+    /// rustls_connection_process_new_packets. It reflects the store rather
+    /// than this connection, so a session preloaded or stored by an earlier
+    /// connection to the same server also counts, and one evicted since does
+    /// not. The server may still refuse to resume.
+    ///
+    /// If the config uses session store callbacks, this calls the get
+    /// callback with this connection's userdata. Returns false for server
+    /// connections.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_can_resume_next_time(
+        conn: *const rustls_connection,
+    ) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            let (store, key) = match &conn.session_key {
+                Some(session_key) => session_key,
+                None => return false,
+            };
+            let guard = match userdata_push(
+                conn.userdata,
+                conn.log_callback,
+                conn.new_ticket_callback,
+                conn.new_ticket_userdata,
+            ) {
+                Ok(g) => g,
+                Err(_) => return false,
+            };
+            let found = store.get(key).is_some();
+            match guard.try_drop() {
+                Ok(()) => found,
+                Err(_) => false,
+            }
+        }
+    }
+
+    /// Return true if the handshake resumed an earlier session rather than
+    /// doing a full handshake, for either a client or a server connection.
+    /// Returns false while the connection is still handshaking.
+    ///
+    /// rustls doesn't report resumption itself, so this is inferred from how
+    /// rustls 0.20 behaves internally: it only authenticates the server with
+    /// its certificate in full handshakes, so a completed handshake counts as
+    /// resumed when the client didn't call its server certificate verifier,
+    /// or the server didn't choose a signature scheme for its key. A custom
+    /// verifier or certificate resolver doesn't change the answer, since
+    /// rustls_client_config_builder_build and rustls_server_config_builder_build
+    /// wrap them to watch for these calls.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_is_resumed(conn: *const rustls_connection) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            !conn.is_handshaking() && !conn.certificate_used
+        }
+    }
+
     /// Sets a limit on the internal buffers used to buffer unsent plaintext (prior
-    /// to completing the TLS handshake) and unsent TLS records. By default, there
-    /// is no limit. The limit can be set at any time, even if the current buffer
-    /// use is higher.
+    /// to completing the TLS handshake) and unsent TLS records. By default, rustls
+    /// limits each of them to 64 KiB. Passing 0 for `n` removes the limit
+    /// entirely, including that default, so the buffers can grow without bound.
+    /// The limit can be set at any time, even if the current buffer use is higher.
+    /// Once the buffers are full, rustls_connection_write accepts fewer bytes than
+    /// it was given, until rustls_connection_write_tls drains them.
     /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.set_buffer_limit>
     #[no_mangle]
     pub extern "C" fn rustls_connection_set_buffer_limit(conn: *mut rustls_connection, n: usize) {
         ffi_panic_boundary! {
             let conn: &mut Connection = try_mut_from_ptr!(conn);
-            conn.set_buffer_limit(Some(n));
+            conn.set_buffer_limit(match n {
+                0 => None,
+                n => Some(n),
+            });
+        }
+    }
+
+    /// Set separate limits on the data the connection buffers in each
+    /// direction. `write_limit` is the limit set by
+    /// rustls_connection_set_buffer_limit, on plaintext written before the
+    /// handshake completes and on TLS records waiting for
+    /// rustls_connection_write_tls.
+    ///
+    /// `read_limit` bounds the data received from the peer: TLS bytes passed
+    /// in with rustls_connection_read_tls but not yet processed by
+    /// rustls_connection_process_new_packets, plus decrypted plaintext not
+    /// yet returned by rustls_connection_read (including peeked bytes). Once
+    /// those reach `read_limit`, rustls_connection_read_tls returns ENOBUFS
+    /// without calling its callback; process the data and read the plaintext,
+    /// then try again. rustls itself has no such limit, so without one a
+    /// peer can make the connection buffer any amount of data that the
+    /// application doesn't read. As the check happens before each read, the
+    /// buffered data can exceed `read_limit` by up to one read's worth.
+    ///
+    /// A limit of 0 means unlimited for that direction. By default there is
+    /// no read limit, while rustls limits the write buffers to 64 KiB each;
+    /// a `write_limit` of 0 removes that default as well. Both limits can be
+    /// changed at any time.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_set_buffer_limits(
+        conn: *mut rustls_connection,
+        read_limit: size_t,
+        write_limit: size_t,
+    ) {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            conn.read_limit = match read_limit {
+                0 => None,
+                n => Some(n),
+            };
+            conn.set_buffer_limit(match write_limit {
+                0 => None,
+                n => Some(n),
+            });
         }
     }
 
@@ -338,135 +912,681 @@ impl rustls_connection {
         }
     }
 
-    /// Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
-    /// borrowed buffer of bytes, and that buffer's len, in the output parameters.
-    /// The borrow lives as long as the connection.
-    /// If the connection is still handshaking, or no ALPN protocol was negotiated,
-    /// stores NULL and 0 in the output parameters.
-    /// The provided pointer is valid until the next mutating function call
-    /// affecting the connection. A mutating function call is one where the
-    /// first argument has type `struct rustls_connection *` (as opposed to
-    ///  `const struct rustls_connection *`).
-    /// <https://www.iana.org/assignments/tls-parameters/>
-    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.alpn_protocol>
+    /// Return the number of certificates the peer provided, so that C code
+    /// can fetch each of them with rustls_connection_get_peer_certificate
+    /// using indexes from 0 to the returned value minus one. Returns 0 if the
+    /// peer hasn't sent any certificates (yet).
     #[no_mangle]
-    pub extern "C" fn rustls_connection_get_alpn_protocol(
+    pub extern "C" fn rustls_connection_get_peer_certificates_len(
         conn: *const rustls_connection,
-        protocol_out: *mut *const u8,
-        protocol_out_len: *mut usize,
-    ) {
+    ) -> size_t {
         ffi_panic_boundary! {
             let conn: &Connection = try_ref_from_ptr!(conn);
-            if protocol_out.is_null() || protocol_out_len.is_null() {
-                return
-            }
-            match conn.alpn_protocol() {
-                Some(p) => unsafe {
-                    *protocol_out = p.as_ptr();
-                    *protocol_out_len = p.len();
-                },
-                None => unsafe {
-                    *protocol_out = null();
-                    *protocol_out_len = 0;
-                }
-            }
+            conn.peer_certificates().map_or(0, |certs| certs.len())
         }
     }
 
-    /// Return the TLS protocol version that has been negotiated. Before this
-    /// has been decided during the handshake, this will return 0. Otherwise,
-    /// the u16 version number as defined in the relevant RFC is returned.
-    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.protocol_version>
-    /// <https://docs.rs/rustls/0.20.0/rustls/internal/msgs/enums/enum.ProtocolVersion.html>
+    /// Copy the DER encoding of the SubjectPublicKeyInfo of the i-th certificate
+    /// provided by the peer to `buf`, which can hold up to `count` bytes, and
+    /// store its length in `out_n`. Index 0 is the end entity certificate.
+    /// This is useful for key pinning or DANE, where C code needs the raw public
+    /// key rather than the whole certificate.
+    /// Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+    /// RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't be parsed,
+    /// and RUSTLS_RESULT_INSUFFICIENT_SIZE if the SubjectPublicKeyInfo is longer
+    /// than `count`.
     #[no_mangle]
-    pub extern "C" fn rustls_connection_get_protocol_version(
+    pub extern "C" fn rustls_connection_get_peer_spki_der(
         conn: *const rustls_connection,
-    ) -> u16 {
+        i: size_t,
+        buf: *mut u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
         ffi_panic_boundary! {
             let conn: &Connection = try_ref_from_ptr!(conn);
-            match conn.protocol_version() {
-                Some(p) => p.get_u16(),
-                _ => 0,
+            if buf.is_null() || out_n.is_null() {
+                return NullParameter
             }
-        }
-    }
-
-    /// Retrieves the cipher suite agreed with the peer.
-    /// This returns NULL until the ciphersuite is agreed.
-    /// The returned pointer lives as long as the program.
-    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.negotiated_cipher_suite>
-    #[no_mangle]
-    pub extern "C" fn rustls_connection_get_negotiated_ciphersuite(
-        conn: *const rustls_connection,
-    ) -> *const rustls_supported_ciphersuite {
-        ffi_panic_boundary! {
-            let conn: &Connection = try_ref_from_ptr!(conn);
-            let negotiated = match conn.negotiated_cipher_suite() {
-                Some(cs) => cs,
-                None => return null(),
+            let cert = match conn.peer_certificates().and_then(|c| c.get(i)) {
+                Some(cert) => cert,
+                None => return rustls_result::NotFound,
             };
-            for cs in ALL_CIPHER_SUITES {
-                // This type annotation is here to enforce the lifetime stated
-                // in the doccomment - that the returned pointer lives as long
-                // as the program.
-                let cs: &'static SupportedCipherSuite = cs;
-                if negotiated == *cs {
-                    return cs as *const SupportedCipherSuite as *const _;
+            let spki: &[u8] = match x509::Certificate::parse(&cert.0) {
+                Some(parsed) => parsed.spki,
+                None => return rustls_result::CertificateParseError,
+            };
+            if spki.len() > count {
+                unsafe {
+                    *out_n = 0
                 }
+                return rustls_result::InsufficientSize;
             }
-            null()
+            unsafe {
+                std::ptr::copy_nonoverlapping(spki.as_ptr(), buf, spki.len());
+                *out_n = spki.len();
+            }
+            rustls_result::Ok
         }
     }
 
-    /// Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
-    /// This will increase the number of output bytes available to
-    /// `rustls_connection_write_tls`.
-    /// On success, store the number of bytes actually written in *out_n
-    /// (this may be less than `count`).
-    /// <https://docs.rs/rustls/0.20.0/rustls/struct.Writer.html#method.write>
+    /// Write the issuer distinguished name of the i-th certificate provided by
+    /// the peer to `buf`, which can hold up to `count` bytes, as a UTF-8 string,
+    /// and store its length in `out_n`. Index 0 is the end entity certificate.
+    /// The output is not NUL-terminated.
+    ///
+    /// The name is formatted on a best-effort basis following RFC 4514: the
+    /// most specific component comes first, e.g. "CN=Example CA,O=Example,C=US",
+    /// and the attributes of a multi-valued component are joined with "+".
+    /// CN, L, ST, O, OU, C, STREET, DC and UID are written by name, other
+    /// attribute types as dotted OIDs. Special characters in string values are
+    /// escaped with a backslash; values that aren't strings are written as "#"
+    /// followed by the hex of their DER encoding. The result is meant for
+    /// display and logging, not for comparing names.
+    ///
+    /// Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+    /// RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't be parsed,
+    /// and RUSTLS_RESULT_INSUFFICIENT_SIZE if the name is longer than `count`.
     #[no_mangle]
-    pub extern "C" fn rustls_connection_write(
-        conn: *mut rustls_connection,
-        buf: *const u8,
+    pub extern "C" fn rustls_connection_get_peer_certificate_issuer(
+        conn: *const rustls_connection,
+        i: size_t,
+        buf: *mut u8,
         count: size_t,
         out_n: *mut size_t,
     ) -> rustls_result {
         ffi_panic_boundary! {
-            let conn: &mut Connection = try_mut_from_ptr!(conn);
-            let write_buf: &[u8] = try_slice!(buf, count);
-            if out_n.is_null() {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if buf.is_null() || out_n.is_null() {
                 return NullParameter
             }
-            let n_written: usize = match conn.writer().write(write_buf) {
-                Ok(n) => n,
-                Err(_) => return rustls_result::Io,
+            let cert = match conn.peer_certificates().and_then(|c| c.get(i)) {
+                Some(cert) => cert,
+                None => return rustls_result::NotFound,
+            };
+            let issuer: String = match x509::Certificate::parse(&cert.0)
+                .and_then(|parsed| x509::format_name(parsed.issuer))
+            {
+                Some(issuer) => issuer,
+                None => return rustls_result::CertificateParseError,
             };
+            if issuer.len() > count {
+                unsafe {
+                    *out_n = 0
+                }
+                return rustls_result::InsufficientSize;
+            }
             unsafe {
-                *out_n = n_written;
+                std::ptr::copy_nonoverlapping(issuer.as_ptr(), buf, issuer.len());
+                *out_n = issuer.len();
             }
             rustls_result::Ok
         }
     }
 
-    /// Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
-    /// On success, store the number of bytes read in *out_n (this may be less
-    /// than `count`). A success with *out_n set to 0 means "all bytes currently
-    /// available have been read, but more bytes may become available after
-    /// subsequent calls to rustls_connection_read_tls and
-    /// rustls_connection_process_new_packets."
+    /// Return the number of seconds from now until the notAfter time of the i-th
+    /// certificate provided by the peer. Index 0 is the end entity certificate.
+    /// The result is negative if the certificate has already expired; the
+    /// default verifier rejects such certificates, so after a successful
+    /// handshake that means a custom verifier accepted it.
     ///
-    /// Subtle note: Even though this function only writes to `buf` and does not
-    /// read from it, the memory in `buf` must be initialized before the call (for
-    /// Rust-internal reasons). Initializing a buffer once and then using it
-    /// multiple times without zeroizing before each call is fine.
-    /// <https://docs.rs/rustls/0.20.0/rustls/struct.Reader.html#method.read>
+    /// Returns INT64_MIN if `conn` is NULL, if there is no certificate at index
+    /// `i`, or if its validity period can't be parsed.
     #[no_mangle]
-    pub extern "C" fn rustls_connection_read(
-        conn: *mut rustls_connection,
-        buf: *mut u8,
-        count: size_t,
-        out_n: *mut size_t,
-    ) -> rustls_result {
+    pub extern "C" fn rustls_connection_peer_certificate_seconds_until_expiry(
+        conn: *const rustls_connection,
+        i: size_t,
+    ) -> i64 {
+        ffi_panic_boundary! {
+            let conn: &Connection = match crate::try_from(conn) {
+                Some(c) => c,
+                None => return i64::MIN,
+            };
+            let not_after = match conn
+                .peer_certificates()
+                .and_then(|c| c.get(i))
+                .and_then(|cert| x509::Certificate::parse(&cert.0))
+                .and_then(|parsed| parsed.validity_period())
+            {
+                Some((_, not_after)) => not_after,
+                None => return i64::MIN,
+            };
+            let now: i64 = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(d) => d.as_secs() as i64,
+                Err(e) => -(e.duration().as_secs() as i64),
+            };
+            not_after - now
+        }
+    }
+
+    /// Call `callback` with each certificate authority name the server sent
+    /// when asking for a client certificate, formatted as described for
+    /// rustls_connection_get_peer_certificate_issuer, e.g.
+    /// "CN=Example CA,O=Example,C=US". This is meant for showing the user
+    /// which issuers the server accepts when they choose a certificate.
+    /// `userdata` is passed through to the callback.
+    ///
+    /// The names are available once the server's CertificateRequest has been
+    /// processed by rustls_connection_process_new_packets, so a
+    /// rustls_client_config_builder_set_certified_key resolver can't see them
+    /// for the same handshake. Nothing is reported if the server didn't ask
+    /// for a certificate or didn't name any authorities, or on a server
+    /// connection. Names that can't be parsed are skipped.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_ca_names_formatted(
+        conn: *const rustls_connection,
+        callback: rustls_ca_name_callback,
+        userdata: *mut c_void,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            let callback = match callback {
+                Some(cb) => cb,
+                None => return NullParameter,
+            };
+            for der in conn.ca_names.iter() {
+                let mut der: &[u8] = der;
+                let name = match x509::read_name(&mut der).and_then(x509::format_name) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let name: rustls_str = match name.as_str().try_into() {
+                    Ok(name) => name,
+                    Err(_) => continue,
+                };
+                unsafe { callback(userdata, name) };
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Copy the OCSP response the server stapled to its certificate into
+    /// `buf`, which can hold up to `len` bytes, and store its length in
+    /// `out_n`. This is for clients that cache or forward the response, or
+    /// apply their own revocation policy after the handshake.
+    ///
+    /// The response is recorded when the server's certificate is verified,
+    /// so it is available once rustls_connection_process_new_packets has
+    /// processed the server's Certificate message. If the server didn't
+    /// staple a response, the handshake was resumed (rustls doesn't verify
+    /// the certificate again), or this is a server connection, `out_n` is
+    /// set to 0 and RUSTLS_RESULT_OK is returned. Returns
+    /// RUSTLS_RESULT_INSUFFICIENT_SIZE if the response is longer than `len`.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_stapled_ocsp_response(
+        conn: *const rustls_connection,
+        buf: *mut u8,
+        len: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if buf.is_null() || out_n.is_null() {
+                return NullParameter
+            }
+            let ocsp: &[u8] = &conn.stapled_ocsp;
+            if ocsp.len() > len {
+                unsafe {
+                    *out_n = 0
+                }
+                return rustls_result::InsufficientSize;
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(ocsp.as_ptr(), buf, ocsp.len());
+                *out_n = ocsp.len();
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Return true if any certificate provided by the peer is signed with a weak
+    /// or deprecated signature algorithm: RSA, DSA or ECDSA with MD2, MD5 or
+    /// SHA-1. The check covers every certificate the peer sent, including the
+    /// end entity, and returns false if the peer sent no certificates.
+    ///
+    /// This is advisory, for scanners and compliance reporting. The default
+    /// WebPKI verifier already refuses chains that rely on these algorithms, so
+    /// a true result after a successful handshake usually means a custom
+    /// verifier accepted the chain, or the weak signature is on a certificate
+    /// that wasn't needed to build the path. Certificates that can't be parsed
+    /// are skipped.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_chain_uses_weak_signatures(
+        conn: *const rustls_connection,
+    ) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            match conn.peer_certificates() {
+                Some(certs) => certs.iter().any(|cert| {
+                    matches!(x509::Certificate::parse(&cert.0), Some(c) if c.has_weak_signature())
+                }),
+                None => false,
+            }
+        }
+    }
+
+    /// Return true if the DER-encoded certificate in `cert_der`, which is `len`
+    /// bytes long, is part of the peer's certificate chain: either the peer
+    /// sent it, or the certificates the peer sent form a chain that is valid
+    /// now with `cert_der` as its trust anchor. This lets C code check that a
+    /// connection chains to a particular root, such as a corporate CA, rather
+    /// than to any root the verifier trusts.
+    ///
+    /// The check is repeated here with `cert_der` as the only trust anchor; it
+    /// doesn't inspect the path the verifier built, which rustls doesn't
+    /// expose. Name checks aren't repeated. Returns false if the peer sent no
+    /// certificates or `cert_der` can't be parsed.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_chain_includes_cert(
+        conn: *const rustls_connection,
+        cert_der: *const u8,
+        len: size_t,
+    ) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            let cert: &[u8] = try_slice!(cert_der, len);
+            let peer_certs = match conn.peer_certificates() {
+                Some(certs) => certs,
+                None => return false,
+            };
+            let now = match webpki::Time::try_from(SystemTime::now()) {
+                Ok(now) => now,
+                Err(_) => return false,
+            };
+            chain_includes_cert(peer_certs, conn.as_server().is_some(), cert, now)
+        }
+    }
+
+    /// Return true if the end-entity certificate the peer sent is valid for
+    /// the NUL-terminated DNS name `name`, using the same name matching as
+    /// the WebPKI verifier, including wildcards. This lets C code check names
+    /// other than the one the connection was made to, such as an alternate
+    /// service name.
+    ///
+    /// Only the name is checked: whether the certificate is trusted is up to
+    /// the verifier that ran during the handshake. Returns false if the peer
+    /// sent no certificates, `name` is NULL or not a valid DNS name, or the
+    /// certificate can't be parsed.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_peer_certificate_valid_for_name(
+        conn: *const rustls_connection,
+        name: *const c_char,
+    ) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if name.is_null() {
+                return false;
+            }
+            let name: &CStr = unsafe { CStr::from_ptr(name) };
+            let name = match name.to_str().ok().map(webpki::DnsNameRef::try_from_ascii_str) {
+                Some(Ok(name)) => name,
+                _ => return false,
+            };
+            let end_entity = match conn.peer_certificates().and_then(|certs| certs.first()) {
+                Some(cert) => cert,
+                None => return false,
+            };
+            match webpki::EndEntityCert::try_from(end_entity.0.as_slice()) {
+                Ok(cert) => cert.verify_is_valid_for_dns_name(name).is_ok(),
+                Err(_) => false,
+            }
+        }
+    }
+
+    /// Write the fingerprint of the i-th certificate provided by the peer to
+    /// `buf`, which can hold up to `count` bytes, and store its length in `out_n`.
+    /// Index 0 is the end entity certificate. The fingerprint is the hash of the
+    /// DER-encoded certificate using `algorithm` (a `rustls_fingerprint_algorithm`),
+    /// formatted as lowercase hex bytes separated by colons, e.g. "c2:86:be:...".
+    /// The output is not NUL-terminated. A SHA-256 fingerprint takes 95 bytes.
+    ///
+    /// SHA-1 fingerprints are provided for comparison with legacy tooling only;
+    /// they are not suitable for security decisions.
+    ///
+    /// Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+    /// RUSTLS_RESULT_INVALID_PARAMETER for an unknown `algorithm`, and
+    /// RUSTLS_RESULT_INSUFFICIENT_SIZE if the fingerprint is longer than `count`.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_peer_certificate_fingerprint(
+        conn: *const rustls_connection,
+        i: size_t,
+        algorithm: u32,
+        buf: *mut u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if buf.is_null() || out_n.is_null() {
+                return NullParameter
+            }
+            let algorithm: &'static digest::Algorithm = match algorithm {
+                a if a == rustls_fingerprint_algorithm::Sha256 as u32 => &digest::SHA256,
+                a if a == rustls_fingerprint_algorithm::Sha1 as u32 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+                _ => return rustls_result::InvalidParameter,
+            };
+            let cert = match conn.peer_certificates().and_then(|c| c.get(i)) {
+                Some(cert) => cert,
+                None => return rustls_result::NotFound,
+            };
+            let hash = digest::digest(algorithm, &cert.0);
+            let fingerprint: Vec<String> = hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect();
+            let fingerprint: String = fingerprint.join(":");
+            if fingerprint.len() > count {
+                unsafe {
+                    *out_n = 0
+                }
+                return rustls_result::InsufficientSize;
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(fingerprint.as_ptr(), buf, fingerprint.len());
+                *out_n = fingerprint.len();
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
+    /// borrowed buffer of bytes, and that buffer's len, in the output parameters.
+    /// The borrow lives as long as the connection.
+    /// If the connection is still handshaking, or no ALPN protocol was negotiated,
+    /// stores NULL and 0 in the output parameters. On a server connection this is
+    /// the protocol the server selected from the client's offer.
+    /// The provided pointer is valid until the next mutating function call
+    /// affecting the connection. A mutating function call is one where the
+    /// first argument has type `struct rustls_connection *` (as opposed to
+    ///  `const struct rustls_connection *`).
+    /// <https://www.iana.org/assignments/tls-parameters/>
+    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.alpn_protocol>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_alpn_protocol(
+        conn: *const rustls_connection,
+        protocol_out: *mut *const u8,
+        protocol_out_len: *mut usize,
+    ) {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if protocol_out.is_null() || protocol_out_len.is_null() {
+                return
+            }
+            match conn.alpn_protocol() {
+                Some(p) => unsafe {
+                    *protocol_out = p.as_ptr();
+                    *protocol_out_len = p.len();
+                },
+                None => unsafe {
+                    *protocol_out = null();
+                    *protocol_out_len = 0;
+                }
+            }
+        }
+    }
+
+    /// Like rustls_connection_get_alpn_protocol, but also stores in
+    /// `out_present` whether an ALPN protocol was negotiated, so callers don't
+    /// have to infer it from a NULL pointer or a zero length. If `out_present`
+    /// is set to false, `out_data` is set to NULL and `out_len` to 0. The
+    /// borrow has the same lifetime as for rustls_connection_get_alpn_protocol.
+    ///
+    /// Returns RUSTLS_RESULT_NULL_PARAMETER if any parameter is NULL.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_alpn_protocol_ext(
+        conn: *const rustls_connection,
+        out_data: *mut *const u8,
+        out_len: *mut size_t,
+        out_present: *mut bool,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if out_data.is_null() || out_len.is_null() || out_present.is_null() {
+                return NullParameter
+            }
+            let (data, len, present) = match conn.alpn_protocol() {
+                Some(p) => (p.as_ptr(), p.len(), true),
+                None => (null(), 0, false),
+            };
+            unsafe {
+                *out_data = data;
+                *out_len = len;
+                *out_present = present;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Return the TLS protocol version that has been negotiated. Before this
+    /// has been decided during the handshake, this will return 0. Otherwise,
+    /// the u16 version number as defined in the relevant RFC is returned.
+    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.protocol_version>
+    /// <https://docs.rs/rustls/0.20.0/rustls/internal/msgs/enums/enum.ProtocolVersion.html>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_protocol_version(
+        conn: *const rustls_connection,
+    ) -> u16 {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            match conn.protocol_version() {
+                Some(p) => p.get_u16(),
+                _ => 0,
+            }
+        }
+    }
+
+    /// Retrieves the cipher suite agreed with the peer.
+    /// This returns NULL until the ciphersuite is agreed.
+    /// The returned pointer lives as long as the program.
+    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.negotiated_cipher_suite>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_negotiated_ciphersuite(
+        conn: *const rustls_connection,
+    ) -> *const rustls_supported_ciphersuite {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            let negotiated = match conn.negotiated_cipher_suite() {
+                Some(cs) => cs,
+                None => return null(),
+            };
+            for cs in ALL_CIPHER_SUITES {
+                // This type annotation is here to enforce the lifetime stated
+                // in the doccomment - that the returned pointer lives as long
+                // as the program.
+                let cs: &'static SupportedCipherSuite = cs;
+                if negotiated == *cs {
+                    return cs as *const SupportedCipherSuite as *const _;
+                }
+            }
+            null()
+        }
+    }
+
+    /// Derive `out_len` bytes of keying material from the connection's secrets
+    /// into `out_buf`, as defined by RFC 5705 (RFC 8446 section 7.5 for TLS
+    /// 1.3). `label` (of length `label_len`) names the use of the material.
+    /// If `has_context` is false, no context value is used and `context` is
+    /// ignored; otherwise the `context_len` bytes at `context`, which may be
+    /// zero, are used as the context value. Note that with TLS 1.2, no
+    /// context and an empty context give different results.
+    ///
+    /// Both ends of a connection derive the same bytes from the same label
+    /// and context. Returns RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE if called
+    /// before the handshake has completed.
+    /// <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.export_keying_material>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_export_keying_material(
+        conn: *const rustls_connection,
+        out_buf: *mut u8,
+        out_len: size_t,
+        label: *const u8,
+        label_len: size_t,
+        context: *const u8,
+        context_len: size_t,
+        has_context: bool,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if out_buf.is_null() {
+                return NullParameter
+            }
+            let output: &mut [u8] = unsafe { slice::from_raw_parts_mut(out_buf, out_len) };
+            let label: &[u8] = try_slice!(label, label_len);
+            let context: Option<&[u8]> = if has_context {
+                Some(try_slice!(context, context_len))
+            } else {
+                None
+            };
+            match conn.export_keying_material(output, label, context) {
+                Ok(()) => rustls_result::Ok,
+                Err(e) => map_error(e),
+            }
+        }
+    }
+
+    /// Return the maximum number of bytes that TLS record framing and encryption
+    /// add to each record sent or received on this connection: the record
+    /// header plus, depending on the cipher suite, the explicit nonce,
+    /// authentication tag and TLS 1.3 inner content type. This can be used to
+    /// size buffers for encrypted data. Until a cipher suite has been
+    /// negotiated, this returns the largest overhead of any supported suite.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_max_record_overhead(
+        conn: *const rustls_connection,
+    ) -> size_t {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            const HEADER: usize = 5;
+            const TAG: usize = 16;
+            const TLS12_EXPLICIT_NONCE: usize = 8;
+            const TLS13_CONTENT_TYPE: usize = 1;
+            match conn.negotiated_cipher_suite() {
+                Some(SupportedCipherSuite::Tls13(_)) => HEADER + TLS13_CONTENT_TYPE + TAG,
+                Some(SupportedCipherSuite::Tls12(s)) => match s.common.bulk {
+                    BulkAlgorithm::Chacha20Poly1305 => HEADER + TAG,
+                    _ => HEADER + TLS12_EXPLICIT_NONCE + TAG,
+                },
+                None => HEADER + TLS12_EXPLICIT_NONCE + TAG,
+            }
+        }
+    }
+
+    /// Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
+    /// This will increase the number of output bytes available to
+    /// `rustls_connection_write_tls`.
+    /// On success, store the number of bytes actually written in *out_n
+    /// (this may be less than `count`).
+    ///
+    /// Plaintext may be written before the handshake completes, for instance
+    /// to queue a request right after creating a client connection. It is
+    /// buffered, subject to rustls_connection_set_buffer_limit, and encrypted
+    /// and sent once the handshake finishes; this is not an error. Use
+    /// rustls_connection_is_handshaking to tell whether that is still pending.
+    /// Functions that need a completed handshake, such as
+    /// rustls_connection_export_keying_material, return
+    /// RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE instead.
+    /// <https://docs.rs/rustls/0.20.0/rustls/struct.Writer.html#method.write>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_write(
+        conn: *mut rustls_connection,
+        buf: *const u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            let write_buf: &[u8] = try_slice!(buf, count);
+            if out_n.is_null() {
+                return NullParameter
+            }
+            let n_written: usize = match conn.writer().write(write_buf) {
+                Ok(n) => n,
+                Err(_) => return rustls_result::Io,
+            };
+            conn.stats.plaintext_written += n_written as u64;
+            unsafe {
+                *out_n = n_written;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Write up to `count` plaintext bytes from `buf` as TLS 1.3 early data
+    /// (0-RTT), to be sent along with the ClientHello. On success, store the
+    /// number of bytes actually written in *out_n; this is less than `count`
+    /// once the server's early data limit is reached.
+    ///
+    /// Early data can only be written by a client connection, before its
+    /// handshake completes, when rustls_client_config_builder_set_enable_early_data
+    /// is on and the connection is resuming a TLS 1.3 session whose server
+    /// allows early data. Otherwise this returns RUSTLS_RESULT_NO_EARLY_DATA.
+    /// The server may still reject the early data: check
+    /// rustls_connection_is_early_data_accepted once the handshake completes,
+    /// and if it returns false, send the data again with
+    /// rustls_connection_write.
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConnection.html#method.early_data>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_write_early_data(
+        conn: *mut rustls_connection,
+        buf: *const u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            let write_buf: &[u8] = try_slice!(buf, count);
+            if out_n.is_null() {
+                return NullParameter
+            }
+            let mut early_data = match conn.as_client_mut().and_then(|c| c.early_data()) {
+                Some(early_data) => early_data,
+                None => return rustls_result::NoEarlyData,
+            };
+            let n_written: usize = match early_data.write(write_buf) {
+                Ok(n) => n,
+                Err(_) => return rustls_result::Io,
+            };
+            conn.stats.plaintext_written += n_written as u64;
+            unsafe {
+                *out_n = n_written;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Return true if the server accepted the early data a client connection
+    /// sent with rustls_connection_write_early_data. This is only meaningful
+    /// once the handshake has completed. Returns false for server connections.
+    /// <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConnection.html#method.is_early_data_accepted>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_is_early_data_accepted(
+        conn: *const rustls_connection,
+    ) -> bool {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            match conn.as_client() {
+                Some(client) => client.is_early_data_accepted(),
+                None => false,
+            }
+        }
+    }
+
+    /// Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
+    /// On success, store the number of bytes read in *out_n (this may be less
+    /// than `count`). A success with *out_n set to 0 means "all bytes currently
+    /// available have been read, but more bytes may become available after
+    /// subsequent calls to rustls_connection_read_tls and
+    /// rustls_connection_process_new_packets."
+    ///
+    /// Subtle note: Even though this function only writes to `buf` and does not
+    /// read from it, the memory in `buf` must be initialized before the call (for
+    /// Rust-internal reasons). Initializing a buffer once and then using it
+    /// multiple times without zeroizing before each call is fine.
+    /// <https://docs.rs/rustls/0.20.0/rustls/struct.Reader.html#method.read>
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_read(
+        conn: *mut rustls_connection,
+        buf: *mut u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
         ffi_panic_boundary! {
             let conn: &mut Connection = try_mut_from_ptr!(conn);
             if buf.is_null() {
@@ -475,6 +1595,13 @@ impl rustls_connection {
             if out_n.is_null() {
                 return NullParameter
             }
+            if conn.peer_finished {
+                conn.discard_plaintext();
+                unsafe {
+                    *out_n = 0;
+                }
+                return rustls_result::Ok;
+            }
 
             // Safety: the memory pointed at by buf must be initialized
             // (required by documentation of this function).
@@ -482,12 +1609,22 @@ impl rustls_connection {
                 slice::from_raw_parts_mut(buf, count)
             };
 
-            let n_read: usize = match conn.reader().read(read_buf) {
+            if !conn.peeked.is_empty() {
+                let peeked = conn.take_peeked(count);
+                read_buf[..peeked.len()].copy_from_slice(&peeked);
+                unsafe {
+                    *out_n = peeked.len();
+                }
+                return rustls_result::Ok;
+            }
+
+            let n_read: usize = match conn.read_plaintext(read_buf) {
                 Ok(n) => n,
                 Err(e) if e.kind() == ErrorKind::UnexpectedEof => return rustls_result::UnexpectedEof,
                 Err(e) if e.kind() == ErrorKind::WouldBlock => return rustls_result::PlaintextEmpty,
                 Err(_) => return rustls_result::Io,
             };
+            conn.count_plaintext_read(n_read);
             unsafe {
                 *out_n = n_read;
             }
@@ -495,6 +1632,249 @@ impl rustls_connection {
         }
     }
 
+    /// Copy up to `count` plaintext bytes from the `rustls_connection` into `buf`
+    /// without consuming them: a subsequent rustls_connection_read or
+    /// rustls_connection_peek returns the same bytes again. This is useful for
+    /// sniffing the application protocol before deciding how to handle it.
+    /// On success, store the number of bytes copied in *out_n (this may be less
+    /// than `count` if fewer bytes are currently available).
+    ///
+    /// Peeked bytes are moved out of rustls into a buffer held by the
+    /// connection until they are read. Received plaintext never counts
+    /// against rustls_connection_set_buffer_limit, which only limits outgoing
+    /// data, but peeked bytes do count against the read limit set with
+    /// rustls_connection_set_buffer_limits, just like unread plaintext. Only
+    /// the plaintext that is actually available is copied, so a large
+    /// `count` doesn't make the connection buffer more than that.
+    ///
+    /// Returns RUSTLS_RESULT_PLAINTEXT_EMPTY and RUSTLS_RESULT_UNEXPECTED_EOF
+    /// under the same conditions as rustls_connection_read, if no bytes
+    /// have been peeked.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_peek(
+        conn: *mut rustls_connection,
+        buf: *mut u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            if buf.is_null() {
+                return NullParameter
+            }
+            if out_n.is_null() {
+                return NullParameter
+            }
+            if conn.peer_finished {
+                conn.discard_plaintext();
+                unsafe {
+                    *out_n = 0;
+                }
+                return rustls_result::Ok;
+            }
+
+            if conn.peeked.len() < count {
+                // Ask for at least one byte, so that rustls reports whether
+                // the plaintext is empty or the peer closed the connection.
+                let want = min(count - conn.peeked.len(), max(conn.plaintext_pending, 1));
+                let mut more = vec![0u8; want];
+                match conn.read_plaintext(&mut more) {
+                    Ok(n) => conn.peeked.extend_from_slice(&more[..n]),
+                    Err(_) if !conn.peeked.is_empty() => {}
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return rustls_result::UnexpectedEof,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return rustls_result::PlaintextEmpty,
+                    Err(_) => return rustls_result::Io,
+                }
+            }
+
+            let n = min(count, conn.peeked.len());
+            unsafe {
+                std::ptr::copy_nonoverlapping(conn.peeked.as_ptr(), buf, n);
+                *out_n = n;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Return the total number of plaintext bytes returned by
+    /// rustls_connection_read over the life of the connection, that is, the
+    /// offset of the next byte that read will return within the decrypted
+    /// stream. Bytes copied by rustls_connection_peek are not counted until
+    /// they are read. rustls_connection_reset_stats doesn't affect it.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_total_plaintext_read(
+        conn: *const rustls_connection,
+    ) -> u64 {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            conn.plaintext_read
+        }
+    }
+
+    /// Store the number of TLS bytes the connection has written and read
+    /// during the handshake in `sent` and `received`. These count the raw
+    /// bytes passed through rustls_connection_write_tls and
+    /// rustls_connection_read_tls, record headers included, until the
+    /// handshake completes. `sent` also includes the messages rustls had
+    /// queued at that point, like a client's Finished message or a TLS 1.3
+    /// server's session tickets. Bytes read after the handshake completes,
+    /// like session tickets arriving at a client, aren't counted. Comparing
+    /// the values for full and resumed handshakes shows the fixed cost of a
+    /// new connection.
+    ///
+    /// The counts are close but not exact: if a single
+    /// rustls_connection_read_tls call returns the peer's last handshake
+    /// message together with application data, that data is counted as
+    /// well. They are not affected by rustls_connection_reset_stats.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_handshake_bytes(
+        conn: *const rustls_connection,
+        sent: *mut size_t,
+        received: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if sent.is_null() || received.is_null() {
+                return NullParameter
+            }
+            unsafe {
+                *sent = conn.handshake_sent;
+                *received = conn.handshake_received;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Fill in `out` with the connection's byte counts since it was created
+    /// or rustls_connection_reset_stats was last called.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_get_stats(
+        conn: *const rustls_connection,
+        out: *mut rustls_connection_stats,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if out.is_null() {
+                return NullParameter
+            }
+            unsafe {
+                *out = conn.stats;
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Set the byte counts returned by rustls_connection_get_stats back to
+    /// zero, so that they count from now on. This is meant for reporting
+    /// metrics per interval without keeping the previous values. It doesn't
+    /// affect the connection itself.
+    ///
+    /// Counters that describe a position or a one-off event are not reset:
+    /// rustls_connection_total_plaintext_read is the offset into the
+    /// decrypted stream, and rustls_connection_get_handshake_bytes describes
+    /// the handshake, which happens once per connection. The TLS bytes of a
+    /// handshake still show up in the interval they were sent or received in.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_reset_stats(conn: *mut rustls_connection) {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            conn.stats = rustls_connection_stats::default();
+        }
+    }
+
+    /// Write a human-readable summary of the connection's state to `buf`,
+    /// which can hold up to `len` bytes, and store its length in `out_n`. The
+    /// output is not NUL-terminated. It has one "name: value" line per item,
+    /// covering the role, whether the handshake is in progress, the
+    /// negotiated protocol version, cipher suite and ALPN protocol, the
+    /// number of peer certificates and the byte counters. rustls doesn't
+    /// expose the negotiated key exchange group, so it isn't included.
+    ///
+    /// This is meant for logging and debugging only. The format may change
+    /// in any release, so don't parse it; use the individual getters instead.
+    ///
+    /// Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if the summary is longer than
+    /// `len`. 1024 bytes is currently always enough.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_describe(
+        conn: *const rustls_connection,
+        buf: *mut u8,
+        len: size_t,
+        out_n: *mut size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let conn: &Connection = try_ref_from_ptr!(conn);
+            if buf.is_null() || out_n.is_null() {
+                return NullParameter
+            }
+            let role = if conn.as_client().is_some() { "client" } else { "server" };
+            let version = conn
+                .protocol_version()
+                .map(|v| v.as_str().unwrap_or("unknown"))
+                .unwrap_or("none");
+            let suite = conn
+                .negotiated_cipher_suite()
+                .map(|cs| cs.suite().as_str().unwrap_or("unknown"))
+                .unwrap_or("none");
+            let alpn = match conn.alpn_protocol() {
+                None => "none".to_string(),
+                Some(p) if p.iter().all(|b| b.is_ascii_graphic()) => {
+                    String::from_utf8_lossy(p).into_owned()
+                }
+                Some(p) => format!("0x{}", p.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+            };
+            let lines = [
+                format!("role: {}", role),
+                format!("handshaking: {}", conn.is_handshaking()),
+                format!("protocol version: {}", version),
+                format!("cipher suite: {}", suite),
+                format!("alpn protocol: {}", alpn),
+                format!(
+                    "peer certificates: {}",
+                    conn.peer_certificates().map_or(0, |certs| certs.len())
+                ),
+                format!(
+                    "handshake bytes: {} sent, {} received",
+                    conn.handshake_sent, conn.handshake_received
+                ),
+                format!("plaintext bytes read: {}", conn.plaintext_read),
+            ];
+            let description = lines.join("\n");
+            if description.len() > len {
+                unsafe {
+                    *out_n = 0
+                }
+                return rustls_result::InsufficientSize;
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(description.as_ptr(), buf, description.len());
+                *out_n = description.len();
+            }
+            rustls_result::Ok
+        }
+    }
+
+    /// Tell the connection that the application protocol has received
+    /// everything it expects from the peer, e.g. because a Content-Length was
+    /// reached, even though no close_notify has arrived. Plaintext that has
+    /// been received but not read is dropped, and from now on
+    /// rustls_connection_read and rustls_connection_peek discard any
+    /// plaintext that arrives and return RUSTLS_RESULT_OK with `*out_n` set to
+    /// 0, as they do after a clean close.
+    ///
+    /// This only affects the read side. Writing, rustls_connection_send_close_notify
+    /// and TLS I/O work as before; rustls_connection_read_tls and
+    /// rustls_connection_process_new_packets still need to be called if the
+    /// application wants to see the peer's close_notify or alerts.
+    #[no_mangle]
+    pub extern "C" fn rustls_connection_set_peer_finished_sending(conn: *mut rustls_connection) {
+        ffi_panic_boundary! {
+            let conn: &mut Connection = try_mut_from_ptr!(conn);
+            conn.peer_finished = true;
+            conn.discard_plaintext();
+        }
+    }
+
     /// Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
     /// On success, store the number of bytes read in *out_n (this may be less
     /// than `count`). A success with *out_n set to 0 means "all bytes currently
@@ -521,18 +1901,36 @@ impl rustls_connection {
             if buf.is_null() || out_n.is_null() {
                 return NullParameter
             }
+            if conn.peer_finished {
+                conn.discard_plaintext();
+                unsafe {
+                    *out_n = 0;
+                }
+                return rustls_result::Ok;
+            }
             let read_buf: &mut [std::mem::MaybeUninit<u8>] = unsafe {
                 slice::from_raw_parts_mut(buf, count)
             };
 
             let mut read_buf = std::io::ReadBuf::uninit(read_buf);
 
+            if !conn.peeked.is_empty() {
+                let peeked = conn.take_peeked(count);
+                read_buf.append(&peeked);
+                unsafe {
+                    *out_n = peeked.len();
+                }
+                return rustls_result::Ok;
+            }
+
             let n_read: usize = match conn.reader().read_buf(&mut read_buf) {
                 Ok(()) => read_buf.filled_len(),
                 Err(e) if e.kind() == ErrorKind::UnexpectedEof => return rustls_result::UnexpectedEof,
                 Err(e) if e.kind() == ErrorKind::WouldBlock => return rustls_result::PlaintextEmpty,
                 Err(_) => return rustls_result::Io,
             };
+            conn.plaintext_pending = conn.plaintext_pending.saturating_sub(n_read);
+            conn.count_plaintext_read(n_read);
             unsafe {
                 *out_n = n_read;
             }
@@ -551,3 +1949,934 @@ impl rustls_connection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::{
+        rustls_certificate, rustls_certified_key, rustls_client_cert_verifier_optional,
+        rustls_root_cert_store,
+    };
+    use crate::rslice::rustls_slice_bytes;
+    use crate::server::rustls_server_config_builder;
+    use crate::test_util::{
+        handshake, localhost_certified_key, new_server_connection, transfer, ConnectionPair,
+    };
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_peer_certificate_info() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+
+        assert!(!rustls_connection::rustls_connection_chain_uses_weak_signatures(client));
+        // The client didn't send any certificates.
+        assert!(!rustls_connection::rustls_connection_chain_uses_weak_signatures(server));
+
+        let mut buf = [0u8; 512];
+        let mut n: size_t = 0;
+        let result = rustls_connection::rustls_connection_get_peer_spki_der(
+            client,
+            0,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 294);
+        let result = rustls_connection::rustls_connection_get_peer_spki_der(
+            client,
+            0,
+            buf.as_mut_ptr(),
+            100,
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::InsufficientSize));
+        let result = rustls_connection::rustls_connection_get_peer_spki_der(
+            client,
+            1,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::NotFound));
+
+        let result = rustls_connection::rustls_connection_get_peer_certificate_issuer(
+            client,
+            0,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "CN=minica root ca 3ed3c5"
+        );
+        let result = rustls_connection::rustls_connection_get_peer_certificate_issuer(
+            client,
+            0,
+            buf.as_mut_ptr(),
+            10,
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::InsufficientSize));
+        // The test certificate expired on 2023-04-19T05:14:09Z.
+        let until_expiry =
+            rustls_connection::rustls_connection_peer_certificate_seconds_until_expiry(client, 0);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        assert!((until_expiry - (1681881249 - now)).abs() <= 1);
+        assert_eq!(
+            rustls_connection::rustls_connection_peer_certificate_seconds_until_expiry(client, 1),
+            i64::MIN
+        );
+
+        let localhost_der =
+            rustls_pemfile::certs(&mut include_str!("../localhost/cert.pem").as_bytes())
+                .unwrap()
+                .remove(0);
+        let minica_der = rustls_pemfile::certs(&mut include_str!("../minica.pem").as_bytes())
+            .unwrap()
+            .remove(0);
+        assert!(rustls_connection::rustls_connection_chain_includes_cert(
+            client,
+            localhost_der.as_ptr(),
+            localhost_der.len(),
+        ));
+        assert!(!rustls_connection::rustls_connection_chain_includes_cert(
+            client,
+            b"garbage".as_ptr(),
+            7,
+        ));
+        // The test certificates have expired, so check the chain to the root
+        // at a time when they were valid.
+        let peer_certs = [rustls::Certificate(localhost_der)];
+        let valid = webpki::Time::from_seconds_since_unix_epoch(1650000000);
+        assert!(chain_includes_cert(&peer_certs, false, &minica_der, valid));
+        let expired = webpki::Time::from_seconds_since_unix_epoch(1700000000);
+        assert!(!chain_includes_cert(
+            &peer_certs,
+            false,
+            &minica_der,
+            expired
+        ));
+
+        let result = rustls_connection::rustls_connection_get_peer_certificate_fingerprint(
+            client,
+            0,
+            crate::enums::rustls_fingerprint_algorithm::Sha256 as u32,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "c2:86:be:20:79:62:c0:96:ea:8a:4a:cb:61:69:a9:1b:a4:63:09:46:1e:2c:ed:55:5d:de:3a:6e:25:54:d7:e5"
+        );
+        let result = rustls_connection::rustls_connection_get_peer_certificate_fingerprint(
+            client,
+            0,
+            crate::enums::rustls_fingerprint_algorithm::Sha1 as u32,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "9e:9f:75:e1:32:19:68:5e:14:88:29:c3:a5:e3:8d:31:e7:38:af:02"
+        );
+        let result = rustls_connection::rustls_connection_get_peer_certificate_fingerprint(
+            client,
+            0,
+            0,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_peer_certificate_chain() {
+        // Serve the localhost certificate followed by its issuer.
+        let chain_pem = [
+            include_str!("../localhost/cert.pem"),
+            include_str!("../minica.pem"),
+        ]
+        .concat();
+        let key_pem = include_str!("../localhost/key.pem").as_bytes();
+        let mut certified_key: *const rustls_certified_key = null();
+        let result = rustls_certified_key::rustls_certified_key_build(
+            chain_pem.as_ptr(),
+            chain_pem.len(),
+            key_pem.as_ptr(),
+            key_pem.len(),
+            &mut certified_key,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let pair = ConnectionPair::with_server_config(server_config);
+        let (client, server) = (pair.client, pair.server);
+        assert_eq!(
+            rustls_connection::rustls_connection_get_peer_certificates_len(client),
+            0
+        );
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+        let expected = rustls_pemfile::certs(&mut chain_pem.as_bytes()).unwrap();
+        let len = rustls_connection::rustls_connection_get_peer_certificates_len(client);
+        assert_eq!(len, 2);
+        for (i, expected) in expected.iter().enumerate() {
+            let cert = rustls_connection::rustls_connection_get_peer_certificate(client, i);
+            assert!(!cert.is_null());
+            let mut der: *const u8 = null();
+            let mut der_len: size_t = 0;
+            let result =
+                rustls_certificate::rustls_certificate_get_der(cert, &mut der, &mut der_len);
+            assert!(matches!(result, rustls_result::Ok));
+            assert_eq!(
+                unsafe { slice::from_raw_parts(der, der_len) },
+                &expected[..]
+            );
+        }
+        assert!(rustls_connection::rustls_connection_get_peer_certificate(client, len).is_null());
+        // The client didn't send any certificates.
+        assert_eq!(
+            rustls_connection::rustls_connection_get_peer_certificates_len(server),
+            0
+        );
+        assert_eq!(
+            rustls_connection::rustls_connection_get_peer_certificates_len(null()),
+            0
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_peer_certificate_valid_for_name() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        let valid_for = |conn, name: &str| {
+            rustls_connection::rustls_connection_peer_certificate_valid_for_name(
+                conn,
+                name.as_ptr() as *const c_char,
+            )
+        };
+        assert!(!valid_for(client, "localhost\0"));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+        assert!(valid_for(client, "localhost\0"));
+        assert!(valid_for(client, "LOCALHOST\0"));
+        assert!(!valid_for(client, "example.com\0"));
+        assert!(!valid_for(client, "not a name\0"));
+        assert!(
+            !rustls_connection::rustls_connection_peer_certificate_valid_for_name(client, null())
+        );
+        // The client didn't send any certificates.
+        assert!(!valid_for(server, "localhost\0"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_set_buffer_limit() {
+        let pair = ConnectionPair::connected();
+        let client = pair.client;
+
+        let data = [0u8; 1000];
+        let mut n: size_t = 0;
+        rustls_connection::rustls_connection_set_buffer_limit(client, 100);
+        let result =
+            rustls_connection::rustls_connection_write(client, data.as_ptr(), data.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(n < data.len());
+        let result =
+            rustls_connection::rustls_connection_write(client, data.as_ptr(), data.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+
+        // 0 removes the limit.
+        rustls_connection::rustls_connection_set_buffer_limit(client, 0);
+        let result =
+            rustls_connection::rustls_connection_write(client, data.as_ptr(), data.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, data.len());
+
+        // By default rustls limits the buffers to 64 KiB; 0 removes that too.
+        let server2 = new_server_connection(pair.server_config);
+        let large = vec![0u8; 100 * 1024];
+        let result = rustls_connection::rustls_connection_write(
+            server2,
+            large.as_ptr(),
+            large.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 64 * 1024);
+        rustls_connection::rustls_connection_set_buffer_limit(server2, 0);
+        let result = rustls_connection::rustls_connection_write(
+            server2,
+            large.as_ptr(),
+            large.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, large.len());
+        rustls_connection::rustls_connection_free(server2);
+    }
+
+    unsafe extern "C" fn record_ticket(userdata: *mut c_void, ticket: *const rustls_slice_bytes) {
+        let tickets = &mut *(userdata as *mut Vec<Vec<u8>>);
+        let ticket = &*ticket;
+        tickets.push(slice::from_raw_parts(ticket.data, ticket.len).to_vec());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_set_new_ticket_callback() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        let mut tickets: Vec<Vec<u8>> = vec![];
+        rustls_connection::rustls_connection_set_new_ticket_callback(
+            client,
+            Some(record_ticket),
+            &mut tickets as *mut Vec<Vec<u8>> as *mut c_void,
+        );
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+
+        // rustls 0.20 sends a single TLS 1.3 ticket. The client also stores
+        // a key exchange hint, which must not be reported as a ticket.
+        assert_eq!(tickets.len(), 1);
+        assert!(!tickets[0].is_empty());
+        assert!(rustls_connection::rustls_connection_can_resume_next_time(
+            client
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_set_buffer_limits() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+        rustls_connection::rustls_connection_set_buffer_limits(server, 100, 0);
+
+        let data = [0u8; 1000];
+        let mut n: size_t = 0;
+        let result =
+            rustls_connection::rustls_connection_write(client, data.as_ptr(), data.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        let mut tls: Vec<u8> = vec![];
+        try_mut_from_ptr!(client).write_tls(&mut tls).unwrap();
+        let (first, second) = tls.split_at(tls.len() / 2);
+
+        let read_tls = |mut rd: &[u8]| -> Option<i32> {
+            let server: &mut Connection = crate::try_from_mut(server).unwrap();
+            server
+                .read_tls(&mut rd)
+                .err()
+                .and_then(|e| e.raw_os_error())
+        };
+        // Unprocessed TLS bytes count against the limit...
+        assert_eq!(read_tls(first), None);
+        assert_eq!(read_tls(second), Some(libc::ENOBUFS));
+        assert_eq!(read_tls(&[]), Some(libc::ENOBUFS));
+        let result = rustls_connection::rustls_connection_process_new_packets(server);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(read_tls(second), None);
+        let result = rustls_connection::rustls_connection_process_new_packets(server);
+        assert!(matches!(result, rustls_result::Ok));
+        // ...and so does unread plaintext.
+        assert_eq!(read_tls(&[]), Some(libc::ENOBUFS));
+
+        let mut buf = [0u8; 1000];
+        let result = rustls_connection::rustls_connection_read(
+            server,
+            buf.as_mut_ptr(),
+            buf.len() - 50,
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, buf.len() - 50);
+        let result =
+            rustls_connection::rustls_connection_peek(server, buf.as_mut_ptr(), 50, &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 50);
+        assert_eq!(read_tls(&[]), None);
+
+        // The 50 peeked bytes still count.
+        rustls_connection::rustls_connection_set_buffer_limits(server, 10, 0);
+        assert_eq!(read_tls(&[]), Some(libc::ENOBUFS));
+        // 0 removes the limit.
+        rustls_connection::rustls_connection_set_buffer_limits(server, 0, 0);
+        assert_eq!(read_tls(&[]), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_get_stapled_ocsp_response() {
+        let ocsp = b"not really an OCSP response";
+        let certified_key = localhost_certified_key();
+        let mut with_ocsp: *const rustls_certified_key = null();
+        let result = rustls_certified_key::rustls_certified_key_clone_with_ocsp(
+            certified_key,
+            &ocsp[..].into(),
+            &mut with_ocsp,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder, &with_ocsp, 1,
+        );
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let pair = ConnectionPair::with_server_config(server_config);
+        let (client, server) = (pair.client, pair.server);
+
+        let mut buf = [0u8; 100];
+        let mut n: size_t = 1;
+        let result = rustls_connection::rustls_connection_get_stapled_ocsp_response(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        let result = rustls_connection::rustls_connection_get_stapled_ocsp_response(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], &ocsp[..]);
+        let result = rustls_connection::rustls_connection_get_stapled_ocsp_response(
+            client,
+            buf.as_mut_ptr(),
+            ocsp.len() - 1,
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::InsufficientSize));
+        let result = rustls_connection::rustls_connection_get_stapled_ocsp_response(
+            server,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+
+        rustls_certified_key::rustls_certified_key_free(with_ocsp);
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_set_peer_finished_sending() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+
+        let mut buf = [0u8; 64];
+        let mut n: size_t = 0;
+        let msg = b"hello world";
+        rustls_connection::rustls_connection_write(server, msg.as_ptr(), msg.len(), &mut n);
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        let result = rustls_connection::rustls_connection_peek(client, buf.as_mut_ptr(), 5, &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+
+        rustls_connection::rustls_connection_set_peer_finished_sending(client);
+        n = 1;
+        let result =
+            rustls_connection::rustls_connection_read(client, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+
+        // Later plaintext is dropped, but the write side still works.
+        rustls_connection::rustls_connection_write(server, msg.as_ptr(), msg.len(), &mut n);
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        n = 1;
+        let result =
+            rustls_connection::rustls_connection_peek(client, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+        rustls_connection::rustls_connection_write(client, msg.as_ptr(), msg.len(), &mut n);
+        assert!(matches!(transfer(client, server), rustls_result::Ok));
+        let result =
+            rustls_connection::rustls_connection_read(server, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], msg);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_peek() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+
+        let mut buf = [0u8; 64];
+        let mut n: size_t = 0;
+        let result =
+            rustls_connection::rustls_connection_peek(client, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::PlaintextEmpty));
+
+        let msg = b"hello world";
+        rustls_connection::rustls_connection_write(server, msg.as_ptr(), msg.len(), &mut n);
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+
+        for _ in 0..2 {
+            let result =
+                rustls_connection::rustls_connection_peek(client, buf.as_mut_ptr(), 5, &mut n);
+            assert!(matches!(result, rustls_result::Ok));
+            assert_eq!(&buf[..n], b"hello");
+        }
+        assert_eq!(
+            rustls_connection::rustls_connection_total_plaintext_read(client),
+            0
+        );
+        let result =
+            rustls_connection::rustls_connection_read(client, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], b"hello");
+        // Only the available plaintext is copied, however large `count` is.
+        let result =
+            rustls_connection::rustls_connection_peek(client, buf.as_mut_ptr(), usize::MAX, &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], b" world");
+        let result =
+            rustls_connection::rustls_connection_read(client, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], b" world");
+        assert_eq!(
+            rustls_connection::rustls_connection_total_plaintext_read(client),
+            msg.len() as u64
+        );
+        let mut stats = rustls_connection_stats::default();
+        let result = rustls_connection::rustls_connection_get_stats(client, &mut stats);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(stats.plaintext_read, msg.len() as u64);
+        assert_eq!(stats.plaintext_written, 0);
+        assert!(stats.tls_read > msg.len() as u64);
+        assert!(stats.tls_written > 0);
+
+        // Resetting the stats starts a new interval, but keeps the offset
+        // into the plaintext stream.
+        rustls_connection::rustls_connection_reset_stats(client);
+        rustls_connection::rustls_connection_write(client, msg.as_ptr(), msg.len(), &mut n);
+        rustls_connection::rustls_connection_get_stats(client, &mut stats);
+        assert_eq!(stats.plaintext_read, 0);
+        assert_eq!(stats.plaintext_written, msg.len() as u64);
+        assert_eq!(stats.tls_read, 0);
+        assert_eq!(stats.tls_written, 0);
+        assert_eq!(
+            rustls_connection::rustls_connection_total_plaintext_read(client),
+            msg.len() as u64
+        );
+    }
+
+    unsafe extern "C" fn collect_ca_name(userdata: *mut c_void, name: rustls_str) {
+        let names = &mut *(userdata as *mut Vec<String>);
+        let name = std::slice::from_raw_parts(name.data as *const u8, name.len);
+        names.push(String::from_utf8(name.to_vec()).unwrap());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_get_ca_names_formatted() {
+        let store = rustls_root_cert_store::rustls_root_cert_store_new();
+        let ca_pem = include_str!("../minica.pem").as_bytes();
+        let result = rustls_root_cert_store::rustls_root_cert_store_add_pem(
+            store,
+            ca_pem.as_ptr(),
+            ca_pem.len(),
+            true,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let verifier =
+            rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_new(store);
+        rustls_root_cert_store::rustls_root_cert_store_free(store);
+
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        rustls_server_config_builder::rustls_server_config_builder_set_client_verifier_optional(
+            builder, verifier,
+        );
+        rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_free(verifier);
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let pair = ConnectionPair::with_server_config(server_config);
+        let (client, server) = (pair.client, pair.server);
+        let mut names: Vec<String> = vec![];
+        let result = rustls_connection::rustls_connection_get_ca_names_formatted(
+            client,
+            Some(collect_ca_name),
+            &mut names as *mut Vec<String> as *mut c_void,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(names.is_empty());
+
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        let result = rustls_connection::rustls_connection_get_ca_names_formatted(
+            client,
+            Some(collect_ca_name),
+            &mut names as *mut Vec<String> as *mut c_void,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(names, vec!["CN=minica root ca 3ed3c5".to_string()]);
+    }
+
+    unsafe extern "C" fn collect_tls(
+        userdata: *mut c_void,
+        buf: *const u8,
+        n: size_t,
+        out_n: *mut size_t,
+    ) -> crate::error::rustls_io_result {
+        let writes = &mut *(userdata as *mut Vec<Vec<u8>>);
+        writes.push(slice::from_raw_parts(buf, n).to_vec());
+        *out_n = n;
+        crate::error::rustls_io_result(0)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_record_coalescing() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+
+        for &coalesce in &[true, false] {
+            rustls_connection::rustls_connection_set_record_coalescing(server, coalesce);
+            let mut n: size_t = 0;
+            for msg in &[&b"hello"[..], &b"world"[..]] {
+                rustls_connection::rustls_connection_write(server, msg.as_ptr(), msg.len(), &mut n);
+            }
+            let mut writes: Vec<Vec<u8>> = vec![];
+            while rustls_connection::rustls_connection_wants_write(server) {
+                let result = rustls_connection::rustls_connection_write_tls(
+                    server,
+                    Some(collect_tls),
+                    &mut writes as *mut Vec<Vec<u8>> as *mut c_void,
+                    &mut n,
+                );
+                assert_eq!(result.0, 0);
+            }
+            // Each message is one TLS 1.3 record: 5 bytes of data plus 22 of overhead.
+            if coalesce {
+                assert_eq!(writes.len(), 1);
+                assert_eq!(writes[0].len(), 2 * (5 + 22));
+            } else {
+                assert_eq!(writes.len(), 2);
+                assert!(writes.iter().all(|w| w.len() == 5 + 22));
+            }
+
+            let mut rest: &[u8] = &writes.concat();
+            while !rest.is_empty() {
+                let c: &mut Connection = try_mut_from_ptr!(client);
+                c.read_tls(&mut rest).unwrap();
+            }
+            let result = rustls_connection::rustls_connection_process_new_packets(client);
+            assert!(matches!(result, rustls_result::Ok));
+            let mut buf = [0u8; 64];
+            let result = rustls_connection::rustls_connection_read(
+                client,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut n,
+            );
+            assert!(matches!(result, rustls_result::Ok));
+            assert_eq!(&buf[..n], b"helloworld");
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_hello_bytes() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+
+        let mut buf = vec![0u8; 4096];
+        let mut n: size_t = 0;
+        let result = rustls_connection::rustls_connection_get_client_hello_bytes(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::NotFound));
+
+        let mut writes: Vec<Vec<u8>> = vec![];
+        let result = rustls_connection::rustls_connection_write_tls(
+            client,
+            Some(collect_tls),
+            &mut writes as *mut Vec<Vec<u8>> as *mut c_void,
+            &mut n,
+        );
+        assert_eq!(result.0, 0);
+        let sent = writes.concat();
+
+        let result = rustls_connection::rustls_connection_get_client_hello_bytes(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], &sent[..]);
+        // A handshake record carrying a ClientHello message.
+        assert_eq!(buf[0], 0x16);
+        assert_eq!(buf[5], 0x01);
+
+        let result = rustls_connection::rustls_connection_get_client_hello_bytes(
+            client,
+            buf.as_mut_ptr(),
+            sent.len() - 1,
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::InsufficientSize));
+        assert_eq!(n, 0);
+
+        // Nothing the client writes after the server responds is captured.
+        {
+            let s: &mut Connection = try_mut_from_ptr!(server);
+            let mut rest: &[u8] = &sent;
+            while !rest.is_empty() {
+                s.read_tls(&mut rest).unwrap();
+            }
+        }
+        assert!(matches!(
+            rustls_connection::rustls_connection_process_new_packets(server),
+            rustls_result::Ok
+        ));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        let result = rustls_connection::rustls_connection_get_client_hello_bytes(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], &sent[..]);
+
+        let result = rustls_connection::rustls_connection_get_client_hello_bytes(
+            server,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::NotFound));
+    }
+
+    fn export(conn: *const rustls_connection, context: Option<&[u8]>) -> (rustls_result, Vec<u8>) {
+        let label = b"EXPORTER-test";
+        let mut out = vec![0u8; 32];
+        let (context_ptr, context_len) = context.map_or((null(), 0), |c| (c.as_ptr(), c.len()));
+        let result = rustls_connection::rustls_connection_export_keying_material(
+            conn,
+            out.as_mut_ptr(),
+            out.len(),
+            label.as_ptr(),
+            label.len(),
+            context_ptr,
+            context_len,
+            context.is_some(),
+        );
+        (result, out)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_write_before_handshake() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        let msg = b"hello";
+        let mut n: size_t = 0;
+        let result =
+            rustls_connection::rustls_connection_write(client, msg.as_ptr(), msg.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, msg.len());
+        assert!(rustls_connection::rustls_connection_is_handshaking(client));
+
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(matches!(transfer(client, server), rustls_result::Ok));
+        let mut buf = [0u8; 16];
+        let result =
+            rustls_connection::rustls_connection_read(server, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(&buf[..n], msg);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_export_keying_material() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        let (result, _) = export(client, None);
+        assert!(matches!(result, rustls_result::HandshakeNotComplete));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+        let mut outputs = vec![];
+        for context in &[None, Some(&b""[..]), Some(&b"context"[..])] {
+            let (result, client_out) = export(client, *context);
+            assert!(matches!(result, rustls_result::Ok));
+            let (result, server_out) = export(server, *context);
+            assert!(matches!(result, rustls_result::Ok));
+            assert_eq!(client_out, server_out);
+            assert_ne!(client_out, vec![0u8; 32]);
+            outputs.push(client_out);
+        }
+        assert_ne!(outputs[0], outputs[2]);
+        assert_ne!(outputs[1], outputs[2]);
+    }
+
+    fn handshake_bytes(conn: *const rustls_connection) -> (size_t, size_t) {
+        let (mut sent, mut received): (size_t, size_t) = (0, 0);
+        let result = rustls_connection::rustls_connection_get_handshake_bytes(
+            conn,
+            &mut sent,
+            &mut received,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        (sent, received)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_handshake_bytes() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        assert_eq!(handshake_bytes(client), (0, 0));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+        let (client_sent, client_received) = handshake_bytes(client);
+        let (server_sent, server_received) = handshake_bytes(server);
+        assert!(client_sent > 0 && client_received > 0);
+        // The client's Finished is sent after its handshake completes, and
+        // still counts.
+        assert_eq!(client_sent, server_received);
+        // Session tickets count for the server, but not for the client.
+        assert!(server_sent >= client_received);
+
+        // Application data doesn't change the counts.
+        let mut n: size_t = 0;
+        for &(from, to) in &[(client, server), (server, client)] {
+            rustls_connection::rustls_connection_write(from, b"hello".as_ptr(), 5, &mut n);
+            assert!(matches!(transfer(from, to), rustls_result::Ok));
+        }
+        assert_eq!(handshake_bytes(client), (client_sent, client_received));
+        assert_eq!(handshake_bytes(server), (server_sent, server_received));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_describe() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+
+        let mut buf = vec![0u8; 1024];
+        let mut n: size_t = 0;
+        let result = rustls_connection::rustls_connection_describe(
+            client,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let description = std::str::from_utf8(&buf[..n]).unwrap();
+        for line in &[
+            "role: client",
+            "handshaking: false",
+            "protocol version: TLSv1_3",
+            "alpn protocol: none",
+            "peer certificates: 1",
+        ] {
+            assert!(description.lines().any(|l| l == *line), "{}", description);
+        }
+
+        let result =
+            rustls_connection::rustls_connection_describe(server, buf.as_mut_ptr(), 10, &mut n);
+        assert!(matches!(result, rustls_result::InsufficientSize));
+        assert_eq!(n, 0);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_connection_io_state() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        let mut state = rustls_io_state {
+            wants_read: false,
+            wants_write: false,
+            is_handshaking: false,
+            peer_has_closed: false,
+            plaintext_bytes_to_read: 0,
+            tls_bytes_to_write: 0,
+        };
+        let result = rustls_connection::rustls_connection_io_state(client, &mut state);
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(state.is_handshaking);
+        assert!(state.wants_write);
+        assert!(state.tls_bytes_to_write > 0);
+
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        let msg = b"hello";
+        let mut n: size_t = 0;
+        rustls_connection::rustls_connection_write(server, msg.as_ptr(), msg.len(), &mut n);
+        let result = rustls_connection::rustls_connection_io_state(server, &mut state);
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(!state.is_handshaking);
+        assert!(state.wants_write);
+        assert_eq!(state.tls_bytes_to_write, 22 + msg.len());
+
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        let result = rustls_connection::rustls_connection_io_state(client, &mut state);
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(!state.wants_write);
+        assert!(!state.peer_has_closed);
+        assert_eq!(state.plaintext_bytes_to_read, msg.len());
+
+        let result = rustls_connection::rustls_connection_io_state(client, null_mut());
+        assert!(matches!(result, rustls_result::NullParameter));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_max_record_overhead() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        // Before negotiation: the TLS 1.2 AES-GCM worst case.
+        assert_eq!(
+            rustls_connection::rustls_connection_max_record_overhead(client),
+            29
+        );
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        // TLS 1.3: header, content type and tag.
+        assert_eq!(
+            rustls_connection::rustls_connection_max_record_overhead(client),
+            22
+        );
+        assert_eq!(
+            rustls_connection::rustls_connection_max_record_overhead(server),
+            22
+        );
+    }
+}
@@ -1,3 +1,9 @@
+use libc::size_t;
+use std::slice;
+
+use crate::rslice::rustls_str;
+use crate::{ffi_panic_boundary, try_slice};
+
 #[repr(C)]
 #[allow(dead_code)]
 /// Definitions of known TLS protocol versions.
@@ -10,6 +16,35 @@ pub enum rustls_tls_version {
     Tlsv1_3 = 0x0304,
 }
 
+#[repr(C)]
+#[allow(dead_code)]
+/// Hash algorithms that can be used to compute certificate fingerprints.
+/// See rustls_connection_get_peer_certificate_fingerprint.
+pub enum rustls_fingerprint_algorithm {
+    Sha256 = 1,
+    Sha1 = 2,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+/// Browsers whose TLS fingerprint can be approximated.
+/// See rustls_client_config_builder_apply_fingerprint_profile.
+pub enum rustls_browser_profile {
+    Chrome = 1,
+    Firefox = 2,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+/// Which entry the in-memory client session cache drops when it is full.
+/// See rustls_client_config_builder_set_session_eviction.
+pub enum rustls_session_eviction_policy {
+    /// Drop the session that was least recently stored or used.
+    Lru = 1,
+    /// Drop the session that was stored first.
+    Fifo = 2,
+}
+
 /// Rustls' list of supported protocol versions. The length of the array is
 /// given by `RUSTLS_ALL_VERSIONS_LEN`.
 #[no_mangle]
@@ -34,9 +69,79 @@ pub static RUSTLS_DEFAULT_VERSIONS: [u16; 2] = [
 #[no_mangle]
 pub static RUSTLS_DEFAULT_VERSIONS_LEN: usize = RUSTLS_DEFAULT_VERSIONS.len();
 
+/// Return true if the protocol version arrays `a` and `b` contain the same
+/// versions, ignoring order and duplicates. This is useful for checking a
+/// version list against a policy, for instance "exactly TLS 1.2 and TLS 1.3",
+/// without spurious mismatches when the lists are ordered differently.
+/// Returns false if `a` or `b` is NULL.
+#[no_mangle]
+pub extern "C" fn rustls_protocol_versions_equal(
+    a: *const u16,
+    a_len: size_t,
+    b: *const u16,
+    b_len: size_t,
+) -> bool {
+    ffi_panic_boundary! {
+        let a: &[u16] = try_slice!(a, a_len);
+        let b: &[u16] = try_slice!(b, b_len);
+        a.iter().all(|v| b.contains(v)) && b.iter().all(|v| a.contains(v))
+    }
+}
+
+/// Return the name of the protocol version `version`, one of the values of
+/// rustls_tls_version, such as "TLSv1.3" for 0x0304. This is useful for
+/// logging the result of rustls_connection_get_protocol_version. Unknown
+/// versions, including 0, give the empty string. The lifetime of the
+/// `rustls_str` is the lifetime of the program, it does not need to be freed.
+#[no_mangle]
+pub extern "C" fn rustls_tls_version_get_name(version: u16) -> rustls_str<'static> {
+    let name = match version {
+        v if v == rustls_tls_version::Sslv2 as u16 => "SSLv2",
+        v if v == rustls_tls_version::Sslv3 as u16 => "SSLv3",
+        v if v == rustls_tls_version::Tlsv1_0 as u16 => "TLSv1.0",
+        v if v == rustls_tls_version::Tlsv1_1 as u16 => "TLSv1.1",
+        v if v == rustls_tls_version::Tlsv1_2 as u16 => "TLSv1.2",
+        v if v == rustls_tls_version::Tlsv1_3 as u16 => "TLSv1.3",
+        _ => "",
+    };
+    rustls_str::from_str_unchecked(name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str;
+
+    #[test]
+    fn protocol_versions_equal() {
+        let tls13_tls12 = [0x0304u16, 0x0303];
+        let tls12_tls13 = [0x0303u16, 0x0304, 0x0303];
+        let tls13 = [0x0304u16];
+        assert!(rustls_protocol_versions_equal(
+            tls13_tls12.as_ptr(),
+            tls13_tls12.len(),
+            tls12_tls13.as_ptr(),
+            tls12_tls13.len()
+        ));
+        assert!(!rustls_protocol_versions_equal(
+            tls13_tls12.as_ptr(),
+            tls13_tls12.len(),
+            tls13.as_ptr(),
+            tls13.len()
+        ));
+        assert!(rustls_protocol_versions_equal(
+            tls13.as_ptr(),
+            0,
+            tls13_tls12.as_ptr(),
+            0
+        ));
+        assert!(!rustls_protocol_versions_equal(
+            std::ptr::null(),
+            1,
+            tls13.as_ptr(),
+            tls13.len()
+        ));
+    }
 
     use rustls::{ALL_VERSIONS, DEFAULT_VERSIONS};
 
@@ -55,4 +160,17 @@ mod tests {
             assert_eq!(original.version.get_u16(), *ffi);
         }
     }
+
+    #[test]
+    fn tls_version_get_name() {
+        let name = |version| {
+            let s = rustls_tls_version_get_name(version);
+            unsafe { str::from_utf8(slice::from_raw_parts(s.data as *const u8, s.len)).unwrap() }
+        };
+        assert_eq!(name(0x0304), "TLSv1.3");
+        assert_eq!(name(0x0303), "TLSv1.2");
+        assert_eq!(name(0x0301), "TLSv1.0");
+        assert_eq!(name(0), "");
+        assert_eq!(name(0x0305), "");
+    }
 }
@@ -19,6 +19,10 @@ impl rustls_result {
     /// this to get a pointer to a buffer containing a detailed error
     /// message. The contents of the error buffer will be out_n bytes long,
     /// UTF-8 encoded, and not NUL-terminated.
+    ///
+    /// Every rustls_result value has a message. If the buffer is too small,
+    /// the message is truncated at a character boundary to fit in `len - 1`
+    /// bytes; a `len` of 0 writes nothing and sets out_n to 0.
     #[no_mangle]
     pub extern "C" fn rustls_error(
         result: c_uint,
@@ -35,7 +39,10 @@ impl rustls_result {
             }
             let result: rustls_result = rustls_result::try_from(result).unwrap_or(rustls_result::InvalidParameter);
             let error_str = result.to_string();
-            let out_len: usize = min(len - 1, error_str.len());
+            let mut out_len: usize = min(len.saturating_sub(1), error_str.len());
+            while !error_str.is_char_boundary(out_len) {
+                out_len -= 1;
+            }
             unsafe {
                 std::ptr::copy_nonoverlapping(error_str.as_ptr() as *mut c_char, buf, out_len);
                 *out_n = out_len;
@@ -97,6 +104,19 @@ fn test_rustls_error() {
     rustls_result::rustls_error(7101, &mut buf as *mut _, buf.len(), &mut n);
     let output: String = String::from_utf8(buf[0..n].iter().map(|b| *b as u8).collect()).unwrap();
     assert_eq!(&output, "peer sent no certificates");
+
+    rustls_result::rustls_error(7101, &mut buf as *mut _, 5, &mut n);
+    assert_eq!(n, 4);
+    rustls_result::rustls_error(7101, &mut buf as *mut _, 0, &mut n);
+    assert_eq!(n, 0);
+
+    for code in 7000..7400 {
+        if rustls_result::try_from(code).is_err() {
+            continue;
+        }
+        rustls_result::rustls_error(code, &mut buf as *mut _, buf.len(), &mut n);
+        assert_ne!(n, 0, "empty message for {}", code);
+    }
 }
 
 #[test]
@@ -130,6 +150,10 @@ pub enum rustls_result {
     InvalidParameter = 7009,
     UnexpectedEof = 7010,
     PlaintextEmpty = 7011,
+    KeyCertMismatch = 7012,
+    MissingSni = 7013,
+    NoEarlyData = 7014,
+    DeferToWebPki = 7015,
 
     // From https://docs.rs/rustls/0.20.0/rustls/enum.Error.html
     CorruptMessage = 7100,
@@ -301,6 +325,10 @@ impl Display for rustls_result {
         CertInvalidData => write!(f, "invalid certificate data found"),
         UnexpectedEof => write!(f,  "unexpected EOF"),
         PlaintextEmpty => write!(f,  "no plaintext available; call rustls_connection_read_tls again"),
+        KeyCertMismatch => write!(f, "private key does not match the end-entity certificate"),
+        MissingSni => write!(f, "client did not send a server name (SNI), which the server requires"),
+        NoEarlyData => write!(f, "early data can't be sent on this connection now"),
+        DeferToWebPki => write!(f, "certificate verification deferred to WebPKI"),
 
         // These variants correspond to a rustls::Error variant with a field,
         // where generating an arbitrary field would produce a confusing error
@@ -80,6 +80,9 @@ pub(crate) type WriteCallback = unsafe extern "C" fn(
 pub(crate) struct CallbackWriter {
     pub callback: WriteCallback,
     pub userdata: *mut c_void,
+    /// If true, copy all the buffers passed to `write_vectored` into one, so
+    /// that several pending TLS records are passed to the callback at once.
+    pub coalesce: bool,
 }
 
 impl Write for CallbackWriter {
@@ -93,6 +96,20 @@ impl Write for CallbackWriter {
         }
     }
 
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        if !self.coalesce || bufs.len() < 2 {
+            return match bufs.iter().find(|b| !b.is_empty()) {
+                Some(buf) => self.write(buf),
+                None => self.write(&[]),
+            };
+        }
+        let mut coalesced: Vec<u8> = Vec::with_capacity(bufs.iter().map(|b| b.len()).sum());
+        for buf in bufs {
+            coalesced.extend_from_slice(buf);
+        }
+        self.write(&coalesced)
+    }
+
     fn flush(&mut self) -> Result<()> {
         Ok(())
     }
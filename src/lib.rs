@@ -16,6 +16,7 @@ use crate::rslice::rustls_str;
 use libc::c_void;
 use std::cell::RefCell;
 use std::mem;
+use std::ptr::null_mut;
 use std::sync::Arc;
 
 pub mod cipher;
@@ -29,12 +30,16 @@ mod panic;
 pub mod rslice;
 pub mod server;
 pub mod session;
+#[cfg(test)]
+mod test_util;
+mod x509;
 
 pub use error::rustls_result;
 pub use error::*;
 
 use crate::log::rustls_log_callback;
 use crate::panic::PanicOrDefault;
+use crate::session::rustls_new_ticket_callback;
 
 // version.rs gets written at compile time by build.rs
 include!(concat!(env!("OUT_DIR"), "/version.rs"));
@@ -56,6 +61,8 @@ thread_local! {
 pub(crate) struct Userdata {
     userdata: *mut c_void,
     log_callback: rustls_log_callback,
+    new_ticket_callback: rustls_new_ticket_callback,
+    new_ticket_userdata: *mut c_void,
 }
 
 /// UserdataGuard pops an entry off the USERDATA stack, restoring the
@@ -78,6 +85,8 @@ impl UserdataGuard {
             data: Some(Userdata {
                 userdata: u,
                 log_callback: None,
+                new_ticket_callback: None,
+                new_ticket_userdata: null_mut(),
             }),
         }
     }
@@ -140,6 +149,8 @@ pub(crate) enum UserdataError {
 pub(crate) fn userdata_push(
     u: *mut c_void,
     cb: rustls_log_callback,
+    new_ticket_cb: rustls_new_ticket_callback,
+    new_ticket_userdata: *mut c_void,
 ) -> Result<UserdataGuard, UserdataError> {
     USERDATA
         .try_with(|userdata| {
@@ -149,6 +160,8 @@ pub(crate) fn userdata_push(
                     v.push(Userdata {
                         userdata: u,
                         log_callback: cb,
+                        new_ticket_callback: new_ticket_cb,
+                        new_ticket_userdata,
                     });
                     Ok(())
                 },
@@ -186,6 +199,21 @@ pub(crate) fn log_callback_get() -> Result<(rustls_log_callback, *mut c_void), U
         .unwrap_or(Err(UserdataError::AccessError))
 }
 
+pub(crate) fn new_ticket_callback_get(
+) -> Result<(rustls_new_ticket_callback, *mut c_void), UserdataError> {
+    USERDATA
+        .try_with(|userdata| {
+            userdata.try_borrow_mut().map_or_else(
+                |_| Err(UserdataError::AlreadyBorrowed),
+                |v| match v.last() {
+                    Some(u) => Ok((u.new_ticket_callback, u.new_ticket_userdata)),
+                    None => Err(UserdataError::EmptyStack),
+                },
+            )
+        })
+        .unwrap_or(Err(UserdataError::AccessError))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -195,7 +223,7 @@ mod tests {
     fn guard_try_pop() {
         let data = "hello";
         let data_ptr: *mut c_void = data as *const _ as _;
-        let mut guard = userdata_push(data_ptr, None).unwrap();
+        let mut guard = userdata_push(data_ptr, None, None, null_mut()).unwrap();
         assert_eq!(userdata_get().unwrap(), data_ptr);
         guard.try_pop().unwrap();
         assert!(matches!(guard.try_pop(), Err(_)));
@@ -205,7 +233,7 @@ mod tests {
     fn guard_try_drop() {
         let data = "hello";
         let data_ptr: *mut c_void = data as *const _ as _;
-        let guard = userdata_push(data_ptr, None).unwrap();
+        let guard = userdata_push(data_ptr, None, None, null_mut()).unwrap();
         assert_eq!(userdata_get().unwrap(), data_ptr);
         guard.try_drop().unwrap();
         assert!(matches!(userdata_get(), Err(_)));
@@ -216,7 +244,7 @@ mod tests {
         let data = "hello";
         let data_ptr: *mut c_void = data as *const _ as _;
         {
-            let _guard = userdata_push(data_ptr, None).unwrap();
+            let _guard = userdata_push(data_ptr, None, None, null_mut()).unwrap();
             assert_eq!(userdata_get().unwrap(), data_ptr);
         }
         assert!(matches!(userdata_get(), Err(_)));
@@ -227,12 +255,12 @@ mod tests {
         let hello = "hello";
         let hello_ptr: *mut c_void = hello as *const _ as _;
         {
-            let guard = userdata_push(hello_ptr, None).unwrap();
+            let guard = userdata_push(hello_ptr, None, None, null_mut()).unwrap();
             assert_eq!(userdata_get().unwrap(), hello_ptr);
             {
                 let yo = "yo";
                 let yo_ptr: *mut c_void = yo as *const _ as _;
-                let guard2 = userdata_push(yo_ptr, None).unwrap();
+                let guard2 = userdata_push(yo_ptr, None, None, null_mut()).unwrap();
                 assert_eq!(userdata_get().unwrap(), yo_ptr);
                 guard2.try_drop().unwrap();
             }
@@ -246,12 +274,12 @@ mod tests {
     fn out_of_order_drop() {
         let hello = "hello";
         let hello_ptr: *mut c_void = hello as *const _ as _;
-        let guard = userdata_push(hello_ptr, None).unwrap();
+        let guard = userdata_push(hello_ptr, None, None, null_mut()).unwrap();
         assert_eq!(userdata_get().unwrap(), hello_ptr);
 
         let yo = "yo";
         let yo_ptr: *mut c_void = yo as *const _ as _;
-        let guard2 = userdata_push(yo_ptr, None).unwrap();
+        let guard2 = userdata_push(yo_ptr, None, None, null_mut()).unwrap();
         assert_eq!(userdata_get().unwrap(), yo_ptr);
 
         assert!(matches!(guard.try_drop(), Err(UserdataError::WrongData)));
@@ -262,19 +290,19 @@ mod tests {
     fn userdata_multi_threads() {
         let hello = "hello";
         let hello_ptr: *mut c_void = hello as *const _ as _;
-        let guard = userdata_push(hello_ptr, None).unwrap();
+        let guard = userdata_push(hello_ptr, None, None, null_mut()).unwrap();
         assert_eq!(userdata_get().unwrap(), hello_ptr);
 
         let thread1 = thread::spawn(|| {
             let yo = "yo";
             let yo_ptr: *mut c_void = yo as *const _ as _;
-            let guard2 = userdata_push(yo_ptr, None).unwrap();
+            let guard2 = userdata_push(yo_ptr, None, None, null_mut()).unwrap();
             assert_eq!(userdata_get().unwrap(), yo_ptr);
 
             let greetz = "greetz";
             let greetz_ptr: *mut c_void = greetz as *const _ as _;
 
-            let guard3 = userdata_push(greetz_ptr, None).unwrap();
+            let guard3 = userdata_push(greetz_ptr, None, None, null_mut()).unwrap();
 
             assert_eq!(userdata_get().unwrap(), greetz_ptr);
             guard3.try_drop().unwrap();
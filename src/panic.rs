@@ -29,6 +29,8 @@ pub(crate) trait Defaultable: Default {}
 
 impl Defaultable for u16 {}
 impl Defaultable for usize {}
+impl Defaultable for u64 {}
+impl Defaultable for i64 {}
 impl Defaultable for bool {}
 impl Defaultable for () {}
 impl<T> Defaultable for Option<T> {}
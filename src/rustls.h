@@ -7,6 +7,24 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Browsers whose TLS fingerprint can be approximated.
+ * See rustls_client_config_builder_apply_fingerprint_profile.
+ */
+typedef enum rustls_browser_profile {
+  RUSTLS_BROWSER_PROFILE_CHROME = 1,
+  RUSTLS_BROWSER_PROFILE_FIREFOX = 2,
+} rustls_browser_profile;
+
+/**
+ * Hash algorithms that can be used to compute certificate fingerprints.
+ * See rustls_connection_get_peer_certificate_fingerprint.
+ */
+typedef enum rustls_fingerprint_algorithm {
+  RUSTLS_FINGERPRINT_ALGORITHM_SHA256 = 1,
+  RUSTLS_FINGERPRINT_ALGORITHM_SHA1 = 2,
+} rustls_fingerprint_algorithm;
+
 enum rustls_result {
   RUSTLS_RESULT_OK = 7000,
   RUSTLS_RESULT_IO = 7001,
@@ -20,6 +38,10 @@ enum rustls_result {
   RUSTLS_RESULT_INVALID_PARAMETER = 7009,
   RUSTLS_RESULT_UNEXPECTED_EOF = 7010,
   RUSTLS_RESULT_PLAINTEXT_EMPTY = 7011,
+  RUSTLS_RESULT_KEY_CERT_MISMATCH = 7012,
+  RUSTLS_RESULT_MISSING_SNI = 7013,
+  RUSTLS_RESULT_NO_EARLY_DATA = 7014,
+  RUSTLS_RESULT_DEFER_TO_WEB_PKI = 7015,
   RUSTLS_RESULT_CORRUPT_MESSAGE = 7100,
   RUSTLS_RESULT_NO_CERTIFICATES_PRESENTED = 7101,
   RUSTLS_RESULT_DECRYPT_ERROR = 7102,
@@ -84,6 +106,21 @@ enum rustls_result {
 };
 typedef uint32_t rustls_result;
 
+/**
+ * Which entry the in-memory client session cache drops when it is full.
+ * See rustls_client_config_builder_set_session_eviction.
+ */
+typedef enum rustls_session_eviction_policy {
+  /**
+   * Drop the session that was least recently stored or used.
+   */
+  RUSTLS_SESSION_EVICTION_POLICY_LRU = 1,
+  /**
+   * Drop the session that was stored first.
+   */
+  RUSTLS_SESSION_EVICTION_POLICY_FIFO = 2,
+} rustls_session_eviction_policy;
+
 /**
  * Definitions of known TLS protocol versions.
  */
@@ -222,6 +259,11 @@ typedef struct rustls_slice_str rustls_slice_str;
  */
 typedef struct rustls_supported_ciphersuite rustls_supported_ciphersuite;
 
+/**
+ * A key exchange group supported by rustls.
+ */
+typedef struct rustls_supported_kx_group rustls_supported_kx_group;
+
 /**
  * A read-only view on a Rust `&str`. The contents are guaranteed to be valid
  * UTF-8. As an additional guarantee on top of Rust's normal UTF-8 guarantee,
@@ -267,16 +309,80 @@ typedef void *rustls_verify_server_cert_user_data;
 /**
  * Input to a custom certificate verifier callback. See
  * rustls_client_config_builder_dangerous_set_certificate_verifier().
+ *
+ * The negotiated ALPN protocol is not included: rustls calls the verifier
+ * without any access to the connection, so it can't be passed through here.
+ * To tie certificate acceptance to the application protocol, check
+ * rustls_connection_get_alpn_protocol() once the handshake completes and
+ * close the connection if the combination isn't acceptable.
  */
 typedef struct rustls_verify_server_cert_params {
   struct rustls_slice_bytes end_entity_cert_der;
   const struct rustls_slice_slice_bytes *intermediate_certs_der;
   struct rustls_str dns_name;
   struct rustls_slice_bytes ocsp_response;
+  /**
+   * The time rustls is verifying the certificate at, in seconds since the
+   * Unix epoch, or 0 if the system clock is set before the epoch.
+   */
+  uint64_t now_unix_secs;
 } rustls_verify_server_cert_params;
 
 typedef uint32_t (*rustls_verify_server_cert_callback)(rustls_verify_server_cert_user_data userdata, const struct rustls_verify_server_cert_params *params);
 
+/**
+ * A callback for rustls_client_config_builder_set_key_log_callback. It is
+ * called with a TLS secret in the NSS key log format: `label` says what
+ * kind of secret it is (e.g. "CLIENT_HANDSHAKE_TRAFFIC_SECRET"),
+ * `client_random` identifies the session and `secret` holds the secret
+ * itself. All three are only valid for the duration of the callback.
+ */
+typedef void (*rustls_key_log_callback)(void *userdata, struct rustls_str label, struct rustls_slice_bytes client_random, struct rustls_slice_bytes secret);
+
+/**
+ * A callback for rustls_client_config_builder_load_roots_from_file_with_callback.
+ * It is called once for each certificate in the file that can't be used as
+ * a trust anchor, with the certificate's DER bytes and a short description
+ * of why it was rejected. Both are only valid for the duration of the
+ * callback. Return true to skip the certificate and continue loading, or
+ * false to stop.
+ */
+typedef bool (*rustls_bad_root_callback)(void *userdata, struct rustls_slice_bytes cert, struct rustls_str reason);
+
+/**
+ * A read-only view on a Rust slice of 16-bit integers in platform endianness.
+ *
+ * This is used to pass data from rustls-ffi to callback functions provided
+ * by the user of the API.
+ * `len` indicates the number of bytes than can be safely read.
+ *
+ * The memory exposed is available as specified by the function
+ * using this in its signature. For instance, when this is a parameter to a
+ * callback, the lifetime will usually be the duration of the callback.
+ * Functions that receive one of these must not dereference the data pointer
+ * beyond the allowed lifetime.
+ */
+typedef struct rustls_slice_u16 {
+  const uint16_t *data;
+  size_t len;
+} rustls_slice_u16;
+
+/**
+ * A callback for rustls_client_config_builder_set_client_cert_resolver.
+ * `acceptable_issuers` holds the DER encoded distinguished names from the
+ * server's CertificateRequest, and `signature_schemes` the TLS signature
+ * schemes it supports. Return the certified key to authenticate with, or
+ * NULL to send no certificate.
+ */
+typedef const struct rustls_certified_key *(*rustls_client_cert_resolver_callback)(void *userdata, const struct rustls_slice_slice_bytes *acceptable_issuers, struct rustls_slice_u16 signature_schemes);
+
+/**
+ * A callback for rustls_client_config_builder_validate. It is called once
+ * for each problem found, with a human-readable description of the problem.
+ * The `message` is only valid for the duration of the callback.
+ */
+typedef void (*rustls_validate_callback)(void *userdata, struct rustls_str message);
+
 typedef size_t rustls_log_level;
 
 typedef struct rustls_log_params {
@@ -286,6 +392,98 @@ typedef struct rustls_log_params {
 
 typedef void (*rustls_log_callback)(void *userdata, const struct rustls_log_params *params);
 
+/**
+ * Prototype of a callback that can be installed on a client connection with
+ * `rustls_connection_set_new_ticket_callback`. It is invoked whenever the
+ * connection receives a new session from the server that can later be used
+ * for resumption (for TLS 1.3, once per NewSessionTicket message).
+ * `userdata` is the value passed to `rustls_connection_set_new_ticket_callback`.
+ *
+ * NOTE: the passed in `ticket` is only available during the
+ * callback invocation.
+ */
+typedef void (*rustls_new_ticket_callback)(void *userdata, const struct rustls_slice_bytes *ticket);
+
+/**
+ * A callback for rustls_connection_get_ca_names_formatted. It is called
+ * once for each certificate authority name, which is only valid for the
+ * duration of the callback.
+ */
+typedef void (*rustls_ca_name_callback)(void *userdata, struct rustls_str name);
+
+/**
+ * A snapshot of a connection's I/O state, filled in by
+ * rustls_connection_io_state.
+ * <https://docs.rs/rustls/0.20.0/rustls/struct.IoState.html>
+ */
+typedef struct rustls_io_state {
+  /**
+   * Same as rustls_connection_wants_read.
+   */
+  bool wants_read;
+  /**
+   * Same as rustls_connection_wants_write.
+   */
+  bool wants_write;
+  /**
+   * Same as rustls_connection_is_handshaking.
+   */
+  bool is_handshaking;
+  /**
+   * True if the peer has sent a close_notify alert.
+   */
+  bool peer_has_closed;
+  /**
+   * How many plaintext bytes rustls_connection_read can return without
+   * further I/O.
+   */
+  size_t plaintext_bytes_to_read;
+  /**
+   * How many bytes rustls_connection_write_tls could write right now.
+   */
+  size_t tls_bytes_to_write;
+} rustls_io_state;
+
+/**
+ * Byte counts for a connection, filled in by rustls_connection_get_stats.
+ * They count from when the connection was created, or from the last call
+ * to rustls_connection_reset_stats.
+ */
+typedef struct rustls_connection_stats {
+  /**
+   * Plaintext bytes returned by rustls_connection_read.
+   */
+  uint64_t plaintext_read;
+  /**
+   * Plaintext bytes accepted by rustls_connection_write and
+   * rustls_connection_write_early_data.
+   */
+  uint64_t plaintext_written;
+  /**
+   * TLS bytes passed in with rustls_connection_read_tls.
+   */
+  uint64_t tls_read;
+  /**
+   * TLS bytes passed out with rustls_connection_write_tls.
+   */
+  uint64_t tls_written;
+} rustls_connection_stats;
+
+/**
+ * A session to add to a client's session cache before any connections are
+ * made. See rustls_client_config_builder_preload_session_cache.
+ */
+typedef struct rustls_session_cache_entry {
+  /**
+   * The NUL-terminated DNS name of the server the session is for.
+   */
+  const char *server_name;
+  /**
+   * An encoded session, as previously passed to a rustls_new_ticket_callback.
+   */
+  struct rustls_slice_bytes session;
+} rustls_session_cache_entry;
+
 /**
  * A return value for a function that may return either success (0) or a
  * non-zero value representing an error. The values should match socket
@@ -347,24 +545,6 @@ typedef rustls_io_result (*rustls_write_vectored_callback)(void *userdata, const
  */
 typedef void *rustls_client_hello_userdata;
 
-/**
- * A read-only view on a Rust slice of 16-bit integers in platform endianness.
- *
- * This is used to pass data from rustls-ffi to callback functions provided
- * by the user of the API.
- * `len` indicates the number of bytes than can be safely read.
- *
- * The memory exposed is available as specified by the function
- * using this in its signature. For instance, when this is a parameter to a
- * callback, the lifetime will usually be the duration of the callback.
- * Functions that receive one of these must not dereference the data pointer
- * beyond the allowed lifetime.
- */
-typedef struct rustls_slice_u16 {
-  const uint16_t *data;
-  size_t len;
-} rustls_slice_u16;
-
 /**
  * The TLS Client Hello information provided to a ClientHelloCallback function.
  * `sni_name` is the SNI servername provided by the client. If the client
@@ -467,6 +647,14 @@ extern const struct rustls_supported_ciphersuite *RUSTLS_DEFAULT_CIPHER_SUITES[9
 
 extern const size_t RUSTLS_DEFAULT_CIPHER_SUITES_LEN;
 
+extern const struct rustls_supported_kx_group *RUSTLS_ALL_KX_GROUPS[3];
+
+extern const size_t RUSTLS_ALL_KX_GROUPS_LEN;
+
+extern const struct rustls_supported_kx_group *RUSTLS_DEFAULT_KX_GROUPS[3];
+
+extern const size_t RUSTLS_DEFAULT_KX_GROUPS_LEN;
+
 extern const uint16_t RUSTLS_ALL_VERSIONS[2];
 
 extern const size_t RUSTLS_ALL_VERSIONS_LEN;
@@ -490,6 +678,27 @@ rustls_result rustls_certificate_get_der(const struct rustls_certificate *cert,
                                          const uint8_t **out_der_data,
                                          size_t *out_der_len);
 
+/**
+ * Store the notBefore time of the DER-encoded certificate in the `der_len`
+ * bytes at `cert_der` in `*out`, in seconds since the Unix epoch (0 for
+ * times before the epoch). This lets C code, such as a custom certificate
+ * verifier, check a certificate's validity period without an X.509 parser
+ * of its own. Returns RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR, and leaves
+ * `*out` unchanged, if the certificate can't be parsed.
+ */
+rustls_result rustls_certificate_get_not_before_unix(const uint8_t *cert_der,
+                                                     size_t der_len,
+                                                     uint64_t *out);
+
+/**
+ * Store the notAfter time of the DER-encoded certificate in the `der_len`
+ * bytes at `cert_der` in `*out`, in seconds since the Unix epoch, as for
+ * rustls_certificate_get_not_before_unix.
+ */
+rustls_result rustls_certificate_get_not_after_unix(const uint8_t *cert_der,
+                                                    size_t der_len,
+                                                    uint64_t *out);
+
 /**
  * Return a 16-bit unsigned integer corresponding to this cipher suite's assignment from
  * <https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-4>.
@@ -531,6 +740,13 @@ size_t rustls_default_ciphersuites_len(void);
  */
 const struct rustls_supported_ciphersuite *rustls_default_ciphersuites_get_entry(size_t i);
 
+/**
+ * Return a 16-bit unsigned integer corresponding to this key exchange
+ * group's assignment from
+ * <https://www.iana.org/assignments/tls-parameters/tls-parameters.xhtml#tls-parameters-8>.
+ */
+uint16_t rustls_supported_kx_group_get_group(const struct rustls_supported_kx_group *supported_kx_group);
+
 /**
  * Build a `rustls_certified_key` from a certificate chain and a private key.
  * `cert_chain` must point to a buffer of `cert_chain_len` bytes, containing
@@ -538,7 +754,15 @@ const struct rustls_supported_ciphersuite *rustls_default_ciphersuites_get_entry
  * certificate first.
  *
  * `private_key` must point to a buffer of `private_key_len` bytes, containing
- * a PEM-encoded private key in either PKCS#1 or PKCS#8 format.
+ * a PEM-encoded private key in PKCS#1 (RSA), PKCS#8 or SEC1 (EC) format.
+ * SEC1 keys must include their curve parameters and public key, as
+ * `openssl ecparam -genkey` outputs by default. `cert_chain` must
+ * contain at least one certificate, or CertificateParseError is
+ * returned.
+ *
+ * This checks that the private key is usable for signing, but not that
+ * it matches the end-entity certificate; call
+ * `rustls_certified_key_verify_consistency` for that.
  *
  * On success, this writes a pointer to the newly created
  * `rustls_certified_key` in `certified_key_out`. That pointer must later
@@ -577,8 +801,9 @@ const struct rustls_certificate *rustls_certified_key_get_certificate(const stru
 
 /**
  * Create a copy of the rustls_certified_key with the given OCSP response data
- * as DER encoded bytes. The OCSP response may be given as NULL to clear any
- * possibly present OCSP data from the cloned key.
+ * as DER encoded bytes. The OCSP response may be given as NULL, or as a
+ * zero-length slice, to clear any possibly present OCSP data from the
+ * cloned key.
  * The cloned key is independent from its original and needs to be freed
  * by the application.
  */
@@ -586,6 +811,18 @@ rustls_result rustls_certified_key_clone_with_ocsp(const struct rustls_certified
                                                    const struct rustls_slice_bytes *ocsp_response,
                                                    const struct rustls_certified_key **cloned_key_out);
 
+/**
+ * Check that the private key in a rustls_certified_key matches the public
+ * key in its end-entity certificate, by signing a test message with the
+ * private key and verifying the signature against the certificate. This
+ * catches swapped or mismatched key and certificate files at configuration
+ * time, rather than as a handshake failure. Returns
+ * RUSTLS_RESULT_KEY_CERT_MISMATCH if they don't match, and
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the end-entity certificate is
+ * missing or can't be parsed. The rest of the chain is not checked.
+ */
+rustls_result rustls_certified_key_verify_consistency(const struct rustls_certified_key *certified_key);
+
 /**
  * "Free" a certified_key previously returned from
  * rustls_certified_key_build. Since certified_key is actually an
@@ -619,6 +856,21 @@ rustls_result rustls_root_cert_store_add_pem(struct rustls_root_cert_store *stor
                                              size_t pem_len,
                                              bool strict);
 
+/**
+ * Create a rustls_root_cert_store holding the certificates from every file
+ * in the directory named by `path` whose name ends in ".pem" or ".crt".
+ * Each file may contain any number of PEM-encoded certificates. Files that
+ * can't be read or parsed, and certificates that can't be used as trust
+ * anchors, are skipped; subdirectories are not searched. If `count_out` is
+ * not NULL, the number of certificates added is stored there.
+ *
+ * Caller owns the memory and must eventually call
+ * rustls_root_cert_store_free. Returns NULL if `path` is NULL or the
+ * directory can't be read.
+ */
+struct rustls_root_cert_store *rustls_root_cert_store_from_dir(const char *path,
+                                                               size_t *count_out);
+
 /**
  * Free a rustls_root_cert_store previously returned from rustls_root_cert_store_builder_build.
  * Calling with NULL is fine. Must not be called twice with the same value.
@@ -694,6 +946,9 @@ struct rustls_client_config_builder *rustls_client_config_builder_new(void);
  *
  * `versions` will only be used during the call and the application retains
  * ownership. `len` is the number of consecutive `uint16_t` pointed to by `versions`.
+ *
+ * This uses rustls' default key exchange groups; see
+ * rustls_client_config_builder_new_custom_ex to choose them.
  */
 rustls_result rustls_client_config_builder_new_custom(const struct rustls_supported_ciphersuite *const *cipher_suites,
                                                       size_t cipher_suites_len,
@@ -701,14 +956,63 @@ rustls_result rustls_client_config_builder_new_custom(const struct rustls_suppor
                                                       size_t tls_versions_len,
                                                       struct rustls_client_config_builder **builder_out);
 
+/**
+ * Create a rustls_client_config_builder, as
+ * rustls_client_config_builder_new_custom does, with the key exchange
+ * groups to offer given in preference order. `kx_groups` must point to
+ * an array containing `kx_groups_len` pointers to
+ * `rustls_supported_kx_group`, for instance from the provided arrays
+ * RUSTLS_DEFAULT_KX_GROUPS or RUSTLS_ALL_KX_GROUPS. An empty list returns
+ * RUSTLS_RESULT_INVALID_PARAMETER.
+ *
+ * The arrays are only used during the call and the application retains
+ * ownership.
+ */
+rustls_result rustls_client_config_builder_new_custom_ex(const struct rustls_supported_ciphersuite *const *cipher_suites,
+                                                         size_t cipher_suites_len,
+                                                         const uint16_t *tls_versions,
+                                                         size_t tls_versions_len,
+                                                         const struct rustls_supported_kx_group *const *kx_groups,
+                                                         size_t kx_groups_len,
+                                                         struct rustls_client_config_builder **builder_out);
+
+/**
+ * Create a rustls_client_config_builder from a declarative profile, so TLS
+ * policy can be managed as data rather than code. Caller owns the memory
+ * and must eventually call rustls_client_config_builder_build, then free
+ * the resulting rustls_client_config.
+ *
+ * `profile` is a NUL-terminated UTF-8 string with one `key = value`
+ * setting per line. Empty lines and lines starting with `#` are ignored.
+ * List values are separated by commas. The supported keys are:
+ *
+ * - `versions`: protocol versions, `TLSv1.2` and/or `TLSv1.3`.
+ * - `cipher_suites`: cipher suite names in preference order, as returned
+ *   by rustls_supported_ciphersuite_get_name, e.g. `TLS13_AES_256_GCM_SHA384`.
+ * - `alpn`: ALPN protocol IDs in preference order, e.g. `h2, http/1.1`.
+ *   Each must be at most 255 bytes long.
+ * - `roots`: path to a file of PEM-formatted trusted root certificates.
+ * - `enable_sni`: `true` or `false`.
+ *
+ * Settings that are not given keep the defaults of
+ * rustls_client_config_builder_new. Unknown keys, malformed lines and
+ * unknown values result in RUSTLS_RESULT_INVALID_PARAMETER. Errors loading
+ * `roots` are reported as in rustls_client_config_builder_load_roots_from_file.
+ * If this returns an error, `builder_out` is left unchanged.
+ */
+rustls_result rustls_client_config_builder_from_profile(const char *profile,
+                                                        struct rustls_client_config_builder **builder_out);
+
 /**
  * Set a custom server certificate verifier.
  *
  * The callback must not capture any of the pointers in its
  * rustls_verify_server_cert_params.
- * If `userdata` has been set with rustls_connection_set_userdata, it
- * will be passed to the callback. Otherwise the userdata param passed to
- * the callback will be NULL.
+ * If `userdata` has been set with
+ * rustls_client_config_builder_set_verifier_userdata, it will be passed to
+ * the callback. Otherwise, if `userdata` has been set with
+ * rustls_connection_set_userdata, that will be passed. Otherwise the
+ * userdata param passed to the callback will be NULL.
  *
  * The callback must be safe to call on any thread at any time, including
  * multiple concurrent calls. So, for instance, if the callback mutates
@@ -716,28 +1020,128 @@ rustls_result rustls_client_config_builder_new_custom(const struct rustls_suppor
  * to make such mutation safe.
  *
  * The callback receives certificate chain information as raw bytes.
- * Currently this library offers no functions for C code to parse the
- * certificates, so you'll need to bring your own certificate parsing library
- * if you need to parse them.
+ * This library only offers simple lookups on them, such as
+ * rustls_certificate_get_not_before_unix and
+ * rustls_certificate_get_not_after_unix for the validity period; for
+ * anything more you'll need to bring your own certificate parsing library.
  *
  * If you intend to write a verifier that accepts all certificates, be aware
  * that special measures are required for IP addresses. Rustls currently
  * (0.20.0) doesn't support building a ClientConnection with an IP address
  * (because it's not a valid DnsNameRef). One workaround is to detect IP
- * addresses and rewrite them to `example.invalid`, and _also_ to disable
- * SNI via rustls_client_config_builder_set_enable_sni (IP addresses don't
+ * addresses and connect with rustls_client_connection_new_anonymous, which
+ * uses the name `example.invalid` and disables SNI (IP addresses don't
  * need SNI).
  *
  * If the custom verifier accepts the certificate, it should return
  * RUSTLS_RESULT_OK. Otherwise, it may return any other rustls_result error.
  * Feel free to use an appropriate error from the RUSTLS_RESULT_CERT_*
  * section.
+ * RUSTLS_RESULT_DEFER_TO_WEB_PKI is only meaningful with
+ * rustls_client_config_builder_set_certificate_verifier_with_roots; here
+ * it rejects the certificate like any other error.
  *
  * <https://docs.rs/rustls/0.20.0/rustls/client/struct.DangerousClientConfig.html#method.set_certificate_verifier>
  */
 rustls_result rustls_client_config_builder_dangerous_set_certificate_verifier(struct rustls_client_config_builder *config_builder,
                                                                               rustls_verify_server_cert_callback callback);
 
+/**
+ * Set a server certificate verifier that runs `callback` for extra
+ * checks on top of standard WebPKI validation against `roots`. The
+ * callback is called as for
+ * rustls_client_config_builder_dangerous_set_certificate_verifier, and
+ * runs first:
+ *
+ * - RUSTLS_RESULT_DEFER_TO_WEB_PKI means the callback's own checks
+ *   passed; the certificate is then verified against `roots`, and that
+ *   decides the outcome.
+ * - RUSTLS_RESULT_OK accepts the certificate without WebPKI validation.
+ * - Any other value rejects it, as for
+ *   rustls_client_config_builder_dangerous_set_certificate_verifier.
+ *
+ * A callback that only adds policy checks, such as requiring an
+ * organization in the subject, should return RUSTLS_RESULT_DEFER_TO_WEB_PKI
+ * rather than RUSTLS_RESULT_OK when they pass. The roots are copied, so
+ * `roots` may be freed afterwards.
+ */
+rustls_result rustls_client_config_builder_set_certificate_verifier_with_roots(struct rustls_client_config_builder *config_builder,
+                                                                               const struct rustls_root_cert_store *roots,
+                                                                               rustls_verify_server_cert_callback callback);
+
+/**
+ * Use a custom server certificate verifier for connections to one host.
+ * When the server name passed to rustls_client_connection_new matches
+ * `hostname` (a NUL-terminated string, compared case-insensitively),
+ * `callback` verifies the server's certificate instead of the builder's
+ * default verifier. Connections to every other host keep using the
+ * default verifier set with the other functions on this builder.
+ *
+ * This allows, for instance, pinning the certificate of one host while
+ * trusting a root store for all others. `callback` follows the same
+ * rules as for rustls_client_config_builder_dangerous_set_certificate_verifier,
+ * including how it receives userdata.
+ *
+ * Calling this again with the same hostname replaces its verifier.
+ * Returns RUSTLS_RESULT_INVALID_DNS_NAME_ERROR if `hostname` isn't a
+ * valid DNS name.
+ */
+rustls_result rustls_client_config_builder_set_per_host_verifier(struct rustls_client_config_builder *config_builder,
+                                                                 const char *hostname,
+                                                                 rustls_verify_server_cert_callback callback);
+
+/**
+ * Set the userdata passed to every certificate verifier callback on this
+ * builder, set with
+ * rustls_client_config_builder_dangerous_set_certificate_verifier,
+ * rustls_client_config_builder_set_certificate_verifier_with_roots or
+ * rustls_client_config_builder_set_per_host_verifier. It
+ * takes precedence over userdata set with rustls_connection_set_userdata,
+ * and unlike that, it doesn't depend on thread-local storage, so it
+ * reaches the callback even when certificate verification runs on a
+ * different thread than the rustls_connection_read_tls or
+ * rustls_connection_process_new_packets call that triggered it.
+ *
+ * This may be called before or after setting the verifier. Passing NULL
+ * goes back to the userdata set with rustls_connection_set_userdata.
+ *
+ * The pointer is not dereferenced by rustls, but it must stay valid for
+ * as long as any rustls_client_config built from this builder, or any
+ * connection made from such a config, is in use. Since the callback may
+ * be called concurrently from several connections, any state behind it
+ * must be safe to share between threads.
+ */
+void rustls_client_config_builder_set_verifier_userdata(struct rustls_client_config_builder *config_builder,
+                                                         void *userdata);
+
+/**
+ * Remove any certificate verifier or trusted roots configured on the
+ * builder, including per-host verifiers, returning it to its initial
+ * state, in which all server certificates are rejected. This lets
+ * configuration code undo a dangerous verifier installed earlier; a new
+ * verifier or roots must be configured before the resulting config can
+ * connect to anything.
+ */
+void rustls_client_config_builder_reset_verifier(struct rustls_client_config_builder *config_builder);
+
+/**
+ * Trust exactly one certificate: connections succeed only if the server's
+ * end-entity certificate is byte-for-byte equal to the DER-encoded
+ * certificate in `cert_der` (of length `cert_der_len`). This is intended
+ * for development and internal tooling using a self-signed certificate,
+ * and is safer than accepting all certificates.
+ *
+ * By design, the server name, validity period and issuer of the certificate
+ * are not checked. The server must still prove possession of the
+ * certificate's private key during the handshake.
+ *
+ * This replaces any previously configured verifier or trusted roots. The
+ * certificate is copied, so the caller can free `cert_der` afterwards.
+ */
+rustls_result rustls_client_config_builder_trust_self_signed(struct rustls_client_config_builder *config_builder,
+                                                             const uint8_t *cert_der,
+                                                             size_t cert_der_len);
+
 /**
  * Use the trusted root certificates from the provided store.
  *
@@ -749,6 +1153,45 @@ rustls_result rustls_client_config_builder_dangerous_set_certificate_verifier(st
 rustls_result rustls_client_config_builder_use_roots(struct rustls_client_config_builder *config_builder,
                                                      const struct rustls_root_cert_store *roots);
 
+/**
+ * Use the trusted root certificates from the provided store, as
+ * rustls_client_config_builder_use_roots does, and also reject server
+ * certificates that are listed as revoked. `crl_ders` points to `crls_len`
+ * DER-encoded certificate revocation lists (CRLs). A certificate in the
+ * server's chain counts as revoked if a CRL with the same issuer name lists
+ * its serial number.
+ *
+ * Each CRL must be signed by a root in `roots` whose subject is the CRL's
+ * issuer; CRLs issued by intermediate CAs aren't supported. Once a CRL is
+ * past its nextUpdate time, certificates from its issuer are rejected
+ * until the CRLs are replaced, so callers must keep them current. The
+ * CRLs are copied, so the caller can free them afterwards.
+ *
+ * On error, the builder is left unchanged. Returns
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if any CRL can't be parsed,
+ * RUSTLS_RESULT_CERT_INVALID_SIGNATURE_TYPE if one uses an unsupported
+ * signature algorithm, and RUSTLS_RESULT_CERT_INVALID_SIGNATURE if one
+ * isn't signed by a matching root.
+ */
+rustls_result rustls_client_config_builder_use_roots_with_crls(struct rustls_client_config_builder *config_builder,
+                                                               const struct rustls_root_cert_store *roots,
+                                                               const struct rustls_slice_bytes *crl_ders,
+                                                               size_t crls_len);
+
+/**
+ * Pin the server's end-entity certificate: in addition to the normal
+ * certificate verification (for instance against the roots given to
+ * rustls_client_config_builder_use_roots), require the SHA-256 hash of
+ * the DER-encoded end-entity certificate to equal `sha256`. Certificates
+ * that don't match are rejected with RUSTLS_RESULT_CERT_INVALID_DATA.
+ * The pin applies to whichever verifier the builder has when it's built,
+ * so it may be set before or after the roots. `sha256_len` must be 32,
+ * or RUSTLS_RESULT_INVALID_PARAMETER is returned.
+ */
+rustls_result rustls_client_config_builder_set_pinned_cert_sha256(struct rustls_client_config_builder *config_builder,
+                                                                  const uint8_t *sha256,
+                                                                  size_t sha256_len);
+
 /**
  * Add trusted root certificates from the named file, which should contain
  * PEM-formatted certificates.
@@ -756,6 +1199,86 @@ rustls_result rustls_client_config_builder_use_roots(struct rustls_client_config
 rustls_result rustls_client_config_builder_load_roots_from_file(struct rustls_client_config_builder *config_builder,
                                                                 const char *filename);
 
+/**
+ * Add trusted root certificates from the named file, which should contain
+ * PEM-formatted certificates. This behaves like
+ * rustls_client_config_builder_load_roots_from_file, but if a certificate
+ * can't be used, its 0-based position in the file is written to
+ * `first_failed_index_out` along with the
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR result, to help track down the
+ * bad entry in a large bundle. Otherwise SIZE_MAX is written there.
+ * `first_failed_index_out` may be NULL.
+ */
+rustls_result rustls_client_config_builder_load_roots_from_file_ex(struct rustls_client_config_builder *config_builder,
+                                                                   const char *filename,
+                                                                   size_t *first_failed_index_out);
+
+/**
+ * Add trusted root certificates from the named file, which should contain
+ * PEM-formatted certificates, skipping any certificates that can't be
+ * used instead of failing. The number of certificates added and skipped
+ * are stored in `added_out` and `failed_out`. This suits large bundles
+ * like the Mozilla CA set, which may contain a certificate rustls can't
+ * parse; rustls_client_config_builder_load_roots_from_file rejects those.
+ *
+ * Returns RUSTLS_RESULT_IO if the file can't be read or contains no
+ * certificates, and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if none of its
+ * certificates can be used. In those cases the builder is left
+ * unchanged; the counts are stored whenever the file could be read.
+ */
+rustls_result rustls_client_config_builder_load_roots_from_file_lenient(struct rustls_client_config_builder *config_builder,
+                                                                        const char *filename,
+                                                                        size_t *added_out,
+                                                                        size_t *failed_out);
+
+/**
+ * Add trusted root certificates from every file in the directory named
+ * `dirname` whose name ends in ".pem" or ".crt", such as a system trust
+ * store directory like /etc/ssl/certs. Files that can't be read or
+ * parsed, and certificates that can't be used, are skipped, as for
+ * rustls_root_cert_store_from_dir. Returns RUSTLS_RESULT_IO if the
+ * directory can't be read or yields no usable certificates; the builder
+ * is then left unchanged.
+ */
+rustls_result rustls_client_config_builder_load_roots_from_dir(struct rustls_client_config_builder *config_builder,
+                                                               const char *dirname);
+
+/**
+ * Add trusted root certificates from `pem_bytes`, a buffer of length
+ * `pem_len` holding PEM-formatted certificates, e.g. a CA bundle embedded
+ * in the program or fetched over the network. This behaves like
+ * rustls_client_config_builder_load_roots_from_file, without the need to
+ * write the PEM to a file first. The buffer is not retained, so the
+ * caller can free it afterwards.
+ *
+ * Returns RUSTLS_RESULT_IO if the buffer contains no certificates, and
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if any of them can't be used.
+ * In either case the builder is left unchanged.
+ */
+rustls_result rustls_client_config_builder_load_roots_from_buffer(struct rustls_client_config_builder *config_builder,
+                                                                  const uint8_t *pem_bytes,
+                                                                  size_t pem_len);
+
+/**
+ * Add trusted root certificates from the named file, which should contain
+ * PEM-formatted certificates, calling `callback` with `userdata` for each
+ * certificate that can't be used. If the callback returns true for every
+ * bad certificate, they are skipped and the remaining certificates are
+ * used. If it returns false, loading stops, the builder is left unchanged
+ * and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR is returned. This is useful for
+ * auditing large CA bundles, where
+ * rustls_client_config_builder_load_roots_from_file only reports that
+ * some certificate failed.
+ *
+ * Returns RUSTLS_RESULT_IO if the file can't be read or contains no
+ * certificates, and RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if every
+ * certificate was skipped. In those cases the builder is left unchanged.
+ */
+rustls_result rustls_client_config_builder_load_roots_from_file_with_callback(struct rustls_client_config_builder *config_builder,
+                                                                             const char *filename,
+                                                                             rustls_bad_root_callback callback,
+                                                                             void *userdata);
+
 /**
  * Set the ALPN protocol list to the given protocols. `protocols` must point
  * to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
@@ -766,6 +1289,10 @@ rustls_result rustls_client_config_builder_load_roots_from_file(struct rustls_cl
  *
  * This function makes a copy of the data in `protocols` and does not retain
  * any pointers, so the caller can free the pointed-to memory after calling.
+ * Protocol IDs are copied byte for byte and may contain any byte values,
+ * including NUL and non-ASCII bytes. Each must be between 1 and 255 bytes
+ * long, as required on the wire; otherwise this returns
+ * RUSTLS_RESULT_INVALID_PARAMETER and leaves the builder unchanged.
  *
  * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.alpn_protocols>
  */
@@ -773,6 +1300,115 @@ rustls_result rustls_client_config_builder_set_alpn_protocols(struct rustls_clie
                                                               const struct rustls_slice_bytes *protocols,
                                                               size_t len);
 
+/**
+ * Set the ALPN protocol list, in the client's order of preference. This is
+ * the same as `rustls_client_config_builder_set_alpn_protocols`, but
+ * documents the ordering guarantee: protocols are offered to the server in
+ * exactly the order given, most preferred first. A server that honors
+ * client preference will pick the first entry it supports.
+ *
+ * <https://datatracker.ietf.org/doc/html/rfc7301#section-3.1>
+ */
+rustls_result rustls_client_config_builder_set_alpn_protocols_with_fallback_order(struct rustls_client_config_builder *builder,
+                                                                                 const struct rustls_slice_bytes *protocols,
+                                                                                 size_t len);
+
+/**
+ * Remove cipher suites from the builder's current set, leaving the rest in
+ * their existing preference order. `suite_ids` must point to an array of
+ * `len` IANA cipher suite identifiers, as returned by
+ * `rustls_supported_ciphersuite_get_suite`. Identifiers that are not in the
+ * current set are ignored. Returns `RUSTLS_RESULT_INVALID_PARAMETER`, and
+ * leaves the builder unchanged, if this would leave no cipher suites usable
+ * with the builder's protocol versions.
+ */
+rustls_result rustls_client_config_builder_disable_cipher_suites(struct rustls_client_config_builder *builder,
+                                                                 const uint16_t *suite_ids,
+                                                                 size_t len);
+
+/**
+ * Configure the builder to resemble a web browser's ClientHello, for
+ * testing and automation against servers that fingerprint TLS clients.
+ * `profile` is a `rustls_browser_profile`. This sets the cipher suite
+ * order, the protocol versions and the ALPN protocol list, replacing any
+ * previous settings for them.
+ *
+ * The result is only an approximation. Browsers offer cipher suites,
+ * extensions, key exchange groups and GREASE values that rustls doesn't
+ * support or doesn't let callers control, and rustls picks its own
+ * extension order. Fingerprints that hash the whole ClientHello, like
+ * JA3, will still differ from the real browser.
+ *
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER for an unknown `profile`.
+ */
+rustls_result rustls_client_config_builder_apply_fingerprint_profile(struct rustls_client_config_builder *builder,
+                                                                     uint32_t profile);
+
+/**
+ * Replace the default in-memory session cache with one that evicts
+ * according to `policy`, one of the `rustls_session_eviction_policy`
+ * values. Like the default, the cache holds up to 256 entries. With LRU,
+ * looking up a session for resumption counts as a use; with FIFO, only
+ * storing one does.
+ *
+ * `max_age_secs` is a hint for how long a cached entry stays usable: an
+ * older entry is dropped instead of being offered for resumption. Pass 0
+ * to keep entries until they are evicted. The server's ticket lifetime
+ * still applies on top of this.
+ *
+ * Returns RUSTLS_RESULT_INVALID_PARAMETER for an unknown `policy`.
+ */
+rustls_result rustls_client_config_builder_set_session_eviction(struct rustls_client_config_builder *builder,
+                                                                uint32_t policy,
+                                                                uint64_t max_age_secs);
+
+/**
+ * Register callbacks for persistence of TLS sessions, so that sessions
+ * can be kept outside the process, e.g. to resume across restarts. The
+ * keys identify the server a session is for, and the values are the
+ * encoded sessions, both opaque byte strings. Values are highly
+ * sensitive data, containing enough information to break the security
+ * of the connections involved.
+ *
+ * This replaces the in-memory cache, including one chosen with
+ * rustls_client_config_builder_set_session_eviction. Sessions added with
+ * rustls_client_config_builder_preload_session_cache are passed to the
+ * put callback, with NULL userdata, when the config is built.
+ *
+ * rustls looks up the session to resume while creating a connection,
+ * before rustls_connection_set_userdata can be called, so the get callback
+ * is passed the userdata given to rustls_client_connection_new_full, or
+ * NULL for the other constructors. Later calls, including the put
+ * callback for sessions the server sends, are passed the userdata set with
+ * rustls_connection_set_userdata, or NULL if none was set.
+ */
+rustls_result rustls_client_config_builder_set_persistence(struct rustls_client_config_builder *builder,
+                                                            rustls_session_store_get_callback get_cb,
+                                                            rustls_session_store_put_callback put_cb);
+
+/**
+ * Add previously saved sessions to the client's session cache, so that the
+ * first connection to each of those servers can resume instead of doing a
+ * full handshake. `entries` must point to an array of `len`
+ * `rustls_session_cache_entry`. Each session must be one that was passed
+ * to a `rustls_new_ticket_callback` for a connection to the same server
+ * name; the encoding is internal to rustls and sessions saved by a
+ * different rustls-ffi version may be ignored. A session the server no
+ * longer accepts just means a full handshake.
+ *
+ * The sessions are added when the config is built, to the cache chosen
+ * with rustls_client_config_builder_set_session_eviction if there is one,
+ * and the cache's size limit applies to them. A later entry for the same
+ * server name replaces an earlier one. This copies the data in `entries`
+ * and does not retain any pointers.
+ *
+ * Returns RUSTLS_RESULT_INVALID_DNS_NAME_ERROR, and adds none of the
+ * entries, if any server name is not a valid DNS name.
+ */
+rustls_result rustls_client_config_builder_preload_session_cache(struct rustls_client_config_builder *builder,
+                                                                 const struct rustls_session_cache_entry *entries,
+                                                                 size_t len);
+
 /**
  * Enable or disable SNI.
  * <https://docs.rs/rustls/0.20.0/rustls/struct.ClientConfig.html#structfield.enable_sni>
@@ -780,6 +1416,66 @@ rustls_result rustls_client_config_builder_set_alpn_protocols(struct rustls_clie
 void rustls_client_config_builder_set_enable_sni(struct rustls_client_config_builder *config,
                                                  bool enable);
 
+/**
+ * Enable or disable sending TLS 1.3 early data (0-RTT) with
+ * rustls_connection_write_early_data. The default is disabled. Early data
+ * can only be sent when resuming a TLS 1.3 session from a server that
+ * allows it, and it can be replayed by an attacker, so only send requests
+ * that are safe to process more than once.
+ * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.enable_early_data>
+ */
+void rustls_client_config_builder_set_enable_early_data(struct rustls_client_config_builder *config,
+                                                       bool enable);
+
+/**
+ * Set the maximum size of the TLS records this client sends, including
+ * the 5-byte record header, so that each record fits the buffers of a
+ * memory-constrained peer. `size` must be between 32 and 16389 (the
+ * largest TLS record); otherwise this returns
+ * RUSTLS_RESULT_INVALID_PARAMETER and leaves the setting unchanged. A
+ * `size` of 0 restores the default, which is the largest record size.
+ * This does not negotiate the max_fragment_length extension, so it
+ * doesn't limit the records the server sends.
+ * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.max_fragment_size>
+ */
+rustls_result rustls_client_config_builder_set_max_fragment_size(struct rustls_client_config_builder *config,
+                                                                 size_t size);
+
+/**
+ * Register a callback that receives the TLS secrets of each connection
+ * made with this config, in the NSS key log format that tools like
+ * Wireshark use to decrypt captured traffic. Anyone with these secrets
+ * can decrypt the connection, so only enable this for debugging.
+ *
+ * If `userdata` has been set with rustls_connection_set_userdata, it
+ * will be passed to the callback. Otherwise the userdata param passed to
+ * the callback will be NULL.
+ *
+ * The callback must be safe to call on any thread at any time, including
+ * multiple concurrent calls. So, for instance, if the callback mutates
+ * userdata (or other shared state), it must use synchronization primitives
+ * to make such mutation safe.
+ *
+ * This replaces any key log set earlier, including one installed by
+ * rustls_client_config_builder_enable_sslkeylogfile.
+ * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConfig.html#structfield.key_log>
+ */
+rustls_result rustls_client_config_builder_set_key_log_callback(struct rustls_client_config_builder *builder,
+                                                                rustls_key_log_callback callback);
+
+/**
+ * Log the TLS secrets of each connection made with this config to the
+ * file named by the SSLKEYLOGFILE environment variable, in the NSS key
+ * log format. The variable is read and the file opened (for appending)
+ * during this call; if it isn't set, nothing is logged. As with
+ * rustls_client_config_builder_set_key_log_callback, this is only meant
+ * for debugging.
+ *
+ * This replaces any key log set earlier.
+ * <https://docs.rs/rustls/0.20.0/rustls/struct.KeyLogFile.html>
+ */
+rustls_result rustls_client_config_builder_enable_sslkeylogfile(struct rustls_client_config_builder *builder);
+
 /**
  * Provide the configuration a list of certificates where the connection
  * will select the first one that is compatible with the server's signature
@@ -798,6 +1494,48 @@ rustls_result rustls_client_config_builder_set_certified_key(struct rustls_clien
                                                              const struct rustls_certified_key *const *certified_keys,
                                                              size_t certified_keys_len);
 
+/**
+ * Register a callback that chooses the client certificate to send when a
+ * server requests client authentication. The callback receives the DER
+ * encoded distinguished names of the CAs the server accepts (possibly
+ * none) and the signature schemes it supports, and returns the
+ * `rustls_certified_key` to use, or NULL to continue without a client
+ * certificate. `rustls_certified_key_free` may be called on the returned
+ * key afterwards; the connection keeps its own reference.
+ *
+ * If `userdata` has been set with rustls_connection_set_userdata, it
+ * will be passed to the callback. Otherwise the userdata param passed to
+ * the callback will be NULL. The issuers and signature schemes are only
+ * valid for the duration of the callback.
+ *
+ * The callback must be safe to call on any thread at any time, including
+ * multiple concurrent calls. So, for instance, if the callback mutates
+ * userdata (or other shared state), it must use synchronization primitives
+ * to make such mutation safe.
+ *
+ * EXPERIMENTAL: installing a client authentication callback will replace any
+ * configured certified keys and vice versa. It is not permitted to pass a
+ * NULL value for `callback`.
+ */
+rustls_result rustls_client_config_builder_set_client_cert_resolver(struct rustls_client_config_builder *builder,
+                                                                    rustls_client_cert_resolver_callback callback);
+
+/**
+ * Check the builder for configuration problems without consuming it.
+ * If `callback` is non-NULL, it is called once for each problem found,
+ * with `userdata` and a description of the problem. Returns
+ * RUSTLS_RESULT_OK if no problems were found, and
+ * RUSTLS_RESULT_INVALID_PARAMETER otherwise.
+ *
+ * Currently this detects a missing certificate verifier and ALPN
+ * protocol IDs that are empty or longer than 255 bytes. Incompatible
+ * combinations of cipher suites and protocol versions are already
+ * rejected by rustls_client_config_builder_new_custom.
+ */
+rustls_result rustls_client_config_builder_validate(const struct rustls_client_config_builder *builder,
+                                                    rustls_validate_callback callback,
+                                                    void *userdata);
+
 /**
  * Turn a *rustls_client_config_builder (mutable) into a const *rustls_client_config
  * (read-only).
@@ -835,6 +1573,68 @@ rustls_result rustls_client_connection_new(const struct rustls_client_config *co
                                            const char *hostname,
                                            struct rustls_connection **conn_out);
 
+/**
+ * Create a new rustls_connection containing a client connection, as
+ * rustls_client_connection_new does, but with SNI enabled or disabled for
+ * this connection according to `enable_sni`, regardless of the setting
+ * from rustls_client_config_builder_set_enable_sni. This lets one config
+ * be shared between connections that do and don't send SNI, for instance
+ * when some servers are reached through a raw IP alias. `config` is not
+ * modified. Ownership of the connection is as for
+ * rustls_client_connection_new.
+ */
+rustls_result rustls_client_connection_new_with_options(const struct rustls_client_config *config,
+                                                        const char *hostname,
+                                                        bool enable_sni,
+                                                        struct rustls_connection **conn_out);
+
+/**
+ * Create a new rustls_connection containing a client connection for a
+ * server that has no hostname, such as the far end of a tunnel or a bare
+ * IP address. The connection doesn't send SNI, and the server name seen
+ * by the certificate verifier is the placeholder `example.invalid`.
+ *
+ * The config must use a custom certificate verifier (see
+ * rustls_client_config_builder_dangerous_set_certificate_verifier) that
+ * authenticates the server some other way. With a WebPKI verifier the
+ * handshake will fail, since no valid certificate can match the
+ * placeholder name. Ownership of the connection is as for
+ * rustls_client_connection_new.
+ *
+ * Anonymous connections never store or resume sessions. They would all
+ * share the placeholder name, so one endpoint could otherwise resume a
+ * session set up with another, skipping the verifier that is the only
+ * check of which endpoint this is. The config's session store and new
+ * ticket callback are therefore not used.
+ */
+rustls_result rustls_client_connection_new_anonymous(const struct rustls_client_config *config,
+                                                     struct rustls_connection **conn_out);
+
+/**
+ * Create a new rustls_connection containing a client connection, as
+ * rustls_client_connection_new does, with its userdata and ALPN
+ * protocols set before it is handed to the caller. This saves separate
+ * configuration calls between creating a connection and first using it.
+ *
+ * `server_name` is the NUL-terminated hostname to connect to and must not
+ * be NULL. `userdata` is set as by rustls_connection_set_userdata; NULL
+ * leaves it unset, which is the same as the default. If `alpn_override`
+ * is NULL, the ALPN protocols from `config` are offered. Otherwise the
+ * `alpn_len` protocols it points to are offered instead, as for
+ * rustls_client_config_builder_set_alpn_protocols, and `config` is not
+ * modified; an empty list disables ALPN for this connection. As there,
+ * each protocol must be 1 to 255 bytes long, or
+ * RUSTLS_RESULT_INVALID_PARAMETER is returned. The protocols are copied,
+ * so the caller can free them afterwards.
+ * Ownership of the connection is as for rustls_client_connection_new.
+ */
+rustls_result rustls_client_connection_new_full(const struct rustls_client_config *config,
+                                                const char *server_name,
+                                                void *userdata,
+                                                const struct rustls_slice_bytes *alpn_override,
+                                                size_t alpn_len,
+                                                struct rustls_connection **conn_out);
+
 /**
  * Set the userdata pointer associated with this connection. This will be passed
  * to any callbacks invoked by the connection, if you've set up callbacks in the config.
@@ -849,6 +1649,18 @@ void rustls_connection_set_userdata(struct rustls_connection *conn, void *userda
  */
 void rustls_connection_set_log_callback(struct rustls_connection *conn, rustls_log_callback cb);
 
+/**
+ * Set a callback to be invoked whenever this client connection receives a
+ * new session from the server that can be used for later resumption,
+ * e.g. to persist it eagerly in a shared cache. The callback is invoked
+ * during rustls_connection_process_new_packets with `userdata` and the
+ * opaque encoded session. Passing a NULL callback removes it.
+ * This has no effect on server connections.
+ */
+void rustls_connection_set_new_ticket_callback(struct rustls_connection *conn,
+                                               rustls_new_ticket_callback callback,
+                                               void *userdata);
+
 /**
  * Read some TLS bytes from the network into internal buffers. The actual network
  * I/O is performed by `callback`, which you provide. Rustls will invoke your
@@ -858,7 +1670,8 @@ void rustls_connection_set_log_callback(struct rustls_connection *conn, rustls_l
  * this is distinct from the `userdata` parameter set with
  * `rustls_connection_set_userdata`.
  * Returns 0 for success, or an errno value on error. Passes through return values
- * from callback. See rustls_read_callback for more details.
+ * from callback. See rustls_read_callback for more details. Returns ENOBUFS
+ * if the read limit set with rustls_connection_set_buffer_limits is reached.
  * <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.read_tls>
  */
 rustls_io_result rustls_connection_read_tls(struct rustls_connection *conn,
@@ -871,6 +1684,11 @@ rustls_io_result rustls_connection_read_tls(struct rustls_connection *conn,
  * `callback`, which you provide. Rustls will invoke your callback with a
  * suitable buffer containing TLS bytes to send. You don't have to write them
  * all, just as many as you can in one syscall.
+ *
+ * By default, all pending TLS records are copied into a single buffer, so
+ * one call hands the callback everything there is to send. See
+ * rustls_connection_set_record_coalescing.
+ *
  * The `userdata` parameter is passed through directly to `callback`. Note that
  * this is distinct from the `userdata` parameter set with
  * `rustls_connection_set_userdata`.
@@ -883,6 +1701,17 @@ rustls_io_result rustls_connection_write_tls(struct rustls_connection *conn,
                                              void *userdata,
                                              size_t *out_n);
 
+/**
+ * Enable or disable record coalescing for rustls_connection_write_tls. It is
+ * enabled by default: each call copies all pending TLS records into one
+ * buffer and passes it to the callback, so that several records written
+ * with rustls_connection_write go out in one syscall. When disabled, each
+ * call passes only the first pending record, avoiding the copy.
+ * rustls_connection_write_tls_vectored is not affected, since it passes
+ * every pending record to its callback without copying.
+ */
+void rustls_connection_set_record_coalescing(struct rustls_connection *conn, bool enable);
+
 /**
  * Write all available TLS bytes to the network. The actual network I/O is performed by
  * `callback`, which you provide. Rustls will invoke your callback with an array
@@ -900,6 +1729,27 @@ rustls_io_result rustls_connection_write_tls_vectored(struct rustls_connection *
                                                       void *userdata,
                                                       size_t *out_n);
 
+/**
+ * Copy the ClientHello a client connection sent into `buf`, which can
+ * hold up to `len` bytes, and store its length in `out_n`. The bytes are
+ * exactly what rustls passed to rustls_connection_write_tls (or
+ * rustls_connection_write_tls_vectored), record headers included, so they
+ * can be fed to tools that fingerprint a ClientHello.
+ *
+ * Capture starts when the connection is created and stops as soon as
+ * rustls_connection_read_tls reads any bytes from the server, so this is
+ * only meaningful before the server has responded. Call it after the
+ * first rustls_connection_write_tls has sent the whole ClientHello.
+ *
+ * Returns RUSTLS_RESULT_NOT_FOUND for server connections, or if nothing
+ * has been written yet, and RUSTLS_RESULT_INSUFFICIENT_SIZE if the
+ * captured bytes are longer than `len`.
+ */
+rustls_result rustls_connection_get_client_hello_bytes(const struct rustls_connection *conn,
+                                                       uint8_t *buf,
+                                                       size_t len,
+                                                       size_t *out_n);
+
 /**
  * Decrypt any available ciphertext from the internal buffer and put it
  * into the internal plaintext buffer, potentially making bytes available
@@ -908,6 +1758,19 @@ rustls_io_result rustls_connection_write_tls_vectored(struct rustls_connection *
  */
 rustls_result rustls_connection_process_new_packets(struct rustls_connection *conn);
 
+/**
+ * Fill in `out` with a snapshot of the connection's I/O state, collapsing
+ * the individual getters into one call for event loops that poll them
+ * every iteration. To get an up-to-date count of readable plaintext, this
+ * first processes any TLS data already passed in with
+ * rustls_connection_read_tls, exactly as
+ * rustls_connection_process_new_packets does, and returns its error if
+ * that fails. In that case `out` is not modified.
+ * <https://docs.rs/rustls/0.20.0/rustls/struct.IoState.html>
+ */
+rustls_result rustls_connection_io_state(struct rustls_connection *conn,
+                                         struct rustls_io_state *out);
+
 /**
  * <https://docs.rs/rustls/0.20.0/rustls/struct.CommonState.html#method.wants_read>
  */
@@ -923,15 +1786,78 @@ bool rustls_connection_wants_write(const struct rustls_connection *conn);
  */
 bool rustls_connection_is_handshaking(const struct rustls_connection *conn);
 
+/**
+ * Return true if a client connection's session store holds a session
+ * for its server, so that the next connection to that server made with
+ * the same config can resume instead of doing a full handshake. With
+ * TLS 1.3 the server sends its tickets after the handshake, so this may
+ * only become true after further calls to
+ * rustls_connection_process_new_packets. It reflects the store rather
+ * than this connection, so a session preloaded or stored by an earlier
+ * connection to the same server also counts, and one evicted since does
+ * not. The server may still refuse to resume.
+ *
+ * If the config uses session store callbacks, this calls the get
+ * callback with this connection's userdata. Returns false for server
+ * connections.
+ */
+bool rustls_connection_can_resume_next_time(const struct rustls_connection *conn);
+
+/**
+ * Return true if the handshake resumed an earlier session rather than
+ * doing a full handshake, for either a client or a server connection.
+ * Returns false while the connection is still handshaking.
+ *
+ * rustls doesn't report resumption itself, so this is inferred from how
+ * rustls 0.20 behaves internally: it only authenticates the server with
+ * its certificate in full handshakes, so a completed handshake counts as
+ * resumed when the client didn't call its server certificate verifier,
+ * or the server didn't choose a signature scheme for its key. A custom
+ * verifier or certificate resolver doesn't change the answer, since
+ * rustls_client_config_builder_build and rustls_server_config_builder_build
+ * wrap them to watch for these calls.
+ */
+bool rustls_connection_is_resumed(const struct rustls_connection *conn);
+
 /**
  * Sets a limit on the internal buffers used to buffer unsent plaintext (prior
- * to completing the TLS handshake) and unsent TLS records. By default, there
- * is no limit. The limit can be set at any time, even if the current buffer
- * use is higher.
+ * to completing the TLS handshake) and unsent TLS records. By default, rustls
+ * limits each of them to 64 KiB. Passing 0 for `n` removes the limit
+ * entirely, including that default, so the buffers can grow without bound.
+ * The limit can be set at any time, even if the current buffer use is higher.
+ * Once the buffers are full, rustls_connection_write accepts fewer bytes than
+ * it was given, until rustls_connection_write_tls drains them.
  * <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.set_buffer_limit>
  */
 void rustls_connection_set_buffer_limit(struct rustls_connection *conn, size_t n);
 
+/**
+ * Set separate limits on the data the connection buffers in each
+ * direction. `write_limit` is the limit set by
+ * rustls_connection_set_buffer_limit, on plaintext written before the
+ * handshake completes and on TLS records waiting for
+ * rustls_connection_write_tls.
+ *
+ * `read_limit` bounds the data received from the peer: TLS bytes passed
+ * in with rustls_connection_read_tls but not yet processed by
+ * rustls_connection_process_new_packets, plus decrypted plaintext not
+ * yet returned by rustls_connection_read (including peeked bytes). Once
+ * those reach `read_limit`, rustls_connection_read_tls returns ENOBUFS
+ * without calling its callback; process the data and read the plaintext,
+ * then try again. rustls itself has no such limit, so without one a
+ * peer can make the connection buffer any amount of data that the
+ * application doesn't read. As the check happens before each read, the
+ * buffered data can exceed `read_limit` by up to one read's worth.
+ *
+ * A limit of 0 means unlimited for that direction. By default there is
+ * no read limit, while rustls limits the write buffers to 64 KiB each;
+ * a `write_limit` of 0 removes that default as well. Both limits can be
+ * changed at any time.
+ */
+void rustls_connection_set_buffer_limits(struct rustls_connection *conn,
+                                         size_t read_limit,
+                                         size_t write_limit);
+
 /**
  * Queues a close_notify fatal alert to be sent in the next write_tls call.
  * <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.send_close_notify>
@@ -952,12 +1878,183 @@ void rustls_connection_send_close_notify(struct rustls_connection *conn);
 const struct rustls_certificate *rustls_connection_get_peer_certificate(const struct rustls_connection *conn,
                                                                         size_t i);
 
+/**
+ * Return the number of certificates the peer provided, so that C code
+ * can fetch each of them with rustls_connection_get_peer_certificate
+ * using indexes from 0 to the returned value minus one. Returns 0 if the
+ * peer hasn't sent any certificates (yet).
+ */
+size_t rustls_connection_get_peer_certificates_len(const struct rustls_connection *conn);
+
+/**
+ * Copy the DER encoding of the SubjectPublicKeyInfo of the i-th certificate
+ * provided by the peer to `buf`, which can hold up to `count` bytes, and
+ * store its length in `out_n`. Index 0 is the end entity certificate.
+ * This is useful for key pinning or DANE, where C code needs the raw public
+ * key rather than the whole certificate.
+ * Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't be parsed,
+ * and RUSTLS_RESULT_INSUFFICIENT_SIZE if the SubjectPublicKeyInfo is longer
+ * than `count`.
+ */
+rustls_result rustls_connection_get_peer_spki_der(const struct rustls_connection *conn,
+                                                  size_t i,
+                                                  uint8_t *buf,
+                                                  size_t count,
+                                                  size_t *out_n);
+
+/**
+ * Write the issuer distinguished name of the i-th certificate provided by
+ * the peer to `buf`, which can hold up to `count` bytes, as a UTF-8 string,
+ * and store its length in `out_n`. Index 0 is the end entity certificate.
+ * The output is not NUL-terminated.
+ *
+ * The name is formatted on a best-effort basis following RFC 4514: the
+ * most specific component comes first, e.g. "CN=Example CA,O=Example,C=US",
+ * and the attributes of a multi-valued component are joined with "+".
+ * CN, L, ST, O, OU, C, STREET, DC and UID are written by name, other
+ * attribute types as dotted OIDs. Special characters in string values are
+ * escaped with a backslash; values that aren't strings are written as "#"
+ * followed by the hex of their DER encoding. The result is meant for
+ * display and logging, not for comparing names.
+ *
+ * Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+ * RUSTLS_RESULT_CERTIFICATE_PARSE_ERROR if the certificate can't be parsed,
+ * and RUSTLS_RESULT_INSUFFICIENT_SIZE if the name is longer than `count`.
+ */
+rustls_result rustls_connection_get_peer_certificate_issuer(const struct rustls_connection *conn,
+                                                            size_t i,
+                                                            uint8_t *buf,
+                                                            size_t count,
+                                                            size_t *out_n);
+
+/**
+ * Return the number of seconds from now until the notAfter time of the i-th
+ * certificate provided by the peer. Index 0 is the end entity certificate.
+ * The result is negative if the certificate has already expired; the
+ * default verifier rejects such certificates, so after a successful
+ * handshake that means a custom verifier accepted it.
+ *
+ * Returns INT64_MIN if `conn` is NULL, if there is no certificate at index
+ * `i`, or if its validity period can't be parsed.
+ */
+int64_t rustls_connection_peer_certificate_seconds_until_expiry(const struct rustls_connection *conn,
+                                                                size_t i);
+
+/**
+ * Call `callback` with each certificate authority name the server sent
+ * when asking for a client certificate, formatted as described for
+ * rustls_connection_get_peer_certificate_issuer, e.g.
+ * "CN=Example CA,O=Example,C=US". This is meant for showing the user
+ * which issuers the server accepts when they choose a certificate.
+ * `userdata` is passed through to the callback.
+ *
+ * The names are available once the server's CertificateRequest has been
+ * processed by rustls_connection_process_new_packets, so a
+ * rustls_client_config_builder_set_certified_key resolver can't see them
+ * for the same handshake. Nothing is reported if the server didn't ask
+ * for a certificate or didn't name any authorities, or on a server
+ * connection. Names that can't be parsed are skipped.
+ */
+rustls_result rustls_connection_get_ca_names_formatted(const struct rustls_connection *conn,
+                                                       rustls_ca_name_callback callback,
+                                                       void *userdata);
+
+/**
+ * Copy the OCSP response the server stapled to its certificate into
+ * `buf`, which can hold up to `len` bytes, and store its length in
+ * `out_n`. This is for clients that cache or forward the response, or
+ * apply their own revocation policy after the handshake.
+ *
+ * The response is recorded when the server's certificate is verified,
+ * so it is available once rustls_connection_process_new_packets has
+ * processed the server's Certificate message. If the server didn't
+ * staple a response, the handshake was resumed (rustls doesn't verify
+ * the certificate again), or this is a server connection, `out_n` is
+ * set to 0 and RUSTLS_RESULT_OK is returned. Returns
+ * RUSTLS_RESULT_INSUFFICIENT_SIZE if the response is longer than `len`.
+ */
+rustls_result rustls_connection_get_stapled_ocsp_response(const struct rustls_connection *conn,
+                                                          uint8_t *buf,
+                                                          size_t len,
+                                                          size_t *out_n);
+
+/**
+ * Return true if any certificate provided by the peer is signed with a weak
+ * or deprecated signature algorithm: RSA, DSA or ECDSA with MD2, MD5 or
+ * SHA-1. The check covers every certificate the peer sent, including the
+ * end entity, and returns false if the peer sent no certificates.
+ *
+ * This is advisory, for scanners and compliance reporting. The default
+ * WebPKI verifier already refuses chains that rely on these algorithms, so
+ * a true result after a successful handshake usually means a custom
+ * verifier accepted the chain, or the weak signature is on a certificate
+ * that wasn't needed to build the path. Certificates that can't be parsed
+ * are skipped.
+ */
+bool rustls_connection_chain_uses_weak_signatures(const struct rustls_connection *conn);
+
+/**
+ * Return true if the DER-encoded certificate in `cert_der`, which is `len`
+ * bytes long, is part of the peer's certificate chain: either the peer
+ * sent it, or the certificates the peer sent form a chain that is valid
+ * now with `cert_der` as its trust anchor. This lets C code check that a
+ * connection chains to a particular root, such as a corporate CA, rather
+ * than to any root the verifier trusts.
+ *
+ * The check is repeated here with `cert_der` as the only trust anchor; it
+ * doesn't inspect the path the verifier built, which rustls doesn't
+ * expose. Name checks aren't repeated. Returns false if the peer sent no
+ * certificates or `cert_der` can't be parsed.
+ */
+bool rustls_connection_chain_includes_cert(const struct rustls_connection *conn,
+                                           const uint8_t *cert_der,
+                                           size_t len);
+
+/**
+ * Return true if the end-entity certificate the peer sent is valid for
+ * the NUL-terminated DNS name `name`, using the same name matching as
+ * the WebPKI verifier, including wildcards. This lets C code check names
+ * other than the one the connection was made to, such as an alternate
+ * service name.
+ *
+ * Only the name is checked: whether the certificate is trusted is up to
+ * the verifier that ran during the handshake. Returns false if the peer
+ * sent no certificates, `name` is NULL or not a valid DNS name, or the
+ * certificate can't be parsed.
+ */
+bool rustls_connection_peer_certificate_valid_for_name(const struct rustls_connection *conn,
+                                                      const char *name);
+
+/**
+ * Write the fingerprint of the i-th certificate provided by the peer to
+ * `buf`, which can hold up to `count` bytes, and store its length in `out_n`.
+ * Index 0 is the end entity certificate. The fingerprint is the hash of the
+ * DER-encoded certificate using `algorithm` (a `rustls_fingerprint_algorithm`),
+ * formatted as lowercase hex bytes separated by colons, e.g. "c2:86:be:...".
+ * The output is not NUL-terminated. A SHA-256 fingerprint takes 95 bytes.
+ *
+ * SHA-1 fingerprints are provided for comparison with legacy tooling only;
+ * they are not suitable for security decisions.
+ *
+ * Returns RUSTLS_RESULT_NOT_FOUND if there is no certificate at index `i`,
+ * RUSTLS_RESULT_INVALID_PARAMETER for an unknown `algorithm`, and
+ * RUSTLS_RESULT_INSUFFICIENT_SIZE if the fingerprint is longer than `count`.
+ */
+rustls_result rustls_connection_get_peer_certificate_fingerprint(const struct rustls_connection *conn,
+                                                                 size_t i,
+                                                                 uint32_t algorithm,
+                                                                 uint8_t *buf,
+                                                                 size_t count,
+                                                                 size_t *out_n);
+
 /**
  * Get the ALPN protocol that was negotiated, if any. Stores a pointer to a
  * borrowed buffer of bytes, and that buffer's len, in the output parameters.
  * The borrow lives as long as the connection.
  * If the connection is still handshaking, or no ALPN protocol was negotiated,
- * stores NULL and 0 in the output parameters.
+ * stores NULL and 0 in the output parameters. On a server connection this is
+ * the protocol the server selected from the client's offer.
  * The provided pointer is valid until the next mutating function call
  * affecting the connection. A mutating function call is one where the
  * first argument has type `struct rustls_connection *` (as opposed to
@@ -969,6 +2066,20 @@ void rustls_connection_get_alpn_protocol(const struct rustls_connection *conn,
                                          const uint8_t **protocol_out,
                                          size_t *protocol_out_len);
 
+/**
+ * Like rustls_connection_get_alpn_protocol, but also stores in
+ * `out_present` whether an ALPN protocol was negotiated, so callers don't
+ * have to infer it from a NULL pointer or a zero length. If `out_present`
+ * is set to false, `out_data` is set to NULL and `out_len` to 0. The
+ * borrow has the same lifetime as for rustls_connection_get_alpn_protocol.
+ *
+ * Returns RUSTLS_RESULT_NULL_PARAMETER if any parameter is NULL.
+ */
+rustls_result rustls_connection_get_alpn_protocol_ext(const struct rustls_connection *conn,
+                                                      const uint8_t **out_data,
+                                                      size_t *out_len,
+                                                      bool *out_present);
+
 /**
  * Return the TLS protocol version that has been negotiated. Before this
  * has been decided during the handshake, this will return 0. Otherwise,
@@ -986,12 +2097,54 @@ uint16_t rustls_connection_get_protocol_version(const struct rustls_connection *
  */
 const struct rustls_supported_ciphersuite *rustls_connection_get_negotiated_ciphersuite(const struct rustls_connection *conn);
 
+/**
+ * Derive `out_len` bytes of keying material from the connection's secrets
+ * into `out_buf`, as defined by RFC 5705 (RFC 8446 section 7.5 for TLS
+ * 1.3). `label` (of length `label_len`) names the use of the material.
+ * If `has_context` is false, no context value is used and `context` is
+ * ignored; otherwise the `context_len` bytes at `context`, which may be
+ * zero, are used as the context value. Note that with TLS 1.2, no
+ * context and an empty context give different results.
+ *
+ * Both ends of a connection derive the same bytes from the same label
+ * and context. Returns RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE if called
+ * before the handshake has completed.
+ * <https://docs.rs/rustls/0.20.0/rustls/enum.Connection.html#method.export_keying_material>
+ */
+rustls_result rustls_connection_export_keying_material(const struct rustls_connection *conn,
+                                                       uint8_t *out_buf,
+                                                       size_t out_len,
+                                                       const uint8_t *label,
+                                                       size_t label_len,
+                                                       const uint8_t *context,
+                                                       size_t context_len,
+                                                       bool has_context);
+
+/**
+ * Return the maximum number of bytes that TLS record framing and encryption
+ * add to each record sent or received on this connection: the record
+ * header plus, depending on the cipher suite, the explicit nonce,
+ * authentication tag and TLS 1.3 inner content type. This can be used to
+ * size buffers for encrypted data. Until a cipher suite has been
+ * negotiated, this returns the largest overhead of any supported suite.
+ */
+size_t rustls_connection_max_record_overhead(const struct rustls_connection *conn);
+
 /**
  * Write up to `count` plaintext bytes from `buf` into the `rustls_connection`.
  * This will increase the number of output bytes available to
  * `rustls_connection_write_tls`.
  * On success, store the number of bytes actually written in *out_n
  * (this may be less than `count`).
+ *
+ * Plaintext may be written before the handshake completes, for instance
+ * to queue a request right after creating a client connection. It is
+ * buffered, subject to rustls_connection_set_buffer_limit, and encrypted
+ * and sent once the handshake finishes; this is not an error. Use
+ * rustls_connection_is_handshaking to tell whether that is still pending.
+ * Functions that need a completed handshake, such as
+ * rustls_connection_export_keying_material, return
+ * RUSTLS_RESULT_HANDSHAKE_NOT_COMPLETE instead.
  * <https://docs.rs/rustls/0.20.0/rustls/struct.Writer.html#method.write>
  */
 rustls_result rustls_connection_write(struct rustls_connection *conn,
@@ -999,6 +2152,35 @@ rustls_result rustls_connection_write(struct rustls_connection *conn,
                                       size_t count,
                                       size_t *out_n);
 
+/**
+ * Write up to `count` plaintext bytes from `buf` as TLS 1.3 early data
+ * (0-RTT), to be sent along with the ClientHello. On success, store the
+ * number of bytes actually written in *out_n; this is less than `count`
+ * once the server's early data limit is reached.
+ *
+ * Early data can only be written by a client connection, before its
+ * handshake completes, when rustls_client_config_builder_set_enable_early_data
+ * is on and the connection is resuming a TLS 1.3 session whose server
+ * allows early data. Otherwise this returns RUSTLS_RESULT_NO_EARLY_DATA.
+ * The server may still reject the early data: check
+ * rustls_connection_is_early_data_accepted once the handshake completes,
+ * and if it returns false, send the data again with
+ * rustls_connection_write.
+ * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConnection.html#method.early_data>
+ */
+rustls_result rustls_connection_write_early_data(struct rustls_connection *conn,
+                                                 const uint8_t *buf,
+                                                 size_t count,
+                                                 size_t *out_n);
+
+/**
+ * Return true if the server accepted the early data a client connection
+ * sent with rustls_connection_write_early_data. This is only meaningful
+ * once the handshake has completed. Returns false for server connections.
+ * <https://docs.rs/rustls/0.20.0/rustls/client/struct.ClientConnection.html#method.is_early_data_accepted>
+ */
+bool rustls_connection_is_early_data_accepted(const struct rustls_connection *conn);
+
 /**
  * Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
  * On success, store the number of bytes read in *out_n (this may be less
@@ -1018,6 +2200,118 @@ rustls_result rustls_connection_read(struct rustls_connection *conn,
                                      size_t count,
                                      size_t *out_n);
 
+/**
+ * Copy up to `count` plaintext bytes from the `rustls_connection` into `buf`
+ * without consuming them: a subsequent rustls_connection_read or
+ * rustls_connection_peek returns the same bytes again. This is useful for
+ * sniffing the application protocol before deciding how to handle it.
+ * On success, store the number of bytes copied in *out_n (this may be less
+ * than `count` if fewer bytes are currently available).
+ *
+ * Peeked bytes are moved out of rustls into a buffer held by the
+ * connection until they are read. Received plaintext never counts
+ * against rustls_connection_set_buffer_limit, which only limits outgoing
+ * data, but peeked bytes do count against the read limit set with
+ * rustls_connection_set_buffer_limits, just like unread plaintext. Only
+ * the plaintext that is actually available is copied, so a large
+ * `count` doesn't make the connection buffer more than that.
+ *
+ * Returns RUSTLS_RESULT_PLAINTEXT_EMPTY and RUSTLS_RESULT_UNEXPECTED_EOF
+ * under the same conditions as rustls_connection_read, if no bytes
+ * have been peeked.
+ */
+rustls_result rustls_connection_peek(struct rustls_connection *conn,
+                                     uint8_t *buf,
+                                     size_t count,
+                                     size_t *out_n);
+
+/**
+ * Return the total number of plaintext bytes returned by
+ * rustls_connection_read over the life of the connection, that is, the
+ * offset of the next byte that read will return within the decrypted
+ * stream. Bytes copied by rustls_connection_peek are not counted until
+ * they are read. rustls_connection_reset_stats doesn't affect it.
+ */
+uint64_t rustls_connection_total_plaintext_read(const struct rustls_connection *conn);
+
+/**
+ * Store the number of TLS bytes the connection has written and read
+ * during the handshake in `sent` and `received`. These count the raw
+ * bytes passed through rustls_connection_write_tls and
+ * rustls_connection_read_tls, record headers included, until the
+ * handshake completes. `sent` also includes the messages rustls had
+ * queued at that point, like a client's Finished message or a TLS 1.3
+ * server's session tickets. Bytes read after the handshake completes,
+ * like session tickets arriving at a client, aren't counted. Comparing
+ * the values for full and resumed handshakes shows the fixed cost of a
+ * new connection.
+ *
+ * The counts are close but not exact: if a single
+ * rustls_connection_read_tls call returns the peer's last handshake
+ * message together with application data, that data is counted as
+ * well. They are not affected by rustls_connection_reset_stats.
+ */
+rustls_result rustls_connection_get_handshake_bytes(const struct rustls_connection *conn,
+                                                    size_t *sent,
+                                                    size_t *received);
+
+/**
+ * Fill in `out` with the connection's byte counts since it was created
+ * or rustls_connection_reset_stats was last called.
+ */
+rustls_result rustls_connection_get_stats(const struct rustls_connection *conn,
+                                          struct rustls_connection_stats *out);
+
+/**
+ * Set the byte counts returned by rustls_connection_get_stats back to
+ * zero, so that they count from now on. This is meant for reporting
+ * metrics per interval without keeping the previous values. It doesn't
+ * affect the connection itself.
+ *
+ * Counters that describe a position or a one-off event are not reset:
+ * rustls_connection_total_plaintext_read is the offset into the
+ * decrypted stream, and rustls_connection_get_handshake_bytes describes
+ * the handshake, which happens once per connection. The TLS bytes of a
+ * handshake still show up in the interval they were sent or received in.
+ */
+void rustls_connection_reset_stats(struct rustls_connection *conn);
+
+/**
+ * Write a human-readable summary of the connection's state to `buf`,
+ * which can hold up to `len` bytes, and store its length in `out_n`. The
+ * output is not NUL-terminated. It has one "name: value" line per item,
+ * covering the role, whether the handshake is in progress, the
+ * negotiated protocol version, cipher suite and ALPN protocol, the
+ * number of peer certificates and the byte counters. rustls doesn't
+ * expose the negotiated key exchange group, so it isn't included.
+ *
+ * This is meant for logging and debugging only. The format may change
+ * in any release, so don't parse it; use the individual getters instead.
+ *
+ * Returns RUSTLS_RESULT_INSUFFICIENT_SIZE if the summary is longer than
+ * `len`. 1024 bytes is currently always enough.
+ */
+rustls_result rustls_connection_describe(const struct rustls_connection *conn,
+                                         uint8_t *buf,
+                                         size_t len,
+                                         size_t *out_n);
+
+/**
+ * Tell the connection that the application protocol has received
+ * everything it expects from the peer, e.g. because a Content-Length was
+ * reached, even though no close_notify has arrived. Plaintext that has
+ * been received but not read is dropped, and from now on
+ * rustls_connection_read and rustls_connection_peek discard any
+ * plaintext that arrives and return RUSTLS_RESULT_OK with `*out_n` set to
+ * 0, as they do after a clean close.
+ *
+ * This only affects the read side. Writing, rustls_connection_send_close_notify
+ * and TLS I/O work as before; rustls_connection_read_tls and
+ * rustls_connection_process_new_packets still need to be called if the
+ * application wants to see the peer's close_notify or alerts.
+ */
+void rustls_connection_set_peer_finished_sending(struct rustls_connection *conn);
+
 #if defined(DEFINE_READ_BUF)
 /**
  * Read up to `count` plaintext bytes from the `rustls_connection` into `buf`.
@@ -1046,11 +2340,36 @@ rustls_result rustls_connection_read_2(struct rustls_connection *conn,
  */
 void rustls_connection_free(struct rustls_connection *conn);
 
+/**
+ * Return true if the protocol version arrays `a` and `b` contain the same
+ * versions, ignoring order and duplicates. This is useful for checking a
+ * version list against a policy, for instance "exactly TLS 1.2 and TLS 1.3",
+ * without spurious mismatches when the lists are ordered differently.
+ * Returns false if `a` or `b` is NULL.
+ */
+bool rustls_protocol_versions_equal(const uint16_t *a,
+                                    size_t a_len,
+                                    const uint16_t *b,
+                                    size_t b_len);
+
+/**
+ * Return the name of the protocol version `version`, one of the values of
+ * rustls_tls_version, such as "TLSv1.3" for 0x0304. This is useful for
+ * logging the result of rustls_connection_get_protocol_version. Unknown
+ * versions, including 0, give the empty string. The lifetime of the
+ * `rustls_str` is the lifetime of the program, it does not need to be freed.
+ */
+struct rustls_str rustls_tls_version_get_name(uint16_t version);
+
 /**
  * After a rustls function returns an error, you may call
  * this to get a pointer to a buffer containing a detailed error
  * message. The contents of the error buffer will be out_n bytes long,
  * UTF-8 encoded, and not NUL-terminated.
+ *
+ * Every rustls_result value has a message. If the buffer is too small,
+ * the message is truncated at a character boundary to fit in `len - 1`
+ * bytes; a `len` of 0 writes nothing and sets out_n to 0.
  */
 void rustls_error(unsigned int result, char *buf, size_t len, size_t *out_n);
 
@@ -1136,6 +2455,25 @@ void rustls_server_config_builder_set_client_verifier(struct rustls_server_confi
 void rustls_server_config_builder_set_client_verifier_optional(struct rustls_server_config_builder *builder,
                                                                const struct rustls_client_cert_verifier_optional *verifier);
 
+/**
+ * Restrict the signature schemes a client may use to prove possession of
+ * its certificate key (in the CertificateVerify message) to those in
+ * `schemes`, an array of `len` TLS SignatureScheme values as defined in
+ * RFC 8446 section 4.2.3, e.g. 0x0804 for rsa_pss_rsae_sha256. Only these
+ * schemes are offered when requesting a client certificate, and a client
+ * that signs with any other scheme is rejected with
+ * RUSTLS_RESULT_PEER_MISBEHAVED_ERROR, even if its certificate is valid.
+ * The schemes are used in the client verifier's order of preference.
+ * This has no effect unless a client verifier is set.
+ *
+ * This doesn't restrict the algorithms used to sign the certificates in
+ * the client's chain. Returns RUSTLS_RESULT_INVALID_PARAMETER if none of
+ * `schemes` is supported.
+ */
+rustls_result rustls_server_config_builder_set_client_auth_signature_schemes(struct rustls_server_config_builder *builder,
+                                                                             const uint16_t *schemes,
+                                                                             size_t len);
+
 /**
  * "Free" a server_config_builder without building it into a rustls_server_config.
  * Normally builders are built into rustls_server_configs via `rustls_server_config_builder_build`
@@ -1148,12 +2486,38 @@ void rustls_server_config_builder_free(struct rustls_server_config_builder *conf
 /**
  * With `ignore` != 0, the server will ignore the client ordering of cipher
  * suites, aka preference, during handshake and respect its own ordering
- * as configured.
+ * as configured. By default the client's order is followed.
  * <https://docs.rs/rustls/0.20.0/rustls/struct.ServerConfig.html#structfield.ignore_client_order>
  */
 rustls_result rustls_server_config_builder_set_ignore_client_order(struct rustls_server_config_builder *builder,
                                                                    bool ignore);
 
+/**
+ * Set the maximum number of bytes of TLS 1.3 early data (0-RTT) the server
+ * will accept per connection. The default, 0, disables early data.
+ *
+ * Early data is not protected against replay: an attacker who captured it
+ * can send it to the server again. Only accept early data for requests that
+ * are safe to process more than once. rustls only offers early data on
+ * resumption, and with the default in-memory session storage each session
+ * can be resumed only once per server process. Servers sharing session state
+ * (see rustls_server_config_builder_set_persistence) need their own
+ * anti-replay measures.
+ * <https://docs.rs/rustls/0.20.0/rustls/server/struct.ServerConfig.html#structfield.max_early_data_size>
+ */
+rustls_result rustls_server_config_builder_set_max_early_data_size(struct rustls_server_config_builder *builder,
+                                                                   uint32_t max_early_data_size);
+
+/**
+ * With `require` set, the server rejects clients that don't send SNI, instead
+ * of handing them the default certificate. The handshake fails and
+ * rustls_connection_process_new_packets returns RUSTLS_RESULT_MISSING_SNI.
+ * This keeps multi-tenant servers from exposing a default virtual host to
+ * clients that connect by IP address or don't know the hostname.
+ */
+rustls_result rustls_server_config_builder_set_require_sni(struct rustls_server_config_builder *builder,
+                                                           bool require);
+
 /**
  * Set the ALPN protocol list to the given protocols. `protocols` must point
  * to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
@@ -1163,6 +2527,8 @@ rustls_result rustls_server_config_builder_set_ignore_client_order(struct rustls
  *
  * This function makes a copy of the data in `protocols` and does not retain
  * any pointers, so the caller can free the pointed-to memory after calling.
+ * Protocol IDs are copied byte for byte and may contain any byte values,
+ * including NUL and non-ASCII bytes.
  *
  * <https://docs.rs/rustls/0.20.0/rustls/server/struct.ServerConfig.html#structfield.alpn_protocols>
  */
@@ -1248,6 +2614,26 @@ rustls_result rustls_server_connection_get_sni_hostname(const struct rustls_conn
 rustls_result rustls_server_config_builder_set_hello_callback(struct rustls_server_config_builder *builder,
                                                               rustls_client_hello_callback callback);
 
+/**
+ * Register a callback that selects the certificate to present for each
+ * connection created from this config. The callback receives `userdata`
+ * and the ClientHello's SNI, signature schemes and ALPN protocols (see
+ * `rustls_client_hello`), and returns the `rustls_certified_key` to use,
+ * or NULL to abort the handshake. `rustls_client_hello_select_certified_key`
+ * can be used to pick a key matching the offered signature schemes.
+ *
+ * This is like rustls_server_config_builder_set_hello_callback, except that
+ * `userdata` is fixed here rather than taken from the connection. The
+ * callback must be safe to call concurrently from multiple connections.
+ *
+ * Any existing `ResolvesServerCert` implementation currently installed in the
+ * `rustls_server_config` will be replaced. It is not permitted to pass a NULL
+ * value for `callback`.
+ */
+rustls_result rustls_server_config_builder_set_cert_resolver_callback(struct rustls_server_config_builder *builder,
+                                                                      rustls_client_hello_callback callback,
+                                                                      rustls_client_hello_userdata userdata);
+
 /**
  * Select a `rustls_certified_key` from the list that matches the cryptographic
  * parameters of a TLS client hello. Note that this does not do any SNI matching.
@@ -1,18 +1,24 @@
+use std::cell::Cell;
 use std::convert::TryInto;
 use std::ffi::c_void;
 use std::ptr::null;
 use std::slice;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use libc::size_t;
+use rustls::client::HandshakeSignatureValid;
+use rustls::internal::msgs::enums::SignatureAlgorithm;
+use rustls::internal::msgs::handshake::DigitallySignedStruct;
 use rustls::server::{
-    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerifier,
-    ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, ServerConnection,
-    StoresServerSessions,
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientCertVerified,
+    ClientCertVerifier, ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig,
+    ServerConnection, StoresServerSessions,
 };
-use rustls::sign::CertifiedKey;
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
 use rustls::{
-    ProtocolVersion, SignatureScheme, SupportedCipherSuite, WantsVerifier, ALL_CIPHER_SUITES,
+    DistinguishedNames, ProtocolVersion, SignatureScheme, SupportedCipherSuite, WantsVerifier,
+    ALL_CIPHER_SUITES,
 };
 
 use crate::cipher::{
@@ -52,6 +58,9 @@ pub(crate) struct ServerConfigBuilder {
     session_storage: Option<Arc<dyn StoresServerSessions + Send + Sync>>,
     alpn_protocols: Vec<Vec<u8>>,
     ignore_client_order: Option<bool>,
+    max_early_data_size: Option<u32>,
+    require_sni: bool,
+    client_auth_schemes: Option<Vec<SignatureScheme>>,
 }
 
 impl CastPtr for rustls_server_config_builder {
@@ -91,6 +100,9 @@ impl rustls_server_config_builder {
                            session_storage: None,
                            alpn_protocols: vec![],
                            ignore_client_order: None,
+                           max_early_data_size: None,
+                           require_sni: false,
+                           client_auth_schemes: None,
                        };
                 BoxCastPtr::to_mut_ptr(builder)
         }
@@ -153,6 +165,9 @@ impl rustls_server_config_builder {
                 session_storage: None,
                 alpn_protocols: vec![],
                 ignore_client_order: None,
+                max_early_data_size: None,
+                require_sni: false,
+                client_auth_schemes: None,
             };
             BoxCastPtr::set_mut_ptr(builder_out, builder);
             rustls_result::Ok
@@ -192,6 +207,38 @@ impl rustls_server_config_builder {
         }
     }
 
+    /// Restrict the signature schemes a client may use to prove possession of
+    /// its certificate key (in the CertificateVerify message) to those in
+    /// `schemes`, an array of `len` TLS SignatureScheme values as defined in
+    /// RFC 8446 section 4.2.3, e.g. 0x0804 for rsa_pss_rsae_sha256. Only these
+    /// schemes are offered when requesting a client certificate, and a client
+    /// that signs with any other scheme is rejected with
+    /// RUSTLS_RESULT_PEER_MISBEHAVED_ERROR, even if its certificate is valid.
+    /// The schemes are used in the client verifier's order of preference.
+    /// This has no effect unless a client verifier is set.
+    ///
+    /// This doesn't restrict the algorithms used to sign the certificates in
+    /// the client's chain. Returns RUSTLS_RESULT_INVALID_PARAMETER if none of
+    /// `schemes` is supported.
+    #[no_mangle]
+    pub extern "C" fn rustls_server_config_builder_set_client_auth_signature_schemes(
+        builder: *mut rustls_server_config_builder,
+        schemes: *const u16,
+        len: size_t,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+            let schemes: &[u16] = try_slice!(schemes, len);
+            let schemes: Vec<SignatureScheme> = schemes.iter().map(|&s| SignatureScheme::from(s)).collect();
+            let supported = rustls::client::WebPkiVerifier::verification_schemes();
+            if !schemes.iter().any(|s| supported.contains(s)) {
+                return InvalidParameter;
+            }
+            builder.client_auth_schemes = Some(schemes);
+            rustls_result::Ok
+        }
+    }
+
     /// "Free" a server_config_builder without building it into a rustls_server_config.
     /// Normally builders are built into rustls_server_configs via `rustls_server_config_builder_build`
     /// and may not be free'd or otherwise used afterwards.
@@ -206,7 +253,7 @@ impl rustls_server_config_builder {
 
     /// With `ignore` != 0, the server will ignore the client ordering of cipher
     /// suites, aka preference, during handshake and respect its own ordering
-    /// as configured.
+    /// as configured. By default the client's order is followed.
     /// <https://docs.rs/rustls/0.20.0/rustls/struct.ServerConfig.html#structfield.ignore_client_order>
     #[no_mangle]
     pub extern "C" fn rustls_server_config_builder_set_ignore_client_order(
@@ -220,6 +267,46 @@ impl rustls_server_config_builder {
         }
     }
 
+    /// Set the maximum number of bytes of TLS 1.3 early data (0-RTT) the server
+    /// will accept per connection. The default, 0, disables early data.
+    ///
+    /// Early data is not protected against replay: an attacker who captured it
+    /// can send it to the server again. Only accept early data for requests that
+    /// are safe to process more than once. rustls only offers early data on
+    /// resumption, and with the default in-memory session storage each session
+    /// can be resumed only once per server process. Servers sharing session state
+    /// (see rustls_server_config_builder_set_persistence) need their own
+    /// anti-replay measures.
+    /// <https://docs.rs/rustls/0.20.0/rustls/server/struct.ServerConfig.html#structfield.max_early_data_size>
+    #[no_mangle]
+    pub extern "C" fn rustls_server_config_builder_set_max_early_data_size(
+        builder: *mut rustls_server_config_builder,
+        max_early_data_size: u32,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+            config.max_early_data_size = Some(max_early_data_size);
+            rustls_result::Ok
+        }
+    }
+
+    /// With `require` set, the server rejects clients that don't send SNI, instead
+    /// of handing them the default certificate. The handshake fails and
+    /// rustls_connection_process_new_packets returns RUSTLS_RESULT_MISSING_SNI.
+    /// This keeps multi-tenant servers from exposing a default virtual host to
+    /// clients that connect by IP address or don't know the hostname.
+    #[no_mangle]
+    pub extern "C" fn rustls_server_config_builder_set_require_sni(
+        builder: *mut rustls_server_config_builder,
+        require: bool,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let config: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+            config.require_sni = require;
+            rustls_result::Ok
+        }
+    }
+
     /// Set the ALPN protocol list to the given protocols. `protocols` must point
     /// to a buffer of `rustls_slice_bytes` (built by the caller) with `len`
     /// elements. Each element of the buffer must point to a slice of bytes that
@@ -228,6 +315,8 @@ impl rustls_server_config_builder {
     ///
     /// This function makes a copy of the data in `protocols` and does not retain
     /// any pointers, so the caller can free the pointed-to memory after calling.
+    /// Protocol IDs are copied byte for byte and may contain any byte values,
+    /// including NUL and non-ASCII bytes.
     ///
     /// <https://docs.rs/rustls/0.20.0/rustls/server/struct.ServerConfig.html#structfield.alpn_protocols>
     #[no_mangle]
@@ -289,11 +378,23 @@ impl rustls_server_config_builder {
     ) -> *const rustls_server_config {
         ffi_panic_boundary! {
             let builder = try_box_from_ptr!(builder);
-            let base = builder.base.with_client_cert_verifier(builder.verifier);
-            let mut config = if let Some(r) = builder.cert_resolver {
-                base.with_cert_resolver(r)
+            let verifier = match builder.client_auth_schemes {
+                Some(schemes) => Arc::new(RestrictSchemesVerifier {
+                    inner: builder.verifier,
+                    schemes,
+                }),
+                None => builder.verifier,
+            };
+            let base = builder.base.with_client_cert_verifier(verifier);
+            let resolver = match builder.cert_resolver {
+                Some(r) => r,
+                None => return null(),
+            };
+            let resolver = Arc::new(SigningRecordingResolver::new(resolver));
+            let mut config = if builder.require_sni {
+                base.with_cert_resolver(Arc::new(RequireSniResolver { inner: resolver }))
             } else {
-                return null();
+                base.with_cert_resolver(resolver)
             };
             if let Some(ss) = builder.session_storage {
                 config.session_storage = ss;
@@ -302,6 +403,9 @@ impl rustls_server_config_builder {
             if let Some(ignore_client_order) = builder.ignore_client_order {
                 config.ignore_client_order = ignore_client_order;
             }
+            if let Some(max_early_data_size) = builder.max_early_data_size {
+                config.max_early_data_size = max_early_data_size;
+            }
             ArcCastPtr::to_const_ptr(config)
         }
     }
@@ -496,11 +600,27 @@ struct ClientHelloResolver {
     /// Implementation of rustls::ResolvesServerCert that passes values
     /// from the supplied ClientHello to the callback function.
     pub callback: ClientHelloCallback,
+    /// Userdata fixed at registration time. If None, the connection's
+    /// userdata is passed to the callback instead.
+    pub userdata: Option<rustls_client_hello_userdata>,
 }
 
 impl ClientHelloResolver {
     pub fn new(callback: ClientHelloCallback) -> ClientHelloResolver {
-        ClientHelloResolver { callback }
+        ClientHelloResolver {
+            callback,
+            userdata: None,
+        }
+    }
+
+    pub fn with_userdata(
+        callback: ClientHelloCallback,
+        userdata: rustls_client_hello_userdata,
+    ) -> ClientHelloResolver {
+        ClientHelloResolver {
+            callback,
+            userdata: Some(userdata),
+        }
     }
 }
 
@@ -536,9 +656,12 @@ impl ResolvesServerCert for ClientHelloResolver {
         };
 
         let cb = self.callback;
-        let userdata = match userdata_get() {
-            Ok(u) => u,
-            Err(_) => return None,
+        let userdata = match self.userdata {
+            Some(u) => u,
+            None => match userdata_get() {
+                Ok(u) => u,
+                Err(_) => return None,
+            },
         };
         let key_ptr: *const rustls_certified_key = unsafe { cb(userdata, &hello) };
         let certified_key: &CertifiedKey = try_ref_from_ptr!(key_ptr);
@@ -552,6 +675,183 @@ impl ResolvesServerCert for ClientHelloResolver {
 unsafe impl Sync for ClientHelloResolver {}
 unsafe impl Send for ClientHelloResolver {}
 
+thread_local! {
+    // Set when RequireSniResolver rejects a ClientHello. rustls only reports a
+    // generic error when no certificate is resolved, so
+    // rustls_connection_process_new_packets checks this to return MissingSni.
+    static SNI_MISSING: Cell<bool> = Cell::new(false);
+    // Set by SigningRecordingKey when the server's key is about to sign a
+    // handshake. rustls doesn't sign when resuming, so this tells
+    // rustls_connection_is_resumed that the handshake was a full one.
+    static SERVER_KEY_USED: Cell<bool> = Cell::new(false);
+}
+
+/// Return whether a ClientHello processed on this thread since the last call
+/// was rejected for missing SNI, and reset the flag.
+pub(crate) fn take_sni_missing() -> bool {
+    SNI_MISSING.with(|m| m.replace(false))
+}
+
+/// Return whether a server certificate's key was used to sign a handshake on
+/// this thread since the last call, and reset the flag.
+pub(crate) fn take_server_key_used() -> bool {
+    SERVER_KEY_USED.with(|u| u.replace(false))
+}
+
+/// Wraps the configured certificate resolver so that the keys it resolves
+/// record when they are used. Installed by rustls_server_config_builder_build.
+///
+/// Wrapping a key means copying its CertifiedKey, so each wrapped key is
+/// cached alongside the key it was made from and reused while the inner
+/// resolver keeps returning that same key. Entries are dropped once nothing
+/// but the cache refers to the original key.
+struct SigningRecordingResolver {
+    inner: Arc<dyn ResolvesServerCert>,
+    wrapped: Mutex<Vec<(Arc<CertifiedKey>, Arc<CertifiedKey>)>>,
+}
+
+impl SigningRecordingResolver {
+    fn new(inner: Arc<dyn ResolvesServerCert>) -> Self {
+        SigningRecordingResolver {
+            inner,
+            wrapped: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn wrap(certified_key: &Arc<CertifiedKey>) -> Arc<CertifiedKey> {
+        let mut recording = certified_key.as_ref().clone();
+        recording.key = Arc::new(SigningRecordingKey {
+            inner: Arc::clone(&certified_key.key),
+        });
+        Arc::new(recording)
+    }
+}
+
+impl ResolvesServerCert for SigningRecordingResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let certified_key = self.inner.resolve(client_hello)?;
+        let mut wrapped = match self.wrapped.lock() {
+            Ok(w) => w,
+            Err(_) => return Some(Self::wrap(&certified_key)),
+        };
+        if let Some((_, recording)) = wrapped
+            .iter()
+            .find(|(original, _)| Arc::ptr_eq(original, &certified_key))
+        {
+            return Some(Arc::clone(recording));
+        }
+        wrapped.retain(|(original, _)| Arc::strong_count(original) > 1);
+        let recording = Self::wrap(&certified_key);
+        wrapped.push((certified_key, Arc::clone(&recording)));
+        Some(recording)
+    }
+}
+
+/// Records in SERVER_KEY_USED when rustls chooses a scheme to sign with.
+struct SigningRecordingKey {
+    inner: Arc<dyn SigningKey>,
+}
+
+impl SigningKey for SigningRecordingKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        SERVER_KEY_USED.with(|u| u.set(true));
+        self.inner.choose_scheme(offered)
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.inner.algorithm()
+    }
+}
+
+/// Wraps another resolver, refusing to resolve a certificate for clients that
+/// don't send SNI. Installed by rustls_server_config_builder_set_require_sni.
+struct RequireSniResolver {
+    inner: Arc<dyn ResolvesServerCert>,
+}
+
+impl ResolvesServerCert for RequireSniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        if client_hello.server_name().is_none() {
+            SNI_MISSING.with(|m| m.set(true));
+            return None;
+        }
+        self.inner.resolve(client_hello)
+    }
+}
+
+/// Wraps a client certificate verifier, allowing only the given signature
+/// schemes in the client's CertificateVerify. Installed by
+/// rustls_server_config_builder_set_client_auth_signature_schemes.
+struct RestrictSchemesVerifier {
+    inner: Arc<dyn ClientCertVerifier>,
+    schemes: Vec<SignatureScheme>,
+}
+
+impl RestrictSchemesVerifier {
+    fn check_scheme(&self, dss: &DigitallySignedStruct) -> Result<(), rustls::Error> {
+        if self.schemes.contains(&dss.scheme) {
+            Ok(())
+        } else {
+            Err(rustls::Error::PeerMisbehavedError(format!(
+                "client signed with disallowed signature scheme {:?}",
+                dss.scheme
+            )))
+        }
+    }
+}
+
+impl ClientCertVerifier for RestrictSchemesVerifier {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> Option<bool> {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        intermediates: &[rustls::Certificate],
+        now: SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        self.inner
+            .verify_client_cert(end_entity, intermediates, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.check_scheme(dss)?;
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::Certificate,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.check_scheme(dss)?;
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner
+            .supported_verify_schemes()
+            .into_iter()
+            .filter(|s| self.schemes.contains(s))
+            .collect()
+    }
+}
+
 impl rustls_server_config_builder {
     /// Register a callback to be invoked when a connection created from this config
     /// sees a TLS ClientHello message. If `userdata` has been set with
@@ -584,6 +884,39 @@ impl rustls_server_config_builder {
             rustls_result::Ok
         }
     }
+
+    /// Register a callback that selects the certificate to present for each
+    /// connection created from this config. The callback receives `userdata`
+    /// and the ClientHello's SNI, signature schemes and ALPN protocols (see
+    /// `rustls_client_hello`), and returns the `rustls_certified_key` to use,
+    /// or NULL to abort the handshake. `rustls_client_hello_select_certified_key`
+    /// can be used to pick a key matching the offered signature schemes.
+    ///
+    /// This is like rustls_server_config_builder_set_hello_callback, except that
+    /// `userdata` is fixed here rather than taken from the connection. The
+    /// callback must be safe to call concurrently from multiple connections.
+    ///
+    /// Any existing `ResolvesServerCert` implementation currently installed in the
+    /// `rustls_server_config` will be replaced. It is not permitted to pass a NULL
+    /// value for `callback`.
+    #[no_mangle]
+    pub extern "C" fn rustls_server_config_builder_set_cert_resolver_callback(
+        builder: *mut rustls_server_config_builder,
+        callback: rustls_client_hello_callback,
+        userdata: rustls_client_hello_userdata,
+    ) -> rustls_result {
+        ffi_panic_boundary! {
+            let callback: ClientHelloCallback = match callback {
+                Some(cb) => cb,
+                None => return rustls_result::NullParameter,
+            };
+            let builder: &mut ServerConfigBuilder = try_mut_from_ptr!(builder);
+            builder.cert_resolver = Some(Arc::new(ClientHelloResolver::with_userdata(
+                callback, userdata
+            )));
+            rustls_result::Ok
+        }
+    }
 }
 
 // Turn a slice of u16's into a vec of SignatureScheme as needed by rustls.
@@ -686,9 +1019,42 @@ impl rustls_server_config_builder {
 
 #[cfg(test)]
 mod tests {
+    use std::cmp::{max, min};
+    use std::io::Read;
     use std::ptr::null_mut;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use libc::{c_char, c_int};
 
     use super::*;
+    use crate::cipher::rustls_root_cert_store;
+    use crate::client::{
+        rustls_client_config, rustls_client_config_builder, rustls_verify_server_cert_params,
+        rustls_verify_server_cert_user_data,
+    };
+    use crate::test_util::{
+        accept_any_server_cert, accepting_client_config_builder, handshake,
+        localhost_certified_key, localhost_server_config, new_client_connection,
+        new_server_connection, transfer, ConnectionPair,
+    };
+
+    // Accept the server certificate only if the verifier was given the
+    // current time.
+    unsafe extern "C" fn accept_if_now_is_current(
+        _userdata: rustls_verify_server_cert_user_data,
+        params: *const rustls_verify_server_cert_params,
+    ) -> u32 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let given = (*params).now_unix_secs;
+        if max(now, given) - min(now, given) < 60 {
+            rustls_result::Ok as u32
+        } else {
+            rustls_result::CertInvalidData as u32
+        }
+    }
 
     #[test]
     fn test_config_builder() {
@@ -710,6 +1076,29 @@ mod tests {
         rustls_server_config::rustls_server_config_free(config);
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_config_builder_max_early_data_size() {
+        let builder: *mut rustls_server_config_builder =
+            rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_server_config_builder::rustls_server_config_builder_set_max_early_data_size(
+            builder, 16384,
+        );
+        let config = rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        {
+            let config2 = try_ref_from_ptr!(config);
+            assert_eq!(config2.max_early_data_size, 16384);
+        }
+        rustls_server_config::rustls_server_config_free(config);
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+    }
+
     // Build a server connection and test the getters and initial values.
     #[test]
     fn test_server_config_builder_new_empty() {
@@ -793,4 +1182,1090 @@ mod tests {
         );
         rustls_connection::rustls_connection_free(conn);
     }
+
+    struct ResolverState {
+        key: *const rustls_certified_key,
+        sni: String,
+    }
+
+    unsafe extern "C" fn resolve_from_userdata(
+        userdata: rustls_client_hello_userdata,
+        hello: *const rustls_client_hello,
+    ) -> *const rustls_certified_key {
+        let state = &mut *(userdata as *mut ResolverState);
+        let hello = &*hello;
+        let sni = slice::from_raw_parts(hello.sni_name.data as *const u8, hello.sni_name.len);
+        state.sni = String::from_utf8_lossy(sni).to_string();
+        state.key
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_cert_resolver_callback() {
+        let mut state = ResolverState {
+            key: localhost_certified_key(),
+            sni: String::new(),
+        };
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let result =
+            rustls_server_config_builder::rustls_server_config_builder_set_cert_resolver_callback(
+                builder,
+                Some(resolve_from_userdata),
+                &mut state as *mut ResolverState as *mut c_void,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let pair = ConnectionPair::with_server_config(server_config);
+        let (client, server) = (pair.client, pair.server);
+        let result = handshake(client, server);
+        if !matches!(result, rustls_result::Ok) {
+            panic!("expected RUSTLS_RESULT_OK from handshake, got {:?}", result);
+        }
+        assert_eq!(state.sni, "localhost");
+
+        rustls_certified_key::rustls_certified_key_free(state.key);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_ignore_client_order() {
+        // The client prefers ChaCha20, while the server's default order puts
+        // AES-256 first.
+        let client_suites: Vec<*const rustls_supported_ciphersuite> = vec![
+            &rustls::cipher_suite::TLS13_CHACHA20_POLY1305_SHA256 as *const SupportedCipherSuite
+                as *const _,
+            &rustls::cipher_suite::TLS13_AES_256_GCM_SHA384 as *const SupportedCipherSuite
+                as *const _,
+        ];
+        let versions: Vec<u16> = vec![0x0304];
+        for &(ignore, expected) in &[
+            (false, rustls::CipherSuite::TLS13_CHACHA20_POLY1305_SHA256),
+            (true, rustls::CipherSuite::TLS13_AES_256_GCM_SHA384),
+        ] {
+            let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+            let certified_key = localhost_certified_key();
+            rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+                builder,
+                &certified_key,
+                1,
+            );
+            rustls_certified_key::rustls_certified_key_free(certified_key);
+            let result =
+                rustls_server_config_builder::rustls_server_config_builder_set_ignore_client_order(
+                    builder, ignore,
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let server_config =
+                rustls_server_config_builder::rustls_server_config_builder_build(builder);
+
+            let mut client_builder: *mut rustls_client_config_builder = null_mut();
+            let result = rustls_client_config_builder::rustls_client_config_builder_new_custom(
+                client_suites.as_ptr(),
+                client_suites.len(),
+                versions.as_ptr(),
+                versions.len(),
+                &mut client_builder,
+            );
+            assert!(matches!(result, rustls_result::Ok));
+            rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+                client_builder,
+                Some(accept_any_server_cert),
+            );
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(client_builder);
+
+            let client = new_client_connection(client_config);
+            let server = new_server_connection(server_config);
+            assert!(matches!(handshake(client, server), rustls_result::Ok));
+            let negotiated = try_ref_from_ptr!(server)
+                .negotiated_cipher_suite()
+                .map(|cs| cs.suite());
+            assert_eq!(negotiated, Some(expected), "ignore = {}", ignore);
+
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+            rustls_server_config::rustls_server_config_free(server_config);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_trust_self_signed() {
+        let server_config = localhost_server_config();
+        let localhost_der =
+            rustls_pemfile::certs(&mut include_str!("../localhost/cert.pem").as_bytes())
+                .unwrap()
+                .remove(0);
+        let other_der = rustls_pemfile::certs(&mut include_str!("../minica.pem").as_bytes())
+            .unwrap()
+            .remove(0);
+        for (der, ok) in &[(localhost_der, true), (other_der, false)] {
+            let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_trust_self_signed(
+                    builder,
+                    der.as_ptr(),
+                    der.len(),
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(builder);
+            let client = new_client_connection(client_config);
+            let server = new_server_connection(server_config);
+            let result = handshake(client, server);
+            if *ok {
+                assert!(matches!(result, rustls_result::Ok), "{:?}", result);
+            } else {
+                assert!(
+                    matches!(result, rustls_result::CertInvalidData),
+                    "{:?}",
+                    result
+                );
+            }
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+        }
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_verifier_now_unix_secs() {
+        let server_config = localhost_server_config();
+        let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+        rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+            builder,
+            Some(accept_if_now_is_current),
+        );
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        let result = handshake(client, server);
+        assert!(matches!(result, rustls_result::Ok), "{:?}", result);
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_per_host_verifier() {
+        let server_config = localhost_server_config();
+        let other_der = rustls_pemfile::certs(&mut include_str!("../minica.pem").as_bytes())
+            .unwrap()
+            .remove(0);
+        // The default verifier rejects the localhost certificate, so the
+        // handshake only succeeds if the per-host verifier is used.
+        for (host, ok) in &[("LocalHost\0", true), ("example.com\0", false)] {
+            let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+            rustls_client_config_builder::rustls_client_config_builder_trust_self_signed(
+                builder,
+                other_der.as_ptr(),
+                other_der.len(),
+            );
+            let result =
+                rustls_client_config_builder::rustls_client_config_builder_set_per_host_verifier(
+                    builder,
+                    host.as_ptr() as *const c_char,
+                    Some(accept_any_server_cert),
+                );
+            assert!(matches!(result, rustls_result::Ok));
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(builder);
+            let client = new_client_connection(client_config);
+            let server = new_server_connection(server_config);
+            let result = handshake(client, server);
+            if *ok {
+                assert!(matches!(result, rustls_result::Ok), "{:?}", result);
+            } else {
+                assert!(
+                    matches!(result, rustls_result::CertInvalidData),
+                    "{:?}",
+                    result
+                );
+            }
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+        }
+
+        let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_per_host_verifier(
+                builder,
+                "not a hostname\0".as_ptr() as *const c_char,
+                Some(accept_any_server_cert),
+            );
+        assert!(matches!(result, rustls_result::InvalidDnsNameError));
+        rustls_client_config_builder::rustls_client_config_builder_free(builder);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_connection_new_anonymous() {
+        let server_config = localhost_server_config();
+        let client_config = rustls_client_config_builder::rustls_client_config_builder_build(
+            accepting_client_config_builder(),
+        );
+        let mut client: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new_anonymous(
+            client_config,
+            &mut client,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let server = new_server_connection(server_config);
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+        let mut buf = [0u8; 64];
+        let mut n: size_t = 1;
+        let result =
+            rustls_server_connection_get_sni_hostname(server, buf.as_mut_ptr(), buf.len(), &mut n);
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, 0);
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        assert!(!rustls_connection::rustls_connection_can_resume_next_time(
+            client
+        ));
+
+        // A second anonymous connection, possibly to a different endpoint,
+        // must not resume the first one's session.
+        let mut client2: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new_anonymous(
+            client_config,
+            &mut client2,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let server2 = new_server_connection(server_config);
+        assert!(matches!(handshake(client2, server2), rustls_result::Ok));
+        assert!(!rustls_connection::rustls_connection_is_resumed(client2));
+        assert!(!rustls_connection::rustls_connection_is_resumed(server2));
+        let key = crate::session::client_session_key("example.invalid").unwrap();
+        let config: &rustls::ClientConfig = try_ref_from_ptr!(client_config);
+        assert!(config.session_storage.get(&key).is_none());
+
+        rustls_connection::rustls_connection_free(client2);
+        rustls_connection::rustls_connection_free(server2);
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_session_key() {
+        let pair = ConnectionPair::connected();
+        let (client, server) = (pair.client, pair.server);
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+
+        // The key used by rustls_client_config_builder_preload_session_cache
+        // must match the one rustls stores received sessions under.
+        let key = crate::session::client_session_key("LocalHost").unwrap();
+        let client_config = pair.client_config;
+        let config: &rustls::ClientConfig = try_ref_from_ptr!(client_config);
+        assert!(config.session_storage.get(&key).is_some());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_can_resume_next_time() {
+        let pair = ConnectionPair::new();
+        let (client, server) = (pair.client, pair.server);
+        assert!(!rustls_connection::rustls_connection_can_resume_next_time(
+            client
+        ));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        // The TLS 1.3 tickets follow the server's last handshake flight.
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        assert!(rustls_connection::rustls_connection_can_resume_next_time(
+            client
+        ));
+        assert!(!rustls_connection::rustls_connection_can_resume_next_time(
+            server
+        ));
+        assert!(!rustls_connection::rustls_connection_can_resume_next_time(
+            null()
+        ));
+
+        // A connection to a different server name has nothing to resume.
+        let mut other: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new(
+            pair.client_config,
+            "example.com\0".as_ptr() as *const c_char,
+            &mut other,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert!(!rustls_connection::rustls_connection_can_resume_next_time(
+            other
+        ));
+
+        rustls_connection::rustls_connection_free(other);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_is_resumed() {
+        let suites: Vec<*const rustls_supported_ciphersuite> = vec![
+            &rustls::cipher_suite::TLS13_AES_128_GCM_SHA256 as *const SupportedCipherSuite
+                as *const _,
+            &rustls::cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+                as *const SupportedCipherSuite as *const _,
+        ];
+        for &version in &[0x0304u16, 0x0303] {
+            let server_config = localhost_server_config();
+            let mut client_builder: *mut rustls_client_config_builder = null_mut();
+            let result = rustls_client_config_builder::rustls_client_config_builder_new_custom(
+                suites.as_ptr(),
+                suites.len(),
+                &version,
+                1,
+                &mut client_builder,
+            );
+            assert!(matches!(result, rustls_result::Ok));
+            rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+                client_builder,
+                Some(accept_any_server_cert),
+            );
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(client_builder);
+
+            // Both configs keep the first session, so the second handshake
+            // resumes it.
+            for &expect_resumed in &[false, true] {
+                let client = new_client_connection(client_config);
+                let server = new_server_connection(server_config);
+                assert!(!rustls_connection::rustls_connection_is_resumed(client));
+                assert!(!rustls_connection::rustls_connection_is_resumed(server));
+                assert!(matches!(handshake(client, server), rustls_result::Ok));
+                assert!(matches!(transfer(server, client), rustls_result::Ok));
+                assert_eq!(
+                    rustls_connection::rustls_connection_is_resumed(client),
+                    expect_resumed,
+                    "version {:#x}",
+                    version
+                );
+                assert_eq!(
+                    rustls_connection::rustls_connection_is_resumed(server),
+                    expect_resumed,
+                    "version {:#x}",
+                    version
+                );
+                rustls_connection::rustls_connection_free(client);
+                rustls_connection::rustls_connection_free(server);
+            }
+
+            rustls_client_config::rustls_client_config_free(client_config);
+            rustls_server_config::rustls_server_config_free(server_config);
+        }
+        assert!(!rustls_connection::rustls_connection_is_resumed(null()));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_early_data() {
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        rustls_server_config_builder::rustls_server_config_builder_set_max_early_data_size(
+            builder, 1024,
+        );
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let builder = accepting_client_config_builder();
+        rustls_client_config_builder::rustls_client_config_builder_set_enable_early_data(
+            builder, true,
+        );
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(builder);
+        assert!(try_ref_from_ptr!(client_config).enable_early_data);
+
+        let request = b"GET / HTTP/1.1\r\n\r\n";
+        let mut n: size_t = 0;
+
+        // There's no session to resume yet.
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        let result = rustls_connection::rustls_connection_write_early_data(
+            client,
+            request.as_ptr(),
+            request.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::NoEarlyData));
+        let result = rustls_connection::rustls_connection_write_early_data(
+            server,
+            request.as_ptr(),
+            request.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::NoEarlyData));
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(matches!(transfer(server, client), rustls_result::Ok));
+        assert!(!rustls_connection::rustls_connection_is_early_data_accepted(client));
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        let result = rustls_connection::rustls_connection_write_early_data(
+            client,
+            request.as_ptr(),
+            request.len(),
+            &mut n,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(n, request.len());
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(rustls_connection::rustls_connection_is_early_data_accepted(
+            client
+        ));
+        assert!(!rustls_connection::rustls_connection_is_early_data_accepted(server));
+        let mut received = vec![];
+        try_mut_from_ptr!(server)
+            .as_server_mut()
+            .and_then(|s| s.early_data())
+            .unwrap()
+            .read_to_end(&mut received)
+            .unwrap();
+        assert_eq!(received, request);
+
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    type SessionMap = std::collections::HashMap<Vec<u8>, Vec<u8>>;
+
+    unsafe extern "C" fn session_map_get(
+        userdata: *mut c_void,
+        key: *const rustls_slice_bytes,
+        remove_after: c_int,
+        buf: *mut u8,
+        count: size_t,
+        out_n: *mut size_t,
+    ) -> u32 {
+        let map = &mut *(userdata as *mut SessionMap);
+        let key = slice::from_raw_parts((*key).data, (*key).len);
+        let value = match map.get(key) {
+            Some(value) if value.len() <= count => value.clone(),
+            _ => return rustls_result::NotFound as u32,
+        };
+        if remove_after != 0 {
+            map.remove(key);
+        }
+        std::ptr::copy_nonoverlapping(value.as_ptr(), buf, value.len());
+        *out_n = value.len();
+        rustls_result::Ok as u32
+    }
+
+    unsafe extern "C" fn session_map_put(
+        userdata: *mut c_void,
+        key: *const rustls_slice_bytes,
+        val: *const rustls_slice_bytes,
+    ) -> u32 {
+        let map = &mut *(userdata as *mut SessionMap);
+        let key = slice::from_raw_parts((*key).data, (*key).len);
+        let val = slice::from_raw_parts((*val).data, (*val).len);
+        map.insert(key.to_vec(), val.to_vec());
+        rustls_result::Ok as u32
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_persistence() {
+        let builder = accepting_client_config_builder();
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_persistence(
+            builder, None, None,
+        );
+        assert!(matches!(result, rustls_result::NullParameter));
+        let result = rustls_client_config_builder::rustls_client_config_builder_set_persistence(
+            builder,
+            Some(session_map_get),
+            Some(session_map_put),
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(builder);
+
+        let server_config = localhost_server_config();
+        let mut sessions = SessionMap::new();
+        for &expect_resumed in &[false, true] {
+            // The session is looked up while the connection is created, so
+            // the userdata has to be passed in then.
+            let mut client: *mut rustls_connection = null_mut();
+            let result = rustls_client_config::rustls_client_connection_new_full(
+                client_config,
+                "localhost\0".as_ptr() as *const c_char,
+                &mut sessions as *mut SessionMap as *mut c_void,
+                null(),
+                0,
+                &mut client,
+            );
+            assert!(matches!(result, rustls_result::Ok));
+            let server = new_server_connection(server_config);
+            assert!(matches!(handshake(client, server), rustls_result::Ok));
+            assert!(matches!(transfer(server, client), rustls_result::Ok));
+            assert_eq!(
+                rustls_connection::rustls_connection_is_resumed(client),
+                expect_resumed
+            );
+            let key = crate::session::client_session_key("localhost").unwrap();
+            assert!(sessions.contains_key(&key));
+
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+        }
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    struct ClientCertRequest {
+        issuers: Vec<Vec<u8>>,
+        schemes: Vec<u16>,
+        key: *const rustls_certified_key,
+    }
+
+    unsafe extern "C" fn resolve_client_cert(
+        userdata: *mut c_void,
+        acceptable_issuers: *const rustls_slice_slice_bytes,
+        signature_schemes: rustls_slice_u16,
+    ) -> *const rustls_certified_key {
+        let request = &mut *(userdata as *mut ClientCertRequest);
+        request.issuers = (*acceptable_issuers)
+            .inner
+            .iter()
+            .map(|i| i.to_vec())
+            .collect();
+        request.schemes =
+            slice::from_raw_parts(signature_schemes.data, signature_schemes.len).to_vec();
+        request.key
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_cert_resolver() {
+        let store = rustls_root_cert_store::rustls_root_cert_store_new();
+        let ca_pem = include_str!("../minica.pem").as_bytes();
+        rustls_root_cert_store::rustls_root_cert_store_add_pem(
+            store,
+            ca_pem.as_ptr(),
+            ca_pem.len(),
+            true,
+        );
+        let verifier =
+            rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_new(store);
+        rustls_root_cert_store::rustls_root_cert_store_free(store);
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_server_config_builder::rustls_server_config_builder_set_client_verifier_optional(
+            builder, verifier,
+        );
+        rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_free(verifier);
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+
+        let client_builder = accepting_client_config_builder();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_client_cert_resolver(
+                client_builder,
+                None,
+            );
+        assert!(matches!(result, rustls_result::NullParameter));
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_client_cert_resolver(
+                client_builder,
+                Some(resolve_client_cert),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(client_builder);
+
+        // Declining sends no certificate, which the optional verifier allows.
+        let mut request = ClientCertRequest {
+            issuers: vec![],
+            schemes: vec![],
+            key: null(),
+        };
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        rustls_connection::rustls_connection_set_userdata(
+            client,
+            &mut request as *mut ClientCertRequest as *mut c_void,
+        );
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(rustls_connection::rustls_connection_get_peer_certificate(server, 0).is_null());
+        let minica_der = rustls_pemfile::certs(&mut &ca_pem[..]).unwrap().remove(0);
+        assert_eq!(request.issuers.len(), 1);
+        assert!(minica_der
+            .windows(request.issuers[0].len())
+            .any(|w| w == &request.issuers[0][..]));
+        assert!(request
+            .schemes
+            .contains(&SignatureScheme::RSA_PSS_SHA256.get_u16()));
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+
+        // The returned key is sent to the server, which rejects it because the
+        // test certificates have expired. A new config is used so that the
+        // first connection's session isn't resumed without client auth.
+        rustls_client_config::rustls_client_config_free(client_config);
+        let client_builder = accepting_client_config_builder();
+        rustls_client_config_builder::rustls_client_config_builder_set_client_cert_resolver(
+            client_builder,
+            Some(resolve_client_cert),
+        );
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(client_builder);
+        request.key = certified_key;
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        rustls_connection::rustls_connection_set_userdata(
+            client,
+            &mut request as *mut ClientCertRequest as *mut c_void,
+        );
+        let result = handshake(client, server);
+        assert!(
+            matches!(result, rustls_result::CertInvalidData),
+            "{:?}",
+            result
+        );
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_auth_signature_schemes() {
+        let verifier = RestrictSchemesVerifier {
+            inner: AllowAnyAnonymousOrAuthenticatedClient::new(rustls::RootCertStore::empty()),
+            schemes: vec![SignatureScheme::ED25519, SignatureScheme::from(0xffff)],
+        };
+        assert_eq!(
+            verifier.supported_verify_schemes(),
+            vec![SignatureScheme::ED25519]
+        );
+        let dss = DigitallySignedStruct::new(SignatureScheme::RSA_PSS_SHA256, vec![0; 256]);
+        let cert = rustls::Certificate(vec![]);
+        assert!(matches!(
+            verifier.verify_tls13_signature(b"message", &cert, &dss),
+            Err(rustls::Error::PeerMisbehavedError(_))
+        ));
+
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let result =
+            rustls_server_config_builder::rustls_server_config_builder_set_client_auth_signature_schemes(
+                builder,
+                [0xffff].as_ptr(),
+                1,
+            );
+        assert!(matches!(result, rustls_result::InvalidParameter));
+        let result =
+            rustls_server_config_builder::rustls_server_config_builder_set_client_auth_signature_schemes(
+                builder,
+                [SignatureScheme::ED25519.get_u16()].as_ptr(),
+                1,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let store = rustls_root_cert_store::rustls_root_cert_store_new();
+        let client_verifier =
+            rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_new(store);
+        rustls_root_cert_store::rustls_root_cert_store_free(store);
+        rustls_server_config_builder::rustls_server_config_builder_set_client_verifier_optional(
+            builder,
+            client_verifier,
+        );
+        rustls_client_cert_verifier_optional::rustls_client_cert_verifier_optional_free(
+            client_verifier,
+        );
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+
+        // The client's only key is RSA, so it can't sign with an allowed
+        // scheme and continues without a certificate.
+        let client_builder = accepting_client_config_builder();
+        rustls_client_config_builder::rustls_client_config_builder_set_certified_key(
+            client_builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(client_builder);
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        assert!(rustls_connection::rustls_connection_get_peer_certificate(server, 0).is_null());
+
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_require_sni() {
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        let result = rustls_server_config_builder::rustls_server_config_builder_set_require_sni(
+            builder, true,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+        let pair = ConnectionPair::with_server_config(server_config);
+        assert!(matches!(pair.handshake(), rustls_result::Ok));
+
+        let mut client: *mut rustls_connection = null_mut();
+        rustls_client_config::rustls_client_connection_new_anonymous(
+            pair.client_config,
+            &mut client,
+        );
+        let server = new_server_connection(pair.server_config);
+        let result = handshake(client, server);
+        assert!(matches!(result, rustls_result::MissingSni), "{:?}", result);
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+    }
+
+    fn get_alpn(conn: *const rustls_connection) -> Option<Vec<u8>> {
+        let mut protocol: *const u8 = null();
+        let mut protocol_len: usize = 0;
+        rustls_connection::rustls_connection_get_alpn_protocol(
+            conn,
+            &mut protocol,
+            &mut protocol_len,
+        );
+        // The _ext variant must agree, with `present` set exactly when a
+        // protocol was negotiated.
+        let mut ext_protocol: *const u8 = null();
+        let mut ext_protocol_len: usize = 0;
+        let mut present = false;
+        let result = rustls_connection::rustls_connection_get_alpn_protocol_ext(
+            conn,
+            &mut ext_protocol,
+            &mut ext_protocol_len,
+            &mut present,
+        );
+        assert!(matches!(result, rustls_result::Ok));
+        assert_eq!(
+            (ext_protocol, ext_protocol_len, present),
+            (protocol, protocol_len, !protocol.is_null())
+        );
+        if protocol.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(protocol, protocol_len) }.to_vec())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_server_alpn_protocol() {
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        let server_alpn: Vec<rustls_slice_bytes> = vec![b"h2"[..].into(), b"http/1.1"[..].into()];
+        rustls_server_config_builder::rustls_server_config_builder_set_alpn_protocols(
+            builder,
+            server_alpn.as_ptr(),
+            server_alpn.len(),
+        );
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+
+        // rustls servers pick the first of their own protocols the client offers.
+        type Case<'a> = (&'a [&'a [u8]], Option<&'a [u8]>);
+        let cases: &[Case] = &[
+            (&[b"http/1.1", b"h2"], Some(b"h2")),
+            (&[b"http/1.1"], Some(b"http/1.1")),
+            (&[], None),
+        ];
+        for (offered, selected) in cases {
+            let builder = accepting_client_config_builder();
+            let client_alpn: Vec<rustls_slice_bytes> =
+                offered.iter().map(|p| (*p).into()).collect();
+            rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+                builder,
+                client_alpn.as_ptr(),
+                client_alpn.len(),
+            );
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(builder);
+            let client = new_client_connection(client_config);
+            let server = new_server_connection(server_config);
+            assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+            let selected: Option<Vec<u8>> = selected.map(|p| p.to_vec());
+            assert_eq!(get_alpn(server), selected);
+            assert_eq!(get_alpn(client), selected);
+
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+        }
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    unsafe extern "C" fn collect_key_log(
+        userdata: *mut c_void,
+        label: rustls_str,
+        client_random: rustls_slice_bytes,
+        secret: rustls_slice_bytes,
+    ) {
+        let lines = &mut *(userdata as *mut Vec<(String, usize, usize)>);
+        let label = slice::from_raw_parts(label.data as *const u8, label.len);
+        lines.push((
+            String::from_utf8(label.to_vec()).unwrap(),
+            client_random.len,
+            secret.len,
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_key_log_callback() {
+        let server_config = localhost_server_config();
+        let builder = accepting_client_config_builder();
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_key_log_callback(
+                builder, None,
+            );
+        assert!(matches!(result, rustls_result::NullParameter));
+        // The callback replaces the key log file.
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_enable_sslkeylogfile(
+                builder,
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let result =
+            rustls_client_config_builder::rustls_client_config_builder_set_key_log_callback(
+                builder,
+                Some(collect_key_log),
+            );
+        assert!(matches!(result, rustls_result::Ok));
+        let client_config =
+            rustls_client_config_builder::rustls_client_config_builder_build(builder);
+
+        let mut lines: Vec<(String, usize, usize)> = vec![];
+        let client = new_client_connection(client_config);
+        let server = new_server_connection(server_config);
+        rustls_connection::rustls_connection_set_userdata(
+            client,
+            &mut lines as *mut Vec<(String, usize, usize)> as *mut c_void,
+        );
+        assert!(matches!(handshake(client, server), rustls_result::Ok));
+        let labels: Vec<&str> = lines.iter().map(|(l, _, _)| l.as_str()).collect();
+        for label in &[
+            "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+            "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+            "CLIENT_TRAFFIC_SECRET_0",
+            "SERVER_TRAFFIC_SECRET_0",
+        ] {
+            assert!(labels.contains(label), "{:?}", labels);
+        }
+        // The negotiated suite is TLS13_AES_256_GCM_SHA384, whose secrets
+        // are SHA-384 sized.
+        assert!(lines
+            .iter()
+            .all(|&(_, random, secret)| random == 32 && secret == 48));
+
+        rustls_connection::rustls_connection_free(client);
+        rustls_connection::rustls_connection_free(server);
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    unsafe extern "C" fn accept_and_mark_userdata(
+        userdata: rustls_verify_server_cert_user_data,
+        _params: *const rustls_verify_server_cert_params,
+    ) -> u32 {
+        *(userdata as *mut bool) = true;
+        rustls_result::Ok as u32
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_client_connection_new_full() {
+        let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+        let certified_key = localhost_certified_key();
+        rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+            builder,
+            &certified_key,
+            1,
+        );
+        rustls_certified_key::rustls_certified_key_free(certified_key);
+        let server_alpn: Vec<rustls_slice_bytes> = vec![b"h2"[..].into(), b"http/1.1"[..].into()];
+        rustls_server_config_builder::rustls_server_config_builder_set_alpn_protocols(
+            builder,
+            server_alpn.as_ptr(),
+            server_alpn.len(),
+        );
+        let server_config =
+            rustls_server_config_builder::rustls_server_config_builder_build(builder);
+
+        // Each case uses a new client config, so that the verifier is called
+        // rather than a session from an earlier case being resumed.
+        let new_client_config = || {
+            let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+            rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+                builder,
+                Some(accept_and_mark_userdata),
+            );
+            let client_alpn: Vec<rustls_slice_bytes> = vec![b"http/1.1"[..].into()];
+            rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+                builder,
+                client_alpn.as_ptr(),
+                client_alpn.len(),
+            );
+            rustls_client_config_builder::rustls_client_config_builder_build(builder)
+        };
+
+        let alpn_override: Vec<rustls_slice_bytes> = vec![b"h2"[..].into()];
+        type Case<'a> = (*const rustls_slice_bytes<'a>, size_t, Option<&'a [u8]>);
+        let cases: &[Case] = &[
+            (null(), 0, Some(b"http/1.1")),
+            (alpn_override.as_ptr(), alpn_override.len(), Some(b"h2")),
+            (alpn_override.as_ptr(), 0, None),
+        ];
+        for &(alpn, alpn_len, selected) in cases {
+            let client_config = new_client_config();
+            let mut verified = false;
+            let mut client: *mut rustls_connection = null_mut();
+            let result = rustls_client_config::rustls_client_connection_new_full(
+                client_config,
+                "localhost\0".as_ptr() as *const c_char,
+                &mut verified as *mut bool as *mut c_void,
+                alpn,
+                alpn_len,
+                &mut client,
+            );
+            assert!(matches!(result, rustls_result::Ok));
+            let server = new_server_connection(server_config);
+            assert!(matches!(handshake(client, server), rustls_result::Ok));
+            assert!(verified);
+            assert_eq!(get_alpn(client), selected.map(|p| p.to_vec()));
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+        }
+
+        let client_config = new_client_config();
+        let mut client: *mut rustls_connection = null_mut();
+        let result = rustls_client_config::rustls_client_connection_new_full(
+            client_config,
+            "not a hostname\0".as_ptr() as *const c_char,
+            null_mut(),
+            null(),
+            0,
+            &mut client,
+        );
+        assert!(matches!(result, rustls_result::InvalidDnsNameError));
+        assert!(client.is_null());
+
+        let too_long = [b'a'; 256];
+        for &bad in &[&b""[..], &too_long[..]] {
+            let alpn_override: Vec<rustls_slice_bytes> = vec![b"h2"[..].into(), bad.into()];
+            let result = rustls_client_config::rustls_client_connection_new_full(
+                client_config,
+                "localhost\0".as_ptr() as *const c_char,
+                null_mut(),
+                alpn_override.as_ptr(),
+                alpn_override.len(),
+                &mut client,
+            );
+            assert!(matches!(result, rustls_result::InvalidParameter));
+            assert!(client.is_null());
+        }
+
+        rustls_client_config::rustls_client_config_free(client_config);
+        rustls_server_config::rustls_server_config_free(server_config);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_alpn_binary_protocol_round_trip() {
+        // ALPN protocol IDs are opaque bytes; none of these may be mangled by
+        // string handling on the way from the builders to the wire and back.
+        let protocols: &[&[u8]] = &[
+            b"\0",
+            b"a\0b",
+            &[0xff, 0xfe, 0x80],
+            "caf\u{e9}/1".as_bytes(),
+        ];
+        for protocol in protocols {
+            let alpn: Vec<rustls_slice_bytes> = vec![b"other"[..].into(), (*protocol).into()];
+            let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+            let certified_key = localhost_certified_key();
+            rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+                builder,
+                &certified_key,
+                1,
+            );
+            rustls_certified_key::rustls_certified_key_free(certified_key);
+            rustls_server_config_builder::rustls_server_config_builder_set_alpn_protocols(
+                builder,
+                alpn[1..].as_ptr(),
+                1,
+            );
+            let server_config =
+                rustls_server_config_builder::rustls_server_config_builder_build(builder);
+            let builder = accepting_client_config_builder();
+            rustls_client_config_builder::rustls_client_config_builder_set_alpn_protocols(
+                builder,
+                alpn.as_ptr(),
+                alpn.len(),
+            );
+            let client_config =
+                rustls_client_config_builder::rustls_client_config_builder_build(builder);
+            let client = new_client_connection(client_config);
+            let server = new_server_connection(server_config);
+            assert!(matches!(handshake(client, server), rustls_result::Ok));
+
+            assert_eq!(get_alpn(server), Some(protocol.to_vec()));
+            assert_eq!(get_alpn(client), Some(protocol.to_vec()));
+
+            rustls_connection::rustls_connection_free(client);
+            rustls_connection::rustls_connection_free(server);
+            rustls_client_config::rustls_client_config_free(client_config);
+            rustls_server_config::rustls_server_config_free(server_config);
+        }
+    }
 }
@@ -1,9 +1,12 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::error::rustls_result;
 use crate::rslice::rustls_slice_bytes;
-use crate::userdata_get;
-use libc::{c_int, c_void, size_t};
+use crate::{new_ticket_callback_get, userdata_get};
+use libc::{c_char, c_int, c_void, size_t};
 
 /// Any context information the callback will receive when invoked.
 pub type rustls_session_store_userdata = *mut c_void;
@@ -166,3 +169,122 @@ impl rustls::client::StoresClientSessions for SessionStoreBroker {
 /// documented as a requirement in the API.
 unsafe impl Sync for SessionStoreBroker {}
 unsafe impl Send for SessionStoreBroker {}
+
+/// Prototype of a callback that can be installed on a client connection with
+/// `rustls_connection_set_new_ticket_callback`. It is invoked whenever the
+/// connection receives a new session from the server that can later be used
+/// for resumption (for TLS 1.3, once per NewSessionTicket message).
+/// `userdata` is the value passed to `rustls_connection_set_new_ticket_callback`.
+///
+/// NOTE: the passed in `ticket` is only available during the
+/// callback invocation.
+pub type rustls_new_ticket_callback =
+    Option<unsafe extern "C" fn(userdata: *mut c_void, ticket: *const rustls_slice_bytes)>;
+
+/// A session to add to a client's session cache before any connections are
+/// made. See rustls_client_config_builder_preload_session_cache.
+#[repr(C)]
+pub struct rustls_session_cache_entry<'a> {
+    /// The NUL-terminated DNS name of the server the session is for.
+    pub server_name: *const c_char,
+    /// An encoded session, as previously passed to a rustls_new_ticket_callback.
+    pub session: rustls_slice_bytes<'a>,
+}
+
+/// The key rustls uses to store the resumption session for `server_name` in
+/// a client session store, or None if `server_name` isn't a valid DNS name.
+/// This mirrors rustls' internal ClientSessionKey encoding.
+pub(crate) fn client_session_key(server_name: &str) -> Option<Vec<u8>> {
+    rustls::ServerName::try_from(server_name).ok()?;
+    let name = server_name.to_ascii_lowercase();
+    let mut key = b"session".to_vec();
+    key.push(0x01);
+    key.push(u8::try_from(name.len()).ok()?);
+    key.extend_from_slice(name.as_bytes());
+    Some(key)
+}
+
+/// Wraps a client session store so that newly stored sessions are also
+/// handed to the new ticket callback of the connection that received them.
+pub(crate) struct NewTicketNotifier {
+    pub inner: Arc<dyn rustls::client::StoresClientSessions>,
+}
+
+impl rustls::client::StoresClientSessions for NewTicketNotifier {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        // rustls also stores key exchange hints in the session store; only
+        // report actual sessions.
+        if key.starts_with(b"session") {
+            if let Ok((Some(cb), userdata)) = new_ticket_callback_get() {
+                let ticket: rustls_slice_bytes = value.as_slice().into();
+                unsafe { cb(userdata, &ticket) };
+            }
+        }
+        self.inner.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.inner.get(key)
+    }
+}
+
+/// A cached session: key, value and when it was stored.
+type CacheEntry = (Vec<u8>, Vec<u8>, Instant);
+
+/// An in-memory client session store that evicts either the least recently
+/// used or the oldest entry once it is full, and optionally drops entries
+/// older than `max_age`.
+pub(crate) struct ClientSessionCache {
+    lru: bool,
+    max_age: Option<Duration>,
+    capacity: usize,
+    // Ordered from next-to-evict to most recently stored (or used, for LRU).
+    entries: Mutex<VecDeque<CacheEntry>>,
+}
+
+impl ClientSessionCache {
+    pub fn new(capacity: usize, lru: bool, max_age: Option<Duration>) -> Self {
+        ClientSessionCache {
+            lru,
+            max_age,
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn expired(&self, stored: Instant) -> bool {
+        matches!(self.max_age, Some(max_age) if stored.elapsed() > max_age)
+    }
+}
+
+impl rustls::client::StoresClientSessions for ClientSessionCache {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        let mut entries = match self.entries.lock() {
+            Ok(e) => e,
+            Err(_) => return false,
+        };
+        entries.retain(|(k, _, stored)| *k != key && !self.expired(*stored));
+        while !entries.is_empty() && entries.len() >= self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((key, value, Instant::now()));
+        true
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut entries = self.entries.lock().ok()?;
+        let i = entries.iter().position(|(k, _, _)| k == key)?;
+        if self.expired(entries[i].2) {
+            entries.remove(i);
+            return None;
+        }
+        if self.lru {
+            let entry = entries.remove(i)?;
+            let value = entry.1.clone();
+            entries.push_back(entry);
+            Some(value)
+        } else {
+            Some(entries[i].1.clone())
+        }
+    }
+}
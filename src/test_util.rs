@@ -0,0 +1,192 @@
+//! Helpers shared by the tests of several modules, for building configs and
+//! running client and server connections against each other in memory.
+
+use std::ptr::{null, null_mut};
+
+use libc::c_char;
+
+use crate::cipher::rustls_certified_key;
+use crate::client::{
+    rustls_client_config, rustls_client_config_builder, rustls_verify_server_cert_params,
+    rustls_verify_server_cert_user_data,
+};
+use crate::connection::{rustls_connection, Connection};
+use crate::error::rustls_result;
+use crate::server::{rustls_server_config, rustls_server_config_builder};
+use crate::try_mut_from_ptr;
+
+pub(crate) fn localhost_certified_key() -> *const rustls_certified_key {
+    let cert_pem = include_str!("../localhost/cert.pem").as_bytes();
+    let key_pem = include_str!("../localhost/key.pem").as_bytes();
+    let mut certified_key: *const rustls_certified_key = null();
+    let result = rustls_certified_key::rustls_certified_key_build(
+        cert_pem.as_ptr(),
+        cert_pem.len(),
+        key_pem.as_ptr(),
+        key_pem.len(),
+        &mut certified_key,
+    );
+    if !matches!(result, rustls_result::Ok) {
+        panic!(
+            "expected RUSTLS_RESULT_OK from rustls_certified_key_build, got {:?}",
+            result
+        );
+    }
+    certified_key
+}
+
+pub(crate) unsafe extern "C" fn accept_any_server_cert(
+    _userdata: rustls_verify_server_cert_user_data,
+    _params: *const rustls_verify_server_cert_params,
+) -> u32 {
+    rustls_result::Ok as u32
+}
+
+// Build a client config that accepts any server certificate.
+pub(crate) fn accepting_client_config_builder() -> *mut rustls_client_config_builder {
+    let builder = rustls_client_config_builder::rustls_client_config_builder_new();
+    rustls_client_config_builder::rustls_client_config_builder_dangerous_set_certificate_verifier(
+        builder,
+        Some(accept_any_server_cert),
+    );
+    builder
+}
+
+// Build a server config presenting the localhost certificate.
+pub(crate) fn localhost_server_config() -> *const rustls_server_config {
+    let builder = rustls_server_config_builder::rustls_server_config_builder_new();
+    let certified_key = localhost_certified_key();
+    rustls_server_config_builder::rustls_server_config_builder_set_certified_keys(
+        builder,
+        &certified_key,
+        1,
+    );
+    rustls_certified_key::rustls_certified_key_free(certified_key);
+    rustls_server_config_builder::rustls_server_config_builder_build(builder)
+}
+
+pub(crate) fn new_client_connection(config: *const rustls_client_config) -> *mut rustls_connection {
+    let mut conn: *mut rustls_connection = null_mut();
+    let result = rustls_client_config::rustls_client_connection_new(
+        config,
+        "localhost\0".as_ptr() as *const c_char,
+        &mut conn,
+    );
+    if !matches!(result, rustls_result::Ok) {
+        panic!("expected RUSTLS_RESULT_OK, got {:?}", result);
+    }
+    conn
+}
+
+pub(crate) fn new_server_connection(config: *const rustls_server_config) -> *mut rustls_connection {
+    let mut conn: *mut rustls_connection = null_mut();
+    let result = rustls_server_config::rustls_server_connection_new(config, &mut conn);
+    if !matches!(result, rustls_result::Ok) {
+        panic!("expected RUSTLS_RESULT_OK, got {:?}", result);
+    }
+    conn
+}
+
+// Move all pending TLS bytes from one connection to the other, and let the
+// receiving side process them.
+pub(crate) fn transfer(from: *mut rustls_connection, to: *mut rustls_connection) -> rustls_result {
+    let mut buf: Vec<u8> = vec![];
+    {
+        let from: &mut Connection = try_mut_from_ptr!(from);
+        while from.wants_write() {
+            if from.write_tls(&mut buf).is_err() {
+                return rustls_result::Io;
+            }
+        }
+    }
+    let mut rest: &[u8] = &buf;
+    while !rest.is_empty() {
+        {
+            let to: &mut Connection = try_mut_from_ptr!(to);
+            if to.read_tls(&mut rest).is_err() {
+                return rustls_result::Io;
+            }
+        }
+        let result = rustls_connection::rustls_connection_process_new_packets(to);
+        if !matches!(result, rustls_result::Ok) {
+            return result;
+        }
+    }
+    rustls_result::Ok
+}
+
+// Run a handshake between a client and a server connection in memory.
+pub(crate) fn handshake(
+    client: *mut rustls_connection,
+    server: *mut rustls_connection,
+) -> rustls_result {
+    for _ in 0..10 {
+        for &(from, to) in &[(client, server), (server, client)] {
+            let result = transfer(from, to);
+            if !matches!(result, rustls_result::Ok) {
+                return result;
+            }
+        }
+        if !rustls_connection::rustls_connection_is_handshaking(client)
+            && !rustls_connection::rustls_connection_is_handshaking(server)
+        {
+            return rustls_result::Ok;
+        }
+    }
+    rustls_result::HandshakeNotComplete
+}
+
+/// A client connection and a server connection, each made from a config of
+/// its own. The client accepts any server certificate. Dropping the pair
+/// frees both connections and both configs.
+pub(crate) struct ConnectionPair {
+    pub(crate) client_config: *const rustls_client_config,
+    pub(crate) server_config: *const rustls_server_config,
+    pub(crate) client: *mut rustls_connection,
+    pub(crate) server: *mut rustls_connection,
+}
+
+impl ConnectionPair {
+    /// A pair whose server presents the localhost certificate. The handshake
+    /// hasn't started.
+    pub(crate) fn new() -> ConnectionPair {
+        ConnectionPair::with_server_config(localhost_server_config())
+    }
+
+    /// A pair whose server uses `server_config`, which the pair takes over.
+    /// The handshake hasn't started.
+    pub(crate) fn with_server_config(server_config: *const rustls_server_config) -> ConnectionPair {
+        let client_config = rustls_client_config_builder::rustls_client_config_builder_build(
+            accepting_client_config_builder(),
+        );
+        ConnectionPair {
+            client_config,
+            server_config,
+            client: new_client_connection(client_config),
+            server: new_server_connection(server_config),
+        }
+    }
+
+    /// A pair as from `new`, that has completed its handshake.
+    pub(crate) fn connected() -> ConnectionPair {
+        let pair = ConnectionPair::new();
+        let result = pair.handshake();
+        if !matches!(result, rustls_result::Ok) {
+            panic!("expected RUSTLS_RESULT_OK from handshake, got {:?}", result);
+        }
+        pair
+    }
+
+    pub(crate) fn handshake(&self) -> rustls_result {
+        handshake(self.client, self.server)
+    }
+}
+
+impl Drop for ConnectionPair {
+    fn drop(&mut self) {
+        rustls_connection::rustls_connection_free(self.client);
+        rustls_connection::rustls_connection_free(self.server);
+        rustls_client_config::rustls_client_config_free(self.client_config);
+        rustls_server_config::rustls_server_config_free(self.server_config);
+    }
+}
@@ -0,0 +1,585 @@
+//! Minimal DER parsing for the few X.509 certificate and CRL fields that
+//! rustls-ffi uses directly, so that C code doesn't need its own certificate
+//! parser for simple lookups. This only locates fields; it does not validate
+//! them. Validation is done by webpki during certificate verification. It
+//! also converts SEC1 EC private keys to PKCS#8, since rustls-pemfile doesn't
+//! read them and ring only accepts PKCS#8.
+
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0c;
+const PRINTABLE_STRING: u8 = 0x13;
+const TELETEX_STRING: u8 = 0x14;
+const IA5_STRING: u8 = 0x16;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const UNIVERSAL_STRING: u8 = 0x1c;
+const BMP_STRING: u8 = 0x1e;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const CONTEXT_0: u8 = 0xa0;
+
+/// The full DER encoding of the id-ecPublicKey OID, 1.2.840.10045.2.1.
+const EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// Read one DER element from the front of `input` and advance past it.
+/// Returns the element's tag, its contents, and its full encoding
+/// (tag, length and contents).
+fn read_tlv<'a>(input: &mut &'a [u8]) -> Option<(u8, &'a [u8], &'a [u8])> {
+    let data: &'a [u8] = input;
+    let tag = *data.first()?;
+    // High tag numbers don't occur in the parts of certificates we look at.
+    if tag & 0x1f == 0x1f {
+        return None;
+    }
+    let first = *data.get(1)? as usize;
+    let (len, header_len) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let mut len = 0usize;
+        for i in 0..n {
+            len = (len << 8) | *data.get(2 + i)? as usize;
+        }
+        (len, 2 + n)
+    };
+    let end = header_len.checked_add(len)?;
+    if end > data.len() {
+        return None;
+    }
+    *input = &data[end..];
+    Some((tag, &data[header_len..end], &data[..end]))
+}
+
+/// Read a DER element from `input`, requiring it to have the given tag, and
+/// return its contents.
+fn expect_tlv<'a>(input: &mut &'a [u8], tag: u8) -> Option<&'a [u8]> {
+    match read_tlv(input)? {
+        (t, contents, _) if t == tag => Some(contents),
+        _ => None,
+    }
+}
+
+/// Borrowed views of the fields of a DER-encoded X.509 certificate.
+pub(crate) struct Certificate<'a> {
+    /// The contents of the serialNumber INTEGER.
+    pub(crate) serial: &'a [u8],
+    /// The full DER encoding of the SubjectPublicKeyInfo.
+    pub(crate) spki: &'a [u8],
+    /// The contents of the issuer Name, a sequence of relative distinguished
+    /// names.
+    pub(crate) issuer: &'a [u8],
+    /// The contents of the Validity sequence: notBefore and notAfter.
+    pub(crate) validity: &'a [u8],
+    /// The contents of the subject Name.
+    pub(crate) subject: &'a [u8],
+    /// The contents of the OID identifying the algorithm the issuer used to
+    /// sign this certificate.
+    pub(crate) signature_algorithm: &'a [u8],
+}
+
+/// Signature algorithms with broken or deprecated hash functions: MD2, MD5
+/// and SHA-1, with RSA, DSA or ECDSA. The values are DER-encoded OID contents.
+const WEAK_SIGNATURE_ALGORITHMS: &[&[u8]] = &[
+    // md2WithRSAEncryption, 1.2.840.113549.1.1.2
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x02],
+    // md5WithRSAEncryption, 1.2.840.113549.1.1.4
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x04],
+    // sha1WithRSAEncryption, 1.2.840.113549.1.1.5
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05],
+    // dsa-with-sha1, 1.2.840.10040.4.3
+    &[0x2a, 0x86, 0x48, 0xce, 0x38, 0x04, 0x03],
+    // ecdsa-with-SHA1, 1.2.840.10045.4.1
+    &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x01],
+];
+
+impl<'a> Certificate<'a> {
+    pub(crate) fn parse(der: &'a [u8]) -> Option<Certificate<'a>> {
+        let mut input = der;
+        let mut cert = expect_tlv(&mut input, SEQUENCE)?;
+        let mut tbs = expect_tlv(&mut cert, SEQUENCE)?;
+        // version [0] EXPLICIT is optional.
+        if tbs.first() == Some(&CONTEXT_0) {
+            read_tlv(&mut tbs)?;
+        }
+        let serial = expect_tlv(&mut tbs, INTEGER)?;
+        let _signature = read_tlv(&mut tbs)?;
+        let issuer = expect_tlv(&mut tbs, SEQUENCE)?;
+        let validity = expect_tlv(&mut tbs, SEQUENCE)?;
+        let subject = expect_tlv(&mut tbs, SEQUENCE)?;
+        let (spki_tag, _, spki) = read_tlv(&mut tbs)?;
+        if spki_tag != SEQUENCE {
+            return None;
+        }
+        let mut signature_algorithm = expect_tlv(&mut cert, SEQUENCE)?;
+        let signature_algorithm = expect_tlv(&mut signature_algorithm, OBJECT_IDENTIFIER)?;
+        Some(Certificate {
+            serial,
+            spki,
+            issuer,
+            validity,
+            subject,
+            signature_algorithm,
+        })
+    }
+
+    /// The notBefore and notAfter times of this certificate, in seconds since
+    /// the Unix epoch.
+    pub(crate) fn validity_period(&self) -> Option<(i64, i64)> {
+        let mut validity = self.validity;
+        let not_before = read_time(&mut validity)?;
+        let not_after = read_time(&mut validity)?;
+        Some((not_before, not_after))
+    }
+
+    /// Whether this certificate is signed with one of the algorithms in
+    /// `WEAK_SIGNATURE_ALGORITHMS`.
+    pub(crate) fn has_weak_signature(&self) -> bool {
+        WEAK_SIGNATURE_ALGORITHMS.contains(&self.signature_algorithm)
+    }
+}
+
+/// Borrowed views of the fields of a DER-encoded X.509 certificate
+/// revocation list (CRL).
+pub(crate) struct Crl<'a> {
+    /// The contents of the issuer Name.
+    pub(crate) issuer: &'a [u8],
+    /// The contents of the serialNumber INTEGER of each revoked certificate.
+    pub(crate) revoked: Vec<&'a [u8]>,
+    /// The nextUpdate time, in seconds since the Unix epoch, if present.
+    pub(crate) next_update: Option<i64>,
+    /// The full DER encoding of the tbsCertList, which is what the issuer
+    /// signed.
+    pub(crate) tbs: &'a [u8],
+    /// The contents of the OID identifying the signature algorithm.
+    pub(crate) signature_algorithm: &'a [u8],
+    /// The signature bits, without the BIT STRING's unused-bits byte.
+    pub(crate) signature: &'a [u8],
+}
+
+impl<'a> Crl<'a> {
+    pub(crate) fn parse(der: &'a [u8]) -> Option<Crl<'a>> {
+        let mut input = der;
+        let mut crl = expect_tlv(&mut input, SEQUENCE)?;
+        if !input.is_empty() {
+            return None;
+        }
+        let (tbs_tag, mut tbs, tbs_der) = read_tlv(&mut crl)?;
+        if tbs_tag != SEQUENCE {
+            return None;
+        }
+        let mut signature_algorithm = expect_tlv(&mut crl, SEQUENCE)?;
+        let signature_algorithm = expect_tlv(&mut signature_algorithm, OBJECT_IDENTIFIER)?;
+        let signature = bit_string_bytes(expect_tlv(&mut crl, BIT_STRING)?)?;
+        // version is optional.
+        if tbs.first() == Some(&INTEGER) {
+            read_tlv(&mut tbs)?;
+        }
+        let _signature = expect_tlv(&mut tbs, SEQUENCE)?;
+        let issuer = expect_tlv(&mut tbs, SEQUENCE)?;
+        read_time(&mut tbs)?;
+        // nextUpdate is optional.
+        let mut next_update = None;
+        if matches!(tbs.first(), Some(&UTC_TIME) | Some(&GENERALIZED_TIME)) {
+            next_update = Some(read_time(&mut tbs)?);
+        }
+        let mut revoked = vec![];
+        // revokedCertificates is left out when there are none.
+        if tbs.first() == Some(&SEQUENCE) {
+            let mut entries = expect_tlv(&mut tbs, SEQUENCE)?;
+            while !entries.is_empty() {
+                let mut entry = expect_tlv(&mut entries, SEQUENCE)?;
+                revoked.push(expect_tlv(&mut entry, INTEGER)?);
+                read_time(&mut entry)?;
+            }
+        }
+        Some(Crl {
+            issuer,
+            revoked,
+            next_update,
+            tbs: tbs_der,
+            signature_algorithm,
+            signature,
+        })
+    }
+}
+
+/// The subjectPublicKey bits of a DER-encoded SubjectPublicKeyInfo, which is
+/// the form of public key that ring verifies signatures with.
+pub(crate) fn subject_public_key(mut spki: &[u8]) -> Option<&[u8]> {
+    let mut spki = expect_tlv(&mut spki, SEQUENCE)?;
+    expect_tlv(&mut spki, SEQUENCE)?;
+    bit_string_bytes(expect_tlv(&mut spki, BIT_STRING)?)
+}
+
+/// The contents of a BIT STRING whose length is a whole number of bytes,
+/// without the leading unused-bits byte.
+fn bit_string_bytes(contents: &[u8]) -> Option<&[u8]> {
+    match contents.split_first() {
+        Some((0, bits)) => Some(bits),
+        _ => None,
+    }
+}
+
+/// Re-encode a DER SEC1 ECPrivateKey (RFC 5915) as a PKCS#8 PrivateKeyInfo,
+/// which is the only form of EC key that ring can load. The curve is taken
+/// from the key's own parameters field, so keys that leave it out are not
+/// supported.
+pub(crate) fn sec1_to_pkcs8(sec1: &[u8]) -> Option<Vec<u8>> {
+    let mut input = sec1;
+    let mut key = expect_tlv(&mut input, SEQUENCE)?;
+    if !input.is_empty() || expect_tlv(&mut key, INTEGER)? != [1] {
+        return None;
+    }
+    expect_tlv(&mut key, OCTET_STRING)?;
+    let mut parameters = expect_tlv(&mut key, CONTEXT_0)?;
+    let (tag, _, curve) = read_tlv(&mut parameters)?;
+    if tag != OBJECT_IDENTIFIER {
+        return None;
+    }
+    let mut algorithm = EC_PUBLIC_KEY.to_vec();
+    algorithm.extend_from_slice(curve);
+    let mut info = write_tlv(INTEGER, &[0]);
+    info.extend(write_tlv(SEQUENCE, &algorithm));
+    info.extend(write_tlv(OCTET_STRING, sec1));
+    Some(write_tlv(SEQUENCE, &info))
+}
+
+/// Encode one DER element with the given tag and contents.
+fn write_tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let len = contents.len();
+    let mut out = vec![tag];
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(contents);
+    out
+}
+
+/// Read a UTCTime or GeneralizedTime in the forms RFC 5280 allows,
+/// "YYMMDDHHMMSSZ" and "YYYYMMDDHHMMSSZ", and return it in seconds since the
+/// Unix epoch.
+fn read_time(input: &mut &[u8]) -> Option<i64> {
+    let (tag, contents, _) = read_tlv(input)?;
+    let (year, rest) = match (tag, contents.len()) {
+        (UTC_TIME, 13) => {
+            let yy = digits(&contents[..2])?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &contents[2..])
+        }
+        (GENERALIZED_TIME, 15) => (digits(&contents[..4])?, &contents[4..]),
+        _ => return None,
+    };
+    if rest[10] != b'Z' {
+        return None;
+    }
+    let month = digits(&rest[0..2])?;
+    let day = digits(&rest[2..4])?;
+    let hour = digits(&rest[4..6])?;
+    let minute = digits(&rest[6..8])?;
+    let second = digits(&rest[8..10])?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Parse a run of ASCII decimal digits.
+fn digits(bytes: &[u8]) -> Option<i64> {
+    bytes.iter().try_fold(0i64, |n, &b| match b {
+        b'0'..=b'9' => Some(n * 10 + (b - b'0') as i64),
+        _ => None,
+    })
+}
+
+/// The number of days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar.
+/// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Short names for attribute types, from RFC 4514 section 3. The values are
+/// DER-encoded OID contents.
+const ATTRIBUTE_NAMES: &[(&[u8], &str)] = &[
+    (&[0x55, 0x04, 0x03], "CN"),
+    (&[0x55, 0x04, 0x07], "L"),
+    (&[0x55, 0x04, 0x08], "ST"),
+    (&[0x55, 0x04, 0x0a], "O"),
+    (&[0x55, 0x04, 0x0b], "OU"),
+    (&[0x55, 0x04, 0x06], "C"),
+    (&[0x55, 0x04, 0x09], "STREET"),
+    (
+        &[0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19],
+        "DC",
+    ),
+    (
+        &[0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01],
+        "UID",
+    ),
+];
+
+/// Read a DER-encoded X.509 Name, such as a distinguished name from a TLS
+/// CertificateRequest, and return its contents for format_name.
+pub(crate) fn read_name<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    expect_tlv(input, SEQUENCE)
+}
+
+/// Format the contents of an X.509 Name as a string, following RFC 4514:
+/// relative distinguished names are listed last to first, separated by ",",
+/// and the attributes of a multi-valued one are joined with "+". Attribute
+/// types without a short name are written as dotted OIDs. String values are
+/// escaped as the RFC describes; values of other types are written as "#"
+/// followed by the hex of their DER encoding.
+pub(crate) fn format_name(mut name: &[u8]) -> Option<String> {
+    let mut rdns = vec![];
+    while !name.is_empty() {
+        let mut rdn = expect_tlv(&mut name, SET)?;
+        let mut attributes = vec![];
+        while !rdn.is_empty() {
+            let mut attribute = expect_tlv(&mut rdn, SEQUENCE)?;
+            let oid = expect_tlv(&mut attribute, OBJECT_IDENTIFIER)?;
+            let (tag, contents, encoded) = read_tlv(&mut attribute)?;
+            let attr_type = match ATTRIBUTE_NAMES.iter().find(|(o, _)| *o == oid) {
+                Some((_, short)) => short.to_string(),
+                None => format_oid(oid)?,
+            };
+            let value = match decode_string(tag, contents) {
+                Some(v) => escape_value(&v),
+                None => {
+                    let hex: String = encoded.iter().map(|b| format!("{:02x}", b)).collect();
+                    format!("#{}", hex)
+                }
+            };
+            attributes.push(format!("{}={}", attr_type, value));
+        }
+        rdns.push(attributes.join("+"));
+    }
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+/// Format the contents of an OBJECT IDENTIFIER in dotted decimal form.
+fn format_oid(oid: &[u8]) -> Option<String> {
+    let mut arcs: Vec<u64> = vec![];
+    let mut arc: u64 = 0;
+    for (i, b) in oid.iter().enumerate() {
+        arc = arc.checked_mul(128)? | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        } else if i == oid.len() - 1 {
+            return None;
+        }
+    }
+    if arcs.is_empty() {
+        return None;
+    }
+    let arcs: Vec<String> = arcs.iter().map(|a| a.to_string()).collect();
+    Some(arcs.join("."))
+}
+
+/// Decode a DirectoryString value. TeletexString is treated as Latin-1, which
+/// is what it holds in practice.
+fn decode_string(tag: u8, contents: &[u8]) -> Option<String> {
+    match tag {
+        UTF8_STRING | PRINTABLE_STRING | IA5_STRING => String::from_utf8(contents.to_vec()).ok(),
+        TELETEX_STRING => Some(contents.iter().map(|&b| b as char).collect()),
+        BMP_STRING => {
+            let chunks = contents.chunks_exact(2);
+            if !chunks.remainder().is_empty() {
+                return None;
+            }
+            let units: Vec<u16> = chunks.map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16(&units).ok()
+        }
+        UNIVERSAL_STRING => {
+            let chunks = contents.chunks_exact(4);
+            if !chunks.remainder().is_empty() {
+                return None;
+            }
+            chunks
+                .map(|c| char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+                .collect()
+        }
+        _ => None,
+    }
+}
+
+/// Escape an attribute value as described in RFC 4514 section 2.4.
+fn escape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '#' if i == 0 => out.push_str("\\#"),
+            ' ' if i == 0 || i == last => out.push_str("\\ "),
+            '\0' => out.push_str("\\00"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn localhost_cert_der() -> Vec<u8> {
+        let mut pem = include_str!("../localhost/cert.pem").as_bytes();
+        rustls_pemfile::certs(&mut pem).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_parse_spki() {
+        let der = localhost_cert_der();
+        let cert = Certificate::parse(&der).unwrap();
+        // A 2048-bit RSA key, as output by
+        // `openssl x509 -pubkey -noout | openssl pkey -pubin -outform DER`.
+        assert_eq!(cert.spki.len(), 294);
+        // The RSAPublicKey inside it.
+        assert_eq!(subject_public_key(cert.spki).unwrap().len(), 270);
+        assert_eq!(
+            &cert.spki[..19],
+            &[
+                0x30, 0x82, 0x01, 0x22, 0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d,
+                0x01, 0x01, 0x01, 0x05, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_weak_signature() {
+        let der = localhost_cert_der();
+        let cert = Certificate::parse(&der).unwrap();
+        // sha256WithRSAEncryption, 1.2.840.113549.1.1.11
+        assert_eq!(
+            cert.signature_algorithm,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]
+        );
+        assert!(!cert.has_weak_signature());
+
+        // Rewrite the outer signatureAlgorithm to sha1WithRSAEncryption.
+        let mut der = der.clone();
+        let oid = cert.signature_algorithm;
+        let pos = der.windows(oid.len()).rposition(|w| w == oid).unwrap();
+        der[pos + oid.len() - 1] = 0x05;
+        let cert = Certificate::parse(&der).unwrap();
+        assert!(cert.has_weak_signature());
+    }
+
+    #[test]
+    fn test_format_issuer() {
+        let der = localhost_cert_der();
+        let cert = Certificate::parse(&der).unwrap();
+        assert_eq!(
+            format_name(cert.issuer).unwrap(),
+            "CN=minica root ca 3ed3c5"
+        );
+        assert_eq!(format_name(cert.subject).unwrap(), "CN=localhost");
+
+        // C=US, O="Acme, Inc." + 1.2.3.4=<INTEGER 5>, CN=" #x"
+        let name: &[u8] = &[
+            0x31, 0x0b, 0x30, 0x09, 0x06, 0x03, 0x55, 0x04, 0x06, 0x13, 0x02, b'U', b'S', //
+            0x31, 0x1d, 0x30, 0x11, 0x06, 0x03, 0x55, 0x04, 0x0a, 0x0c, 0x0a, b'A', b'c', b'm',
+            b'e', b',', b' ', b'I', b'n', b'c', b'.', //
+            0x30, 0x08, 0x06, 0x03, 0x2a, 0x03, 0x04, 0x02, 0x01, 0x05, //
+            0x31, 0x0c, 0x30, 0x0a, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x03, b' ', b'#', b'x',
+        ];
+        assert_eq!(
+            format_name(name).unwrap(),
+            "CN=\\ #x,O=Acme\\, Inc.+1.2.3.4=#020105,C=US"
+        );
+        assert!(format_name(&name[..name.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_validity_period() {
+        let der = localhost_cert_der();
+        let cert = Certificate::parse(&der).unwrap();
+        // From `openssl x509 -noout -dates`: notBefore=Mar 20 05:14:09 2021 GMT,
+        // notAfter=Apr 19 05:14:09 2023 GMT.
+        assert_eq!(cert.validity_period(), Some((1616217249, 1681881249)));
+
+        let mut input: &[u8] = b"\x17\x0d000229120000Z";
+        assert_eq!(read_time(&mut input), Some(951825600));
+        let mut input: &[u8] = b"\x17\x0d491231235959Z";
+        assert_eq!(read_time(&mut input), Some(2524607999));
+        let mut input: &[u8] = b"\x18\x0f19691231235959Z";
+        assert_eq!(read_time(&mut input), Some(-1));
+        let mut input: &[u8] = b"\x17\x0d001301000000Z";
+        assert_eq!(read_time(&mut input), None);
+        let mut input: &[u8] = b"\x17\x0d0001010000000";
+        assert_eq!(read_time(&mut input), None);
+    }
+
+    #[test]
+    fn test_parse_crl() {
+        let der = localhost_cert_der();
+        let cert = Certificate::parse(&der).unwrap();
+        // From `openssl x509 -noout -serial`.
+        assert_eq!(
+            cert.serial,
+            &[0x33, 0x0d, 0xa6, 0x11, 0x98, 0x8c, 0xc4, 0xe7]
+        );
+
+        let crl = Crl::parse(include_bytes!("../localhost/revoked.crl")).unwrap();
+        assert_eq!(format_name(crl.issuer).unwrap(), "CN=minica root ca 3ed3c5");
+        assert_eq!(crl.issuer, cert.issuer);
+        assert_eq!(crl.revoked, vec![cert.serial]);
+        // Sep 21 09:34:47 2126 GMT, from `openssl crl -noout -nextupdate`.
+        assert_eq!(crl.next_update, Some(4_945_656_887));
+        // sha256WithRSAEncryption, signed with the 2048-bit minica root key.
+        assert_eq!(
+            crl.signature_algorithm,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]
+        );
+        assert_eq!(crl.signature.len(), 256);
+
+        let crl = Crl::parse(include_bytes!("../localhost/empty.crl")).unwrap();
+        assert!(crl.revoked.is_empty());
+
+        assert!(Crl::parse(&der).is_none());
+        let crl = include_bytes!("../localhost/revoked.crl");
+        assert!(Crl::parse(&crl[..crl.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        assert!(Certificate::parse(&[]).is_none());
+        assert!(Certificate::parse(&[0x30, 0x82, 0xff]).is_none());
+        let der = localhost_cert_der();
+        assert!(Certificate::parse(&der[..der.len() - 1]).is_none());
+    }
+}